@@ -1,2 +1,10 @@
 /usr/bin/glslc shader.vert -o vert.spv
-/usr/bin/glslc shader.frag -o frag.spv
\ No newline at end of file
+/usr/bin/glslc shader.frag -o frag.spv
+/usr/bin/glslc instanced.vert -o instanced_vert.spv
+/usr/bin/glslc text.vert -o text_vert.spv
+/usr/bin/glslc text_sdf.frag -o text_sdf_frag.spv
+/usr/bin/glslc particles.comp -o particles_comp.spv
+/usr/bin/glslc particles.vert -o particles_vert.spv
+/usr/bin/glslc particles.frag -o particles_frag.spv
+/usr/bin/glslc skybox.vert -o skybox_vert.spv
+/usr/bin/glslc skybox.frag -o skybox_frag.spv
\ No newline at end of file
@@ -0,0 +1,18 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProjection;
+} pcs;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inVelocity;
+
+layout(location = 0) out vec3 fragColor;
+
+void main() {
+    gl_Position = pcs.viewProjection * vec4(inPosition, 1.0);
+    // Obligatoire en POINT_LIST, limité à pointSizeRange sans largePoints
+    gl_PointSize = 2.0;
+    // Des plus lentes (bleues) aux plus rapides (orange)
+    fragColor = mix(vec3(0.2, 0.4, 1.0), vec3(1.0, 0.6, 0.2), clamp(length(inVelocity), 0.0, 1.0));
+}
@@ -1,16 +1,116 @@
 #version 450
 
+struct Material {
+    vec4 color;
+};
+
+// 0: sortie linéaire, encodée si besoin par le format de la swapchain; 1: HDR10 (PQ);
+// 2: sRGB, encodé ici pour une swapchain UNORM
+layout(constant_id = 0) const uint OUTPUT_TRANSFER = 0;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    // Vers la lumière, w inutilisé
+    vec4 lightDirection;
+    vec4 lightColor;
+} ubo;
+
 layout(binding = 1) uniform sampler2D texSampler;
 
+layout(std430, binding = 2) readonly buffer MaterialBuffer {
+    Material materials[];
+};
+
+layout(binding = 3) uniform sampler2D secondTexSampler;
+
 layout(push_constant) uniform PushConstants {
-    layout(offset = 64) float opacity;
+    layout(offset = 68) float opacity;
+    uint materialIndex;
+    float textureBlend;
+    // 0xFFFFFFFF sans sélection
+    uint selectedModel;
+    // 0: rendu normal, 1: couleur des sommets, 2: UVs, 3: normales, 4: profondeur linéaire
+    uint debugView;
 } pcs;
 
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragNormal;
+layout(location = 3) flat in uint fragModelIndex;
 
 layout(location = 0) out vec4 outColor;
+// Lu au clic pour sélectionner le modèle sous le curseur
+layout(location = 1) out uint outModelIndex;
+
+const vec3 SELECTION_TINT = vec3(1.0, 0.6, 0.1);
+// Pour que les faces à l'ombre ne soient pas noires
+const float AMBIENT = 0.15;
+
+vec3 encodeOutput(vec3 color) {
+    if (OUTPUT_TRANSFER == 1) {
+        // BT.709 -> BT.2020, puis le blanc SDR à 203 nits sur les 10000 de PQ (ST 2084)
+        const mat3 BT709_TO_BT2020 = mat3(
+            0.6274, 0.0691, 0.0164,
+            0.3293, 0.9195, 0.0880,
+            0.0433, 0.0114, 0.8956
+        );
+        vec3 l = pow(max(BT709_TO_BT2020 * color, 0.0) * (203.0 / 10000.0), vec3(0.1593017578125));
+        return pow((0.8359375 + 18.8515625 * l) / (1.0 + 18.6875 * l), vec3(78.84375));
+    }
+
+    if (OUTPUT_TRANSFER == 2) {
+        // Swapchain UNORM: l'encodage sRGB que ferait un format _SRGB
+        vec3 c = clamp(color, 0.0, 1.0);
+        return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+    }
+
+    return color;
+}
+
+// La distance à la caméra, de 0.0 au near plane à 1.0 au far plane
+float linearDepth() {
+    // Inverse de la projection (perspective_rh_zo): z_ndc = A + B / distance
+    float a = ubo.proj[2][2];
+    float b = ubo.proj[3][2];
+    float near = b / a;
+    float far = b / (a + 1.0);
+    return (b / (gl_FragCoord.z + a) - near) / (far - near);
+}
+
+vec3 debugColor() {
+    if (pcs.debugView == 1) {
+        return fragColor;
+    }
+    if (pcs.debugView == 2) {
+        return vec3(fract(fragTexCoord), 0.0);
+    }
+    if (pcs.debugView == 3) {
+        // Les modèles sans normales restent noirs
+        return length(fragNormal) > 0.0 ? normalize(fragNormal) * 0.5 + 0.5 : vec3(0.0);
+    }
+    return vec3(linearDepth());
+}
 
 void main() {
-    outColor = vec4(texture(texSampler, fragTexCoord).rgb, pcs.opacity);
-}
\ No newline at end of file
+    outModelIndex = fragModelIndex;
+    if (pcs.debugView != 0) {
+        outColor = vec4(encodeOutput(debugColor()), 1.0);
+        return;
+    }
+
+    vec3 texColor = mix(
+        texture(texSampler, fragTexCoord).rgb,
+        texture(secondTexSampler, fragTexCoord).rgb,
+        pcs.textureBlend
+    );
+    // fragColor: la couleur du matériau du modèle (diffuse du MTL, base color du glTF)
+    vec3 color = texColor * fragColor * materials[pcs.materialIndex].color.rgb;
+    // Lambert, la normale est interpolée donc renormalisée
+    float diffuse = max(dot(normalize(fragNormal), normalize(ubo.lightDirection.xyz)), 0.0);
+    color *= AMBIENT + diffuse * ubo.lightColor.rgb;
+    if (fragModelIndex == pcs.selectedModel) {
+        color = mix(color, SELECTION_TINT, 0.4);
+    }
+    outColor = vec4(encodeOutput(color), pcs.opacity);
+}
@@ -3,23 +3,33 @@
 layout(binding = 0) uniform UniformBufferObject {
     mat4 view;
     mat4 proj;
+    vec4 lightDirection;
+    vec4 lightColor;
 } ubo;
 
 
 layout(push_constant) uniform PushConstants {
     mat4 model;
+    uint modelIndex;
 } pcs;
 
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec2 inTexCoord;
+// Location 7: les locations 3 à 6 sont prises par la matrice par instance
+layout(location = 7) in vec3 inNormal;
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec3 fragNormal;
+layout(location = 3) flat out uint fragModelIndex;
 
 
 void main() {
     gl_Position = ubo.proj * ubo.view * pcs.model * vec4(inPosition, 1.0);
     fragColor = inColor;
     fragTexCoord = inTexCoord;
+    // Inverse transposée: les normales restent perpendiculaires avec une échelle non uniforme
+    fragNormal = transpose(inverse(mat3(pcs.model))) * inNormal;
+    fragModelIndex = pcs.modelIndex;
 }
\ No newline at end of file
@@ -0,0 +1,39 @@
+#version 450
+
+// 0: sortie linéaire, encodée si besoin par le format de la swapchain; 1: HDR10 (PQ);
+// 2: sRGB, encodé ici pour une swapchain UNORM
+layout(constant_id = 0) const uint OUTPUT_TRANSFER = 0;
+
+layout(binding = 4) uniform samplerCube skybox;
+
+layout(location = 0) in vec3 fragDirection;
+
+// L'index des modèles (location 1) n'est pas écrit, le masque de la pipeline l'ignore
+layout(location = 0) out vec4 outColor;
+
+vec3 encodeOutput(vec3 color) {
+    if (OUTPUT_TRANSFER == 1) {
+        // BT.709 -> BT.2020, puis le blanc SDR à 203 nits sur les 10000 de PQ (ST 2084)
+        const mat3 BT709_TO_BT2020 = mat3(
+            0.6274, 0.0691, 0.0164,
+            0.3293, 0.9195, 0.0880,
+            0.0433, 0.0114, 0.8956
+        );
+        vec3 l = pow(max(BT709_TO_BT2020 * color, 0.0) * (203.0 / 10000.0), vec3(0.1593017578125));
+        return pow((0.8359375 + 18.8515625 * l) / (1.0 + 18.6875 * l), vec3(78.84375));
+    }
+
+    if (OUTPUT_TRANSFER == 2) {
+        // Swapchain UNORM: l'encodage sRGB que ferait un format _SRGB
+        vec3 c = clamp(color, 0.0, 1.0);
+        return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+    }
+
+    return color;
+}
+
+void main() {
+    // Le monde a Z vers le haut, les cube maps Y
+    vec3 direction = vec3(fragDirection.x, fragDirection.z, -fragDirection.y);
+    outColor = vec4(encodeOutput(texture(skybox, direction).rgb), 1.0);
+}
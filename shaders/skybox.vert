@@ -0,0 +1,33 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(location = 0) out vec3 fragDirection;
+
+// Les 8 coins du cube, indexés par les 12 triangles de ses faces
+const vec3 CORNERS[8] = vec3[](
+    vec3(-1.0, -1.0, -1.0), vec3(1.0, -1.0, -1.0), vec3(1.0, 1.0, -1.0), vec3(-1.0, 1.0, -1.0),
+    vec3(-1.0, -1.0, 1.0), vec3(1.0, -1.0, 1.0), vec3(1.0, 1.0, 1.0), vec3(-1.0, 1.0, 1.0)
+);
+
+const int INDICES[36] = int[](
+    0, 1, 2, 2, 3, 0,
+    4, 6, 5, 6, 4, 7,
+    0, 4, 5, 5, 1, 0,
+    1, 5, 6, 6, 2, 1,
+    2, 6, 7, 7, 3, 2,
+    3, 7, 4, 4, 0, 3
+);
+
+void main() {
+    vec3 position = CORNERS[INDICES[gl_VertexIndex]];
+    fragDirection = position;
+
+    // Sans la translation de la vue, le cube reste centré sur la caméra
+    vec4 clipPosition = ubo.proj * mat4(mat3(ubo.view)) * vec4(position, 1.0);
+    // z = w: au plan lointain après la division
+    gl_Position = clipPosition.xyww;
+}
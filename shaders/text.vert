@@ -0,0 +1,18 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    vec2 screenSize;
+    float smoothing;
+    vec4 color;
+} pcs;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec2 inTexCoord;
+
+layout(location = 0) out vec2 fragTexCoord;
+
+void main() {
+    // Pixels -> coordonnées normalisées, l'axe y de Vulkan pointe déjà vers le bas
+    gl_Position = vec4(inPosition / pcs.screenSize * 2.0 - 1.0, 0.0, 1.0);
+    fragTexCoord = inTexCoord;
+}
@@ -0,0 +1,262 @@
+//! Sub-allocation of the memory of the buffers from larger blocks, one memory type per
+//! block, so loading many models doesn't hit `maxMemoryAllocationCount` (often 4096).
+//!
+//! A block can't be mapped twice at once: the allocations are mapped (at their offset) only
+//! for the time of a write or a read, never kept mapped.
+//!
+//! The images get their own memory, allocated here as well so that the allocator keeps the
+//! tally of the memory used by the buffers and images (`log_memory_usage`).
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::InstanceV1_1;
+use vulkanalia::Version;
+
+use crate::{get_instance_api_version, AppData};
+
+/// Size of the blocks. Bigger allocations get their own memory.
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// A range of device memory, in a block or dedicated.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    /// Whether `memory` has been allocated for this allocation only.
+    dedicated: bool,
+    memory_type_index: u32,
+}
+
+#[derive(Clone, Debug)]
+struct Block {
+    memory: vk::DeviceMemory,
+    memory_type_index: u32,
+    /// Free ranges (offset and size), sorted by offset and never adjacent.
+    free: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl Block {
+    /// Takes the first free range fitting `requirements`, returns its offset.
+    fn take(&mut self, requirements: vk::MemoryRequirements) -> Option<vk::DeviceSize> {
+        let (index, offset) = self.free.iter().enumerate().find_map(|(i, (offset, size))| {
+            let aligned = offset.next_multiple_of(requirements.alignment.max(1));
+            (aligned + requirements.size <= offset + size).then_some((i, aligned))
+        })?;
+
+        // Ce qui reste avant (alignement) et après l'allocation reste libre
+        let (free_offset, free_size) = self.free.remove(index);
+        let end = offset + requirements.size;
+        let free_end = free_offset + free_size;
+
+        if end < free_end {
+            self.free.insert(index, (end, free_end - end));
+        }
+        if free_offset < offset {
+            self.free.insert(index, (free_offset, offset - free_offset));
+        }
+
+        Some(offset)
+    }
+
+    /// Gives back a range, merged with the free ranges next to it.
+    fn release(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let index = self.free.partition_point(|(o, _)| *o < offset);
+        self.free.insert(index, (offset, size));
+
+        if index + 1 < self.free.len() && offset + size == self.free[index + 1].0 {
+            let (_, next_size) = self.free.remove(index + 1);
+            self.free[index].1 += next_size;
+        }
+
+        if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == offset {
+            let (_, size) = self.free.remove(index);
+            self.free[index - 1].1 += size;
+        }
+    }
+}
+
+/// The blocks the buffers are allocated from, kept until `destroy` even once empty.
+#[derive(Clone, Debug, Default)]
+pub struct Allocator {
+    blocks: Vec<Block>,
+    /// Memory type and size of the memory of each image.
+    images: HashMap<vk::DeviceMemory, (u32, vk::DeviceSize)>,
+    /// Bytes used by the buffers and images (their `vk::MemoryRequirements`) per memory type,
+    /// not counting the unused parts of the blocks.
+    usage: HashMap<u32, vk::DeviceSize>,
+}
+
+impl Allocator {
+    /// Allocates memory of `memory_type_index` for `requirements`, from a block if it fits.
+    pub unsafe fn allocate(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+    ) -> Result<Allocation> {
+        *self.usage.entry(memory_type_index).or_default() += requirements.size;
+
+        if requirements.size > BLOCK_SIZE {
+            let info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index);
+
+            return Ok(Allocation {
+                memory: device.allocate_memory(&info, None)?,
+                offset: 0,
+                size: requirements.size,
+                dedicated: true,
+                memory_type_index,
+            });
+        }
+
+        let found = self.blocks
+            .iter_mut()
+            .filter(|b| b.memory_type_index == memory_type_index)
+            .find_map(|b| b.take(requirements).map(|offset| (b.memory, offset)));
+
+        let (memory, offset) = match found {
+            Some(found) => found,
+            None => {
+                let info = vk::MemoryAllocateInfo::builder()
+                    .allocation_size(BLOCK_SIZE)
+                    .memory_type_index(memory_type_index);
+
+                let mut block = Block {
+                    memory: device.allocate_memory(&info, None)?,
+                    memory_type_index,
+                    free: vec![(0, BLOCK_SIZE)],
+                };
+
+                let offset = block.take(requirements).expect("an empty block fits the allocation");
+                let memory = block.memory;
+                self.blocks.push(block);
+
+                (memory, offset)
+            }
+        };
+
+        Ok(Allocation {
+            memory,
+            offset,
+            size: requirements.size,
+            dedicated: false,
+            memory_type_index,
+        })
+    }
+
+    /// Frees an allocation. Its buffer must have been destroyed.
+    pub unsafe fn free(&mut self, device: &Device, allocation: Allocation) {
+        if let Some(usage) = self.usage.get_mut(&allocation.memory_type_index) {
+            *usage -= allocation.size.min(*usage);
+        }
+
+        if allocation.dedicated {
+            device.free_memory(allocation.memory, None);
+        } else if let Some(block) = self.blocks.iter_mut().find(|b| b.memory == allocation.memory) {
+            block.release(allocation.offset, allocation.size);
+        }
+    }
+
+    /// Allocates the memory of an image, for it only.
+    pub unsafe fn allocate_image(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+    ) -> Result<vk::DeviceMemory> {
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+
+        let memory = device.allocate_memory(&info, None)?;
+        self.images.insert(memory, (memory_type_index, requirements.size));
+        *self.usage.entry(memory_type_index).or_default() += requirements.size;
+
+        Ok(memory)
+    }
+
+    /// Frees the memory of an image (may be null). The image must have been destroyed.
+    pub unsafe fn free_image(&mut self, device: &Device, memory: vk::DeviceMemory) {
+        if let Some((memory_type_index, size)) = self.images.remove(&memory) {
+            if let Some(usage) = self.usage.get_mut(&memory_type_index) {
+                *usage -= size.min(*usage);
+            }
+        }
+
+        device.free_memory(memory, None);
+    }
+
+    /// Frees every block, the allocations from them can't be used anymore.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.blocks
+            .drain(..)
+            .for_each(|b| device.free_memory(b.memory, None));
+        self.usage.clear();
+    }
+}
+
+/// Logs the memory used by the buffers and images, device local and host visible, and the
+/// budget of each heap if `VK_EXT_memory_budget` is supported.
+pub unsafe fn log_memory_usage(instance: &Instance, data: &AppData) -> Result<()> {
+    let memory = instance.get_physical_device_memory_properties(data.physical_device);
+
+    // La mémoire visible de l'hôte et locale (ReBAR) compte comme locale
+    let (mut device_local, mut host_visible) = (0, 0);
+    for (memory_type_index, usage) in &data.allocator.usage {
+        let flags = memory.memory_types[*memory_type_index as usize].property_flags;
+        if flags.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL) {
+            device_local += usage;
+        } else if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            host_visible += usage;
+        }
+    }
+
+    info!(
+        "Memory used by the buffers and images: {:.1} MiB device local, {:.1} MiB host visible.",
+        to_mib(device_local),
+        to_mib(host_visible),
+    );
+
+    let extensions = instance
+        .enumerate_device_extension_properties(data.physical_device, None)?
+        .iter()
+        .map(|e| e.extension_name)
+        .collect::<HashSet<_>>();
+
+    // get_physical_device_memory_properties2 demande Vulkan 1.1 (instance et device)
+    let version = Version::new(1, 1, 0);
+    if !extensions.contains(&vk::EXT_MEMORY_BUDGET_EXTENSION.name)
+        || Version::from(get_instance_api_version(data)) < version
+        || Version::from(data.physical_device_properties.api_version) < version
+    {
+        debug!("Memory budget not available.");
+        return Ok(());
+    }
+
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+    instance.get_physical_device_memory_properties2(data.physical_device, &mut properties);
+
+    // L'usage du budget compte tout le processus, blocs entiers compris
+    for heap_index in 0..memory.memory_heap_count as usize {
+        let local = memory.memory_heaps[heap_index].flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL);
+        info!(
+            "Memory heap {} ({}): {:.1} MiB used of a {:.1} MiB budget.",
+            heap_index,
+            if local { "device local" } else { "host" },
+            to_mib(budget.heap_usage[heap_index]),
+            to_mib(budget.heap_budget[heap_index]),
+        );
+    }
+
+    Ok(())
+}
+
+fn to_mib(bytes: vk::DeviceSize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
@@ -0,0 +1,146 @@
+//! Benchmark of the ways of drawing many copies of the model: one secondary command
+//! buffer per model (recorded on one or several threads) versus a single instanced draw.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Frames rendered before measuring, so the first (slower) frames don't skew the results.
+const WARMUP_FRAMES: u32 = 60;
+
+/// How the models are recorded into the command buffers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    /// One secondary command buffer (and one draw) per model.
+    SecondaryCommandBuffers,
+    /// One secondary command buffer, one instanced draw for every model.
+    Instanced,
+    /// One secondary command buffer per model, recorded on several threads.
+    MultiThreaded,
+}
+
+impl RenderPath {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::SecondaryCommandBuffers => "secondary command buffers",
+            Self::Instanced => "instanced",
+            Self::MultiThreaded => "multi-threaded secondary",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Measurement {
+    path: RenderPath,
+    frames: u32,
+    recording: Duration,
+    elapsed: Duration,
+}
+
+/// Renders the same number of models with every [`RenderPath`] in turn.
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    /// Number of models drawn by each path.
+    pub models: usize,
+    /// Frames measured per path (after warmup).
+    frames: u32,
+    /// Recording threads of the multi-threaded path.
+    threads: usize,
+    paths: Vec<RenderPath>,
+    frame: u32,
+    start: Instant,
+    recording: Duration,
+    results: Vec<Measurement>,
+}
+
+impl Benchmark {
+    pub fn new(models: usize, frames: u32, threads: usize) -> Self {
+        let mut paths = vec![RenderPath::SecondaryCommandBuffers, RenderPath::Instanced];
+        if threads > 1 {
+            paths.push(RenderPath::MultiThreaded);
+        }
+
+        Self {
+            models,
+            frames: frames.max(1),
+            threads,
+            paths,
+            frame: 0,
+            start: Instant::now(),
+            recording: Duration::ZERO,
+            results: vec![],
+        }
+    }
+
+    /// The path to use for the next frame.
+    pub fn path(&self) -> RenderPath {
+        self.paths[self.results.len().min(self.paths.len() - 1)]
+    }
+
+    /// Accounts for a rendered frame which took `recording` to record on the CPU.
+    ///
+    /// Returns `true` once every path has been measured.
+    pub fn frame(&mut self, recording: Duration) -> bool {
+        if self.results.len() == self.paths.len() {
+            return true;
+        }
+
+        self.frame += 1;
+
+        if self.frame == WARMUP_FRAMES {
+            self.start = Instant::now();
+            self.recording = Duration::ZERO;
+        } else if self.frame > WARMUP_FRAMES {
+            self.recording += recording;
+        }
+
+        if self.frame == WARMUP_FRAMES + self.frames {
+            self.results.push(Measurement {
+                path: self.path(),
+                frames: self.frames,
+                recording: self.recording,
+                elapsed: self.start.elapsed(),
+            });
+            self.frame = 0;
+        }
+
+        self.results.len() == self.paths.len()
+    }
+
+    /// The results as a table.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        let _ = writeln!(
+            report,
+            "Benchmark: {} models, {} frames per path, {} recording threads",
+            self.models, self.frames, self.threads,
+        );
+        let _ = writeln!(report, "{:<28}{:>16}{:>12}{:>14}", "path", "recording (ms)", "FPS", "frame (ms)");
+
+        for m in &self.results {
+            let frames = m.frames as f64;
+            let _ = writeln!(
+                report,
+                "{:<28}{:>16.3}{:>12.1}{:>14.3}",
+                m.path.name(),
+                m.recording.as_secs_f64() * 1000.0 / frames,
+                frames / m.elapsed.as_secs_f64(),
+                m.elapsed.as_secs_f64() * 1000.0 / frames,
+            );
+        }
+
+        let recording = |path| self.results.iter().find(|m| m.path == path).map(|m| m.recording);
+        if let (Some(single), Some(multi)) = (
+            recording(RenderPath::SecondaryCommandBuffers),
+            recording(RenderPath::MultiThreaded),
+        ) {
+            let _ = writeln!(
+                report,
+                "Multi-threaded recording speedup: {:.2}x",
+                single.as_secs_f64() / multi.as_secs_f64(),
+            );
+        }
+
+        report
+    }
+}
@@ -0,0 +1,129 @@
+//! Uploads to device local memory through a single staging buffer, kept between the uploads
+//! and grown to the largest one, instead of a staging buffer created and destroyed for each.
+//!
+//! The copies from the staging buffer are waited for (`end_single_time_commands` waits for
+//! the queue to be idle) before the upload returns, so the next one can't overwrite a copy
+//! still in flight. `upload_buffers` stages several uploads at once to wait only once.
+
+use std::mem::size_of_val;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::slice;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator::Allocation;
+use crate::transfer::upload_buffer_regions;
+use crate::{copy_buffer, create_buffer, AppData};
+
+/// The host visible buffer the uploads are written to.
+#[derive(Clone, Debug, Default)]
+pub struct StagingBuffer {
+    buffer: vk::Buffer,
+    memory: Allocation,
+    size: vk::DeviceSize,
+}
+
+/// The bytes of `values`, to be uploaded.
+pub fn as_bytes<T: Copy>(values: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(values.as_ptr().cast(), size_of_val(values)) }
+}
+
+/// Writes `bytes` at the start of the staging buffer, grown first if they don't fit, and
+/// returns the buffer to copy them from.
+pub unsafe fn stage(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    bytes: &[u8],
+) -> Result<vk::Buffer> {
+    let size = bytes.len() as vk::DeviceSize;
+
+    if size > data.staging.size {
+        destroy_staging_buffer(device, data);
+
+        // Puissance de 2 pour ne pas le recréer à chaque upload un peu plus gros
+        let size = size.next_power_of_two();
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.staging = StagingBuffer { buffer, memory, size };
+    }
+
+    let memory = device.map_memory(
+        data.staging.memory.memory,
+        data.staging.memory.offset,
+        size,
+        vk::MemoryMapFlags::empty(),
+    )?;
+
+    memcpy(bytes.as_ptr(), memory.cast(), bytes.len());
+    device.unmap_memory(data.staging.memory.memory);
+
+    Ok(data.staging.buffer)
+}
+
+/// Copies `bytes` to the start of `dst_buffer`, which must have `TRANSFER_DST` usage, for
+/// `dst_access_mask` in `dst_stage_mask` (its first use).
+pub unsafe fn upload_via_staging(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    bytes: &[u8],
+    dst_buffer: vk::Buffer,
+    dst_stage_mask: vk::PipelineStageFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> Result<()> {
+    let staging_buffer = stage(instance, device, data, bytes)?;
+    let size = bytes.len() as vk::DeviceSize;
+    copy_buffer(device, data, staging_buffer, dst_buffer, size, dst_stage_mask, dst_access_mask)
+}
+
+/// Copies the bytes of each of `uploads` to the start of its buffer, with a single submission:
+/// the bytes are staged one after the other. The buffers are first used with
+/// `dst_access_mask` in `dst_stage_mask`.
+pub unsafe fn upload_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    uploads: &[(&[u8], vk::Buffer)],
+    dst_stage_mask: vk::PipelineStageFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> Result<()> {
+    let mut bytes = Vec::with_capacity(uploads.iter().map(|(b, _)| b.len()).sum());
+    let mut regions = Vec::with_capacity(uploads.len());
+
+    for (upload, dst_buffer) in uploads {
+        let region = vk::BufferCopy::builder()
+            .src_offset(bytes.len() as vk::DeviceSize)
+            .dst_offset(0)
+            .size(upload.len() as vk::DeviceSize);
+
+        bytes.extend_from_slice(upload);
+        regions.push((*dst_buffer, region.build()));
+    }
+
+    let staging_buffer = stage(instance, device, data, &bytes)?;
+    let copies = regions
+        .into_iter()
+        .map(|(dst_buffer, region)| (staging_buffer, dst_buffer, region))
+        .collect::<Vec<_>>();
+
+    upload_buffer_regions(device, data, &copies, dst_stage_mask, dst_access_mask)
+}
+
+pub unsafe fn destroy_staging_buffer(device: &Device, data: &mut AppData) {
+    if data.staging.buffer.is_null() {
+        return;
+    }
+
+    device.destroy_buffer(data.staging.buffer, None);
+    data.allocator.free(device, data.staging.memory);
+    data.staging = StagingBuffer::default();
+}
@@ -0,0 +1,97 @@
+//! Fly-through camera, moved with WASD (Left Shift and Left Control for up and down, Space
+//! pausing the animation) and turned with the mouse, and its projection. The scene is Z-up.
+
+use nalgebra_glm as glm;
+use winit::event::VirtualKeyCode;
+
+/// Movement speed, in units per second.
+const SPEED: f32 = 2.0;
+/// Rotation per pixel of mouse movement, in radians.
+const SENSITIVITY: f32 = 0.003;
+/// Maximum pitch, just under 90° so the view never flips over the vertical.
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub position: glm::Vec3,
+    /// Rotation around the vertical axis, in radians (0 looks along +X).
+    pub yaw: f32,
+    /// Rotation above the horizon, in radians.
+    pub pitch: f32,
+}
+
+impl Camera {
+    /// A camera at `position` looking at `target`.
+    pub fn looking_at(position: glm::Vec3, target: glm::Vec3) -> Self {
+        let direction = glm::normalize(&(target - position));
+
+        Self {
+            position,
+            yaw: direction.y.atan2(direction.x),
+            pitch: direction.z.asin().clamp(-MAX_PITCH, MAX_PITCH),
+        }
+    }
+
+    /// The unit vector the camera looks along.
+    pub fn front(&self) -> glm::Vec3 {
+        glm::vec3(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        )
+    }
+
+    pub fn view_matrix(&self) -> glm::Mat4 {
+        glm::look_at(
+            &self.position,
+            &(self.position + self.front()),
+            &glm::vec3(0.0, 0.0, 1.0),
+        )
+    }
+
+    /// Moves the camera for `key` being held during `dt` seconds, so the speed doesn't
+    /// depend on the framerate.
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, dt: f32) {
+        let up = glm::vec3(0.0, 0.0, 1.0);
+        let front = self.front();
+        let right = glm::normalize(&glm::cross(&front, &up));
+
+        let direction = match key {
+            VirtualKeyCode::W => front,
+            VirtualKeyCode::S => -front,
+            VirtualKeyCode::A => -right,
+            VirtualKeyCode::D => right,
+            VirtualKeyCode::LShift => up,
+            VirtualKeyCode::LControl => -up,
+            _ => return,
+        };
+
+        self.position += direction * SPEED * dt;
+    }
+
+    /// Turns the camera for a mouse movement of (`dx`, `dy`) pixels.
+    pub fn process_mouse(&mut self, dx: f64, dy: f64) {
+        // Souris vers la droite: on tourne vers la droite, donc le yaw diminue (Z vers le haut)
+        self.yaw -= dx as f32 * SENSITIVITY;
+        self.pitch = (self.pitch - dy as f32 * SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+}
+
+/// Field of view and clip planes of the perspective projection.
+#[derive(Copy, Clone, Debug)]
+pub struct ProjectionSettings {
+    /// Vertical field of view, in degrees.
+    pub fov_degrees: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for ProjectionSettings {
+    fn default() -> Self {
+        Self {
+            fov_degrees: 45.0,
+            near: 0.1,
+            far: 10.0,
+        }
+    }
+}
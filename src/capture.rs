@@ -0,0 +1,286 @@
+//! Capture of the rendered frames to PNG files.
+//!
+//! The swapchain image is copied to a host visible buffer at the end of the frame, and read
+//! back only once the frame is known to be finished (when its image is acquired again), so
+//! capturing doesn't stall rendering. The PNG files are encoded and written on other threads.
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator::Allocation;
+use crate::{create_buffer, get_attachment_barrier, AppData};
+
+/// The buffers the swapchain images are copied to, and the captures in progress.
+#[derive(Debug, Default)]
+pub struct CaptureData {
+    /// Readback buffers per swapchain image, created on the first capture.
+    buffers: Vec<vk::Buffer>,
+    buffers_memory: Vec<Allocation>,
+    /// File to write the capture of each swapchain image to, once its frame is finished.
+    pending: Vec<Option<PathBuf>>,
+    /// Threads writing the PNG files.
+    writers: Vec<JoinHandle<()>>,
+}
+
+impl Clone for CaptureData {
+    fn clone(&self) -> Self {
+        // Les threads d'écriture appartiennent à l'original
+        Self {
+            buffers: self.buffers.clone(),
+            buffers_memory: self.buffers_memory.clone(),
+            pending: self.pending.clone(),
+            writers: vec![],
+        }
+    }
+}
+
+/// A sequence of frames captured to `frame_0001.png`, `frame_0002.png`... in a directory.
+#[derive(Clone, Debug)]
+pub struct FrameSequence {
+    directory: PathBuf,
+    frame: u32,
+    frame_count: u32,
+}
+
+impl FrameSequence {
+    /// Creates `directory` if needed for a sequence of `frame_count` frames.
+    pub fn new(directory: &Path, frame_count: u32) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            frame: 0,
+            frame_count,
+        })
+    }
+
+    /// The file of the next frame, `None` once every frame has been captured.
+    pub fn next_path(&mut self) -> Option<PathBuf> {
+        if self.frame == self.frame_count {
+            return None;
+        }
+
+        self.frame += 1;
+
+        // Au moins 4 chiffres, plus si la séquence est plus longue
+        let width = self.frame_count.to_string().len().max(4);
+        Some(self.directory.join(format!("frame_{:0width$}.png", self.frame, width = width)))
+    }
+}
+
+/// A file name for a screenshot taken now, `screenshot-<milliseconds since the epoch>.png`,
+/// so that successive screenshots don't overwrite each other.
+pub fn get_screenshot_path() -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+
+    PathBuf::from(format!("screenshot-{}.png", millis))
+}
+
+/// Whether the swapchain images can be captured.
+pub fn check_capture(data: &AppData) -> Result<()> {
+    if !data.swapchain_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+        return Err(anyhow!("The swapchain images can't be copied."));
+    }
+
+    if !matches!(
+        data.swapchain_format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::R8G8B8A8_UNORM
+    ) {
+        return Err(anyhow!("Unsupported swapchain format for captures ({:?}).", data.swapchain_format));
+    }
+
+    Ok(())
+}
+
+/// Records the copy of the swapchain image `image_index` (in the `PRESENT_SRC_KHR`
+/// layout) to its readback buffer, to be written to `path` by `save_capture`.
+pub unsafe fn cmd_capture_swapchain_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    path: PathBuf,
+) -> Result<()> {
+    check_capture(data)?;
+
+    if data.capture.buffers.is_empty() {
+        create_capture_buffers(instance, device, data)?;
+    }
+
+    let image = data.swapchain_images[image_index];
+    let extent = data.swapchain_extent;
+
+    let barrier = get_attachment_barrier(
+        image,
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        vk::AccessFlags::TRANSFER_READ,
+    );
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        data.capture.buffers[image_index],
+        &[region],
+    );
+
+    let barrier = get_attachment_barrier(
+        image,
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+        vk::AccessFlags::TRANSFER_READ,
+        vk::AccessFlags::empty(),
+    );
+
+    // La copie doit être visible par le CPU quand la fence est signalée
+    let memory_barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[memory_barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    data.capture.pending[image_index] = Some(path);
+
+    Ok(())
+}
+
+/// Writes the capture of the swapchain image `image_index`, if any. Its last frame must be
+/// finished.
+pub unsafe fn save_capture(device: &Device, data: &mut AppData, image_index: usize) -> Result<()> {
+    let Some(path) = data.capture.pending.get_mut(image_index).and_then(Option::take) else {
+        return Ok(());
+    };
+
+    let extent = data.swapchain_extent;
+    let size = (extent.width * extent.height * 4) as usize;
+    let memory = data.capture.buffers_memory[image_index];
+
+    let mapped = device.map_memory(memory.memory, memory.offset, size as u64, vk::MemoryMapFlags::empty())?;
+    let mut pixels = std::slice::from_raw_parts(mapped.cast::<u8>(), size).to_vec();
+    device.unmap_memory(memory.memory);
+
+    if matches!(data.swapchain_format, vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM) {
+        pixels.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+    }
+
+    data.capture.writers.retain(|w| !w.is_finished());
+    data.capture.writers.push(thread::spawn(move || {
+        match write_png(&path, &pixels, extent.width, extent.height) {
+            Ok(()) => info!("Captured `{}`.", path.display()),
+            Err(e) => warn!("Could not write `{}`: {}", path.display(), e),
+        }
+    }));
+
+    Ok(())
+}
+
+fn write_png(path: &Path, pixels: &[u8], width: u32, height: u32) -> Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+
+    Ok(())
+}
+
+unsafe fn create_capture_buffers(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let extent = data.swapchain_extent;
+
+    for _ in 0..data.swapchain_images.len() {
+        let (buffer, buffer_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            (extent.width * extent.height * 4) as u64,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.capture.buffers.push(buffer);
+        data.capture.buffers_memory.push(buffer_memory);
+    }
+
+    data.capture.pending = vec![None; data.swapchain_images.len()];
+
+    Ok(())
+}
+
+/// Writes the pending captures, and waits for the files to be written. The device must
+/// be idle.
+pub unsafe fn flush_captures(device: &Device, data: &mut AppData) {
+    for image_index in 0..data.capture.pending.len() {
+        if let Err(e) = save_capture(device, data, image_index) {
+            warn!("Could not read back a capture: {}", e);
+        }
+    }
+
+    data.capture.writers.drain(..).for_each(|w| {
+        let _ = w.join();
+    });
+}
+
+/// Writes the pending captures and destroys the readback buffers. The device must be idle.
+pub unsafe fn destroy_capture_buffers(device: &Device, data: &mut AppData) {
+    flush_captures(device, data);
+
+    data.capture.buffers
+        .iter()
+        .for_each(|b| device.destroy_buffer(*b, None));
+    data.capture.buffers_memory
+        .drain(..)
+        .for_each(|m| data.allocator.free(device, m));
+    data.capture.buffers.clear();
+    data.capture.pending.clear();
+}
@@ -0,0 +1,498 @@
+//! Particles advanced by a compute shader and drawn as points, an optional mode toggled at
+//! runtime (`App::set_particles`).
+//!
+//! The particles are in a single storage buffer, also bound as the vertex buffer of the
+//! point pipeline. Each frame, `particles.comp` advances them at the start of the primary
+//! command buffer, before the render pass, and a buffer barrier makes its writes visible to
+//! the vertex input of the draw. The dispatch is recorded on the graphics queue, which must
+//! support compute (`QueueFamilyIndices::compute`).
+
+use std::f32::consts::PI;
+use std::mem::size_of;
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator::Allocation;
+use crate::buffers::{as_bytes, upload_via_staging};
+use crate::picking::{get_color_attachment_formats, get_picking_blend_attachment};
+use crate::{
+    begin_secondary_command_buffer, create_buffer, create_compute_pipeline, create_shader_module,
+    get_depth_attachment_format, get_output_transfer, read_shader, AppData, QueueFamilyIndices,
+};
+
+/// Number of particles simulated.
+const PARTICLE_COUNT: u32 = 16384;
+/// `local_size_x` of `particles.comp`.
+const WORKGROUP_SIZE: u32 = 256;
+
+/// The Vulkan handles of the particles, null until they are first enabled.
+#[derive(Clone, Debug, Default)]
+pub struct ParticleData {
+    buffer: vk::Buffer,
+    buffer_memory: Allocation,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    compute_pipeline_layout: vk::PipelineLayout,
+    compute_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+/// A particle as stored in the buffer (std430), the `w` components being unused.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Particle {
+    position: glm::Vec4,
+    velocity: glm::Vec4,
+}
+
+impl Particle {
+    fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Particle>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    fn attribute_description() -> [vk::VertexInputAttributeDescription; 2] {
+        let position = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+
+        let velocity = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(size_of::<glm::Vec4>() as u32)
+            .build();
+
+        [position, velocity]
+    }
+}
+
+/// Push constants of `particles.comp`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ComputePushConstants {
+    /// Seconds to advance the particles by.
+    dt: f32,
+    count: u32,
+}
+
+/// Whether the particles have been created.
+pub fn has_particles(data: &AppData) -> bool {
+    !data.particles.buffer.is_null()
+}
+
+/// Particles on a spherical shell around the origin (spread with the golden angle), turning
+/// around the Z axis so that they orbit it.
+fn get_initial_particles() -> Vec<Particle> {
+    let golden_angle = PI * (3.0 - 5f32.sqrt());
+
+    (0..PARTICLE_COUNT)
+        .map(|i| {
+            let t = (i as f32 + 0.5) / PARTICLE_COUNT as f32;
+            let z = 1.0 - 2.0 * t;
+            let r = (1.0 - z * z).sqrt();
+            let angle = golden_angle * i as f32;
+            let radius = 1.0 + 0.5 * (angle * 0.37).sin();
+
+            let position = glm::vec3(r * angle.cos(), r * angle.sin(), z) * radius;
+            let velocity = glm::vec3(-position.y, position.x, 0.0) * 0.8;
+
+            Particle {
+                position: glm::vec4(position.x, position.y, position.z, 1.0),
+                velocity: glm::vec4(velocity.x, velocity.y, velocity.z, 0.0),
+            }
+        })
+        .collect()
+}
+
+/////// COMPUTE ///////
+
+/// Creates the particle buffer, its descriptor set and the compute pipeline, then the point
+/// pipeline (`create_particle_swapchain_objects`).
+pub unsafe fn create_particle_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if QueueFamilyIndices::get(instance, data, data.physical_device)?.compute.is_none() {
+        return Err(anyhow!("The graphics queue doesn't support compute."));
+    }
+
+    let particles = get_initial_particles();
+    let size = (size_of::<Particle>() * particles.len()) as u64;
+
+    let (buffer, buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.particles.buffer = buffer;
+    data.particles.buffer_memory = buffer_memory;
+
+    // Lu et écrit d'abord par le compute shader, puis comme sommets après sa barrière
+    upload_via_staging(
+        instance,
+        device,
+        data,
+        as_bytes(&particles),
+        buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+    )?;
+
+    create_particle_descriptors(device, data)?;
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<ComputePushConstants>() as u32);
+
+    let set_layouts = &[data.particles.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.particles.compute_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let comp = read_shader(data, "particles_comp.spv")?;
+    data.particles.compute_pipeline =
+        create_compute_pipeline(device, data, data.particles.compute_pipeline_layout, &comp[..])?;
+
+    create_particle_swapchain_objects(device, data)
+}
+
+unsafe fn create_particle_descriptors(device: &Device, data: &mut AppData) -> Result<()> {
+    let particle_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let bindings = &[particle_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.particles.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let particle_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[particle_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.particles.descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.particles.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.particles.descriptor_pool)
+        .set_layouts(layouts);
+
+    data.particles.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.particles.buffer)
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64);
+
+    let buffer_info = &[info];
+    let particle_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.particles.descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(buffer_info);
+
+    device.update_descriptor_sets(&[particle_write], &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(())
+}
+
+/// Advances the particles by `dt` seconds, in the primary `command_buffer` outside of any
+/// render pass. Does nothing if they haven't been created.
+pub unsafe fn cmd_update_particles(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, dt: f32) {
+    if !has_particles(data) {
+        return;
+    }
+
+    // Les frames précédentes (en vol) lisent encore le buffer comme vertex buffer, et leur
+    // dispatch doit être visible à celui-ci
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(data.particles.buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    let push_constants = ComputePushConstants { dt, count: PARTICLE_COUNT };
+    let (_, push_constants_bytes, _) = std::slice::from_ref(&push_constants).align_to::<u8>();
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.particles.compute_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        data.particles.compute_pipeline_layout,
+        0,
+        &[data.particles.descriptor_set],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        data.particles.compute_pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        push_constants_bytes,
+    );
+    device.cmd_dispatch(command_buffer, PARTICLE_COUNT.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+    // Les positions écrites sont lues comme sommets par le dessin des points
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(data.particles.buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::VERTEX_INPUT,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/////// PIPELINE ///////
+
+/// Creates the point pipeline, for the current swapchain (if the particles have been created).
+pub unsafe fn create_particle_swapchain_objects(device: &Device, data: &mut AppData) -> Result<()> {
+    if !has_particles(data) {
+        return Ok(());
+    }
+
+    let vert = read_shader(data, "particles_vert.spv")?;
+    let frag = read_shader(data, "particles_frag.spv")?;
+
+    let vert_shader_module = create_shader_module(device, &vert[..])?;
+    let frag_shader_module = create_shader_module(device, &frag[..])?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let output_transfer = get_output_transfer(data).to_ne_bytes();
+    let map_entries = &[vk::SpecializationMapEntry::builder()
+        .constant_id(0)
+        .offset(0)
+        .size(size_of::<u32>())];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&output_transfer);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let binding_descriptions = &[Particle::binding_description()];
+    let attribute_descriptions = Particle::attribute_description();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::POINT_LIST)
+        .primitive_restart_enable(false);
+
+    // Le viewport et le scissor sont dynamiques, fixés par `begin_secondary_command_buffer`
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Les particules passent derrière les modèles
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment.build(), get_picking_blend_attachment(false)];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+        .dynamic_states(dynamic_states);
+
+    // La matrice vue-projection
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(size_of::<glm::Mat4>() as u32);
+
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .push_constant_ranges(push_constant_ranges);
+
+    data.particles.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.particles.pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(0);
+
+    let color_attachment_formats = &get_color_attachment_formats(data);
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_attachment_format(data));
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.particles.pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/////// DRAWING ///////
+
+/// Records a secondary command buffer drawing the particles, seen through `view_projection`.
+pub unsafe fn update_particle_command_buffer(
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    view_projection: &glm::Mat4,
+) -> Result<vk::CommandBuffer> {
+    while image_index >= data.particles.command_buffers.len() {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(data.command_pools[data.particles.command_buffers.len()])
+            .level(vk::CommandBufferLevel::SECONDARY)
+            .command_buffer_count(1);
+
+        let command_buffer = device.allocate_command_buffers(&allocate_info)?[0];
+        data.particles.command_buffers.push(command_buffer);
+    }
+
+    let command_buffer = data.particles.command_buffers[image_index];
+
+    let (_, view_projection_bytes, _) = view_projection.as_slice().align_to::<u8>();
+
+    begin_secondary_command_buffer(device, data, command_buffer, image_index)?;
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.particles.pipeline);
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.particles.buffer], &[0]);
+    device.cmd_push_constants(
+        command_buffer,
+        data.particles.pipeline_layout,
+        vk::ShaderStageFlags::VERTEX,
+        0,
+        view_projection_bytes,
+    );
+    device.cmd_draw(command_buffer, PARTICLE_COUNT, 1, 0, 0);
+
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(command_buffer)
+}
+
+/////// DESTRUCTION ///////
+
+pub unsafe fn destroy_particle_swapchain_objects(device: &Device, data: &mut AppData) {
+    device.destroy_pipeline(data.particles.pipeline, None);
+    device.destroy_pipeline_layout(data.particles.pipeline_layout, None);
+    data.particles.pipeline = vk::Pipeline::null();
+    data.particles.pipeline_layout = vk::PipelineLayout::null();
+}
+
+/// Forgets the command buffers of the particles, freed with the command pools of the
+/// swapchain images they were allocated from.
+pub fn clear_particle_command_buffers(data: &mut AppData) {
+    data.particles.command_buffers.clear();
+}
+
+pub unsafe fn destroy_particle_objects(device: &Device, data: &mut AppData) {
+    destroy_particle_swapchain_objects(device, data);
+
+    device.destroy_pipeline(data.particles.compute_pipeline, None);
+    device.destroy_pipeline_layout(data.particles.compute_pipeline_layout, None);
+    device.destroy_descriptor_pool(data.particles.descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.particles.descriptor_set_layout, None);
+    device.destroy_buffer(data.particles.buffer, None);
+    data.allocator.free(device, data.particles.buffer_memory);
+
+    // Les command buffers restent dans les pools des images
+    let command_buffers = std::mem::take(&mut data.particles.command_buffers);
+    data.particles = ParticleData { command_buffers, ..Default::default() };
+}
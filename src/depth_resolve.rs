@@ -0,0 +1,238 @@
+//! Resolve of the multisampled depth image, for `App::read_depth_at`.
+//!
+//! A multisampled image can't be copied to a buffer, and `cmd_resolve_image` doesn't take
+//! depth formats. Like the model indices (see `picking`), the first sample of each pixel
+//! (the samples of a depth can't be averaged) is resolved to a single sample image, which is
+//! the one read: by an empty render pass whose subpass loads the depth image and resolves it
+//! (`VK_KHR_depth_stencil_resolve`). Its objects are only created for a readback.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrCreateRenderpass2Extension;
+
+use crate::{create_image, create_image_view, get_depth_aspect_mask, get_depth_layout, AppData};
+
+/// The device extensions needed by the resolve, with the ones they depend on (core since
+/// Vulkan 1.1 or 1.2, but the instance may be created with an older version).
+pub const DEPTH_RESOLVE_EXTENSIONS: &[vk::ExtensionName] = &[
+    vk::KHR_MULTIVIEW_EXTENSION.name,
+    vk::KHR_MAINTENANCE2_EXTENSION.name,
+    vk::KHR_CREATE_RENDERPASS2_EXTENSION.name,
+    vk::KHR_DEPTH_STENCIL_RESOLVE_EXTENSION.name,
+];
+
+/// The single sample image the depth image is resolved to, and what resolves it.
+#[derive(Clone, Debug, Default)]
+pub struct DepthResolve {
+    pub image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    image_view: vk::ImageView,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+}
+
+/// Whether `physical_device` supports the extensions of the resolve (which always supports
+/// the resolve of the first sample).
+pub unsafe fn check_depth_resolve(instance: &Instance, physical_device: vk::PhysicalDevice) -> Result<bool> {
+    let extensions = instance
+        .enumerate_device_extension_properties(physical_device, None)?
+        .iter()
+        .map(|e| e.extension_name)
+        .collect::<HashSet<_>>();
+
+    Ok(DEPTH_RESOLVE_EXTENSIONS.iter().all(|e| extensions.contains(e)))
+}
+
+/// Creates the single sample image, left in the `TRANSFER_SRC_OPTIMAL` layout by
+/// `cmd_resolve_depth`, and the render pass and framebuffer resolving the depth image to it.
+pub unsafe fn create_depth_resolve(instance: &Instance, device: &Device, data: &mut AppData) -> Result<DepthResolve> {
+    if !data.depth_resolve {
+        return Err(anyhow!("The device can't resolve the depth (no `VK_KHR_depth_stencil_resolve`)."));
+    }
+
+    let format = data.depth_format;
+    let extent = data.swapchain_extent;
+
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        extent.width,
+        extent.height,
+        1,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    let mut resolve = DepthResolve {
+        image,
+        image_memory,
+        ..Default::default()
+    };
+
+    // Détruit avec ce qui a déjà été créé en cas d'erreur
+    if let Err(e) = create_depth_resolve_objects(device, data, &mut resolve) {
+        destroy_depth_resolve(device, data, &resolve);
+        return Err(e);
+    }
+
+    Ok(resolve)
+}
+
+unsafe fn create_depth_resolve_objects(device: &Device, data: &AppData, resolve: &mut DepthResolve) -> Result<()> {
+    let format = data.depth_format;
+    let depth_layout = get_depth_layout(data);
+
+    resolve.image_view = create_image_view(device, resolve.image, format, get_depth_aspect_mask(format), 1)?;
+
+    // Les layouts séparés ne décrivent que la profondeur, le stencil n'est pas utilisé
+    let separate_stencil = data.separate_depth_stencil_layouts && data.depth_has_stencil;
+    let mut depth_stencil_layout = vk::AttachmentDescriptionStencilLayout::builder()
+        .stencil_initial_layout(vk::ImageLayout::UNDEFINED)
+        .stencil_final_layout(vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL);
+    let mut resolve_stencil_layout = vk::AttachmentDescriptionStencilLayout::builder()
+        .stencil_initial_layout(vk::ImageLayout::UNDEFINED)
+        .stencil_final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+    // La profondeur de la dernière frame est gardée (STORE, voir `create_render_pass`)
+    let mut depth_attachment = vk::AttachmentDescription2::builder()
+        .format(format)
+        .samples(data.msaa_samples)
+        .load_op(vk::AttachmentLoadOp::LOAD)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(depth_layout)
+        .final_layout(depth_layout);
+
+    let mut resolve_attachment = vk::AttachmentDescription2::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+    let mut stencil_reference_layout = vk::AttachmentReferenceStencilLayout::builder()
+        .stencil_layout(vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL);
+    let mut resolve_stencil_reference_layout = vk::AttachmentReferenceStencilLayout::builder()
+        .stencil_layout(vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL);
+
+    let mut depth_attachment_ref = vk::AttachmentReference2::builder()
+        .attachment(0)
+        .layout(depth_layout);
+
+    let mut resolve_attachment_ref = vk::AttachmentReference2::builder()
+        .attachment(1)
+        .layout(depth_layout);
+
+    if separate_stencil {
+        depth_attachment = depth_attachment.push_next(&mut depth_stencil_layout);
+        resolve_attachment = resolve_attachment.push_next(&mut resolve_stencil_layout);
+        depth_attachment_ref = depth_attachment_ref.push_next(&mut stencil_reference_layout);
+        resolve_attachment_ref = resolve_attachment_ref.push_next(&mut resolve_stencil_reference_layout);
+    }
+
+    // Le premier échantillon pour le stencil aussi: les deux modes doivent souvent être égaux
+    let stencil_resolve_mode = if data.depth_has_stencil {
+        vk::ResolveModeFlags::SAMPLE_ZERO
+    } else {
+        vk::ResolveModeFlags::NONE
+    };
+    let mut depth_stencil_resolve = vk::SubpassDescriptionDepthStencilResolve::builder()
+        .depth_resolve_mode(vk::ResolveModeFlags::SAMPLE_ZERO)
+        .stencil_resolve_mode(stencil_resolve_mode)
+        .depth_stencil_resolve_attachment(&resolve_attachment_ref);
+
+    let subpass = vk::SubpassDescription2::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .depth_stencil_attachment(&depth_attachment_ref)
+        .push_next(&mut depth_stencil_resolve);
+
+    // Après l'écriture de la profondeur par la dernière frame, avant la copie de l'image résolue
+    // (la résolution peut se faire avec les tests de profondeur ou la sortie des couleurs)
+    let dependencies = &[
+        vk::SubpassDependency2::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )
+            .build(),
+        vk::SubpassDependency2::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build(),
+    ];
+
+    let attachments = &[depth_attachment.build(), resolve_attachment.build()];
+    let subpasses = &[subpass.build()];
+    let info = vk::RenderPassCreateInfo2::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    resolve.render_pass = device.create_render_pass2_khr(&info, None)?;
+
+    let attachments = &[data.depth_image_view, resolve.image_view];
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(resolve.render_pass)
+        .attachments(attachments)
+        .width(data.swapchain_extent.width)
+        .height(data.swapchain_extent.height)
+        .layers(1);
+
+    resolve.framebuffer = device.create_framebuffer(&info, None)?;
+
+    Ok(())
+}
+
+/// Resolves the depth image of the last frame, in the layout it is left in by the main
+/// pass, to the single sample image.
+pub unsafe fn cmd_resolve_depth(
+    device: &Device,
+    data: &AppData,
+    resolve: &DepthResolve,
+    command_buffer: vk::CommandBuffer,
+) {
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain_extent);
+
+    // Aucun dessin: seule la résolution de fin de subpass a lieu
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(resolve.render_pass)
+        .framebuffer(resolve.framebuffer)
+        .render_area(render_area);
+
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+    device.cmd_end_render_pass(command_buffer);
+}
+
+pub unsafe fn destroy_depth_resolve(device: &Device, data: &mut AppData, resolve: &DepthResolve) {
+    device.destroy_framebuffer(resolve.framebuffer, None);
+    device.destroy_render_pass(resolve.render_pass, None);
+    device.destroy_image_view(resolve.image_view, None);
+    device.destroy_image(resolve.image, None);
+    data.allocator.free_image(device, resolve.image_memory);
+}
@@ -0,0 +1,65 @@
+//! The errors of Vulkan App, returned by its entry points (`App::create_with_config` and
+//! `App::render`) so that their callers can tell them apart.
+//!
+//! Internally the functions return `anyhow` errors, converted at the entry points: the ones
+//! created from a `TutorialError` (`anyhow!(TutorialError::...)`) keep their variant.
+
+use std::io;
+
+use thiserror::Error;
+use vulkanalia::prelude::v1_0::*;
+
+/// The requirements of the app that a physical device doesn't meet, all of them.
+#[derive(Debug, Error)]
+#[error("Missing {}.", .0.join(", "))]
+pub struct SuitabilityError(pub Vec<String>);
+
+#[derive(Debug, Error)]
+pub enum TutorialError {
+    /// No physical device meets the requirements of the app (or the forced one doesn't).
+    #[error("No suitable physical device: {0}")]
+    NoSuitableDevice(String),
+    /// A shader which can't be read or isn't valid SPIR-V.
+    #[error("Invalid shader: {0}")]
+    Shader(String),
+    #[error("Out of device memory.")]
+    OutOfDeviceMemory,
+    /// The device was lost (driver crash or reset), the app can't render anymore.
+    #[error("Device lost.")]
+    DeviceLost,
+    #[error("Vulkan error: {0}")]
+    Vulkan(vk::ErrorCode),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<vk::ErrorCode> for TutorialError {
+    fn from(code: vk::ErrorCode) -> Self {
+        match code {
+            vk::ErrorCode::OUT_OF_DEVICE_MEMORY => Self::OutOfDeviceMemory,
+            vk::ErrorCode::DEVICE_LOST => Self::DeviceLost,
+            _ => Self::Vulkan(code),
+        }
+    }
+}
+
+impl From<anyhow::Error> for TutorialError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<TutorialError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+
+        let error = match error.downcast::<vk::ErrorCode>() {
+            Ok(code) => return code.into(),
+            Err(error) => error,
+        };
+
+        match error.downcast::<io::Error>() {
+            Ok(error) => Self::Io(error),
+            Err(error) => Self::Other(error),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Frame rate and frame time, averaged over a short sliding window, and an optional cap
+//! on the frame rate.
+
+use std::collections::VecDeque;
+use std::hint;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Duration of the sliding window.
+const WINDOW: Duration = Duration::from_millis(500);
+/// End of the wait of `FrameLimiter` spent spinning instead of sleeping, `thread::sleep`
+/// often oversleeping by up to a millisecond.
+const SPIN_TAIL: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameRateCounter {
+    /// When each frame of the window was rendered, oldest first.
+    frames: VecDeque<Instant>,
+}
+
+impl FrameRateCounter {
+    /// Records that a frame has been rendered.
+    pub fn frame(&mut self) {
+        let now = Instant::now();
+        while self.frames.front().is_some_and(|f| now - *f > WINDOW) {
+            self.frames.pop_front();
+        }
+
+        self.frames.push_back(now);
+    }
+
+    /// The average time between two frames, `None` before the second frame.
+    pub fn frame_time(&self) -> Option<Duration> {
+        let (first, last) = (self.frames.front()?, self.frames.back()?);
+        let intervals = self.frames.len() as u32 - 1;
+
+        (intervals > 0).then(|| (*last - *first) / intervals)
+    }
+
+    /// The average number of frames per second, `None` before the second frame.
+    pub fn fps(&self) -> Option<f64> {
+        self.frame_time().map(|t| 1.0 / t.as_secs_f64())
+    }
+
+    pub fn reset(&mut self) {
+        self.frames.clear();
+    }
+}
+
+/// Caps the frame rate by waiting out the rest of the budget of each frame.
+#[derive(Clone, Debug)]
+pub struct FrameLimiter {
+    /// Maximum frames per second, 0 for unlimited.
+    max_fps: u32,
+    /// When the last frame ended: its deadline if it was waited for, so that the late
+    /// wake-ups don't accumulate.
+    last_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(max_fps: u32) -> Self {
+        Self { max_fps, last_frame: Instant::now() }
+    }
+
+    pub fn max_fps(&self) -> u32 {
+        self.max_fps
+    }
+
+    /// Changes the cap, 0 for unlimited.
+    pub fn set_max_fps(&mut self, max_fps: u32) {
+        self.max_fps = max_fps;
+        self.last_frame = Instant::now();
+    }
+
+    /// Waits until the budget of the frame (`1 / max_fps` since the end of the last one)
+    /// is spent: slept, then spun for the last `SPIN_TAIL`.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if self.max_fps == 0 {
+            self.last_frame = now;
+            return;
+        }
+
+        let deadline = self.last_frame + Duration::from_secs_f64(1.0 / self.max_fps as f64);
+        if deadline <= now {
+            // En retard: le budget repart de maintenant plutôt que de rattraper
+            self.last_frame = now;
+            return;
+        }
+
+        if deadline - now > SPIN_TAIL {
+            thread::sleep(deadline - now - SPIN_TAIL);
+        }
+        while Instant::now() < deadline {
+            hint::spin_loop();
+        }
+
+        self.last_frame = deadline;
+    }
+}
@@ -0,0 +1,150 @@
+//! GPU time of the frames, measured by timestamps written at the start and at the end of
+//! their primary command buffer, two queries per frame in flight.
+//!
+//! The timestamps of a frame are read once its fence is signaled, when the same frame in
+//! flight comes around again, so the time shown lags `AppData::frames_in_flight` frames behind.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{AppData, QueueFamilyIndices};
+
+/// The timestamp query pool and the last measure.
+#[derive(Clone, Debug, Default)]
+pub struct GpuTimingData {
+    /// Null if the graphics queue doesn't support timestamps.
+    query_pool: vk::QueryPool,
+    /// Nanoseconds per timestamp tick (`timestampPeriod`).
+    timestamp_period: f32,
+    /// Mask of the bits of the timestamps which are valid (`timestampValidBits`).
+    timestamp_mask: u64,
+    /// Whether the queries of each frame in flight have been written and not read yet.
+    pending: Vec<bool>,
+    frame_time: Option<Duration>,
+}
+
+/// Creates the query pool, if the device supports timestamps on the graphics queue.
+pub unsafe fn create_timestamp_query_pool(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let limits = data.physical_device_properties.limits;
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let valid_bits = instance
+        .get_physical_device_queue_family_properties(data.physical_device)[indices.graphics as usize]
+        .timestamp_valid_bits;
+
+    // timestampComputeAndGraphics les garantit sur toutes les files graphiques, sans la
+    // file utilisée peut quand même les supporter
+    if valid_bits == 0 {
+        warn!(
+            "Timestamps are not supported by the graphics queue (timestampComputeAndGraphics: {}), no GPU frame time.",
+            limits.timestamp_compute_and_graphics == vk::TRUE,
+        );
+        return Ok(());
+    }
+
+    let info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count((data.frames_in_flight * 2) as u32);
+
+    data.gpu_timing = GpuTimingData {
+        query_pool: device.create_query_pool(&info, None)?,
+        timestamp_period: limits.timestamp_period,
+        timestamp_mask: if valid_bits >= 64 { u64::MAX } else { (1 << valid_bits) - 1 },
+        pending: vec![false; data.frames_in_flight],
+        ..Default::default()
+    };
+
+    Ok(())
+}
+
+/// Resets the queries of `frame` and writes its start timestamp, at the beginning of its
+/// primary command buffer (outside of any render pass).
+pub unsafe fn cmd_begin_gpu_timing(
+    device: &Device,
+    data: &mut AppData,
+    command_buffer: vk::CommandBuffer,
+    frame: usize,
+) {
+    let query_pool = data.gpu_timing.query_pool;
+    if query_pool.is_null() {
+        return;
+    }
+
+    device.cmd_reset_query_pool(command_buffer, query_pool, (frame * 2) as u32, 2);
+    device.cmd_write_timestamp(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        query_pool,
+        (frame * 2) as u32,
+    );
+
+    data.gpu_timing.pending[frame] = true;
+}
+
+/// Writes the end timestamp of `frame`, at the end of its primary command buffer.
+pub unsafe fn cmd_end_gpu_timing(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    frame: usize,
+) {
+    let query_pool = data.gpu_timing.query_pool;
+    if query_pool.is_null() {
+        return;
+    }
+
+    device.cmd_write_timestamp(
+        command_buffer,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        query_pool,
+        (frame * 2 + 1) as u32,
+    );
+}
+
+/// Reads the timestamps of `frame`, whose fence must have been waited for.
+pub unsafe fn read_gpu_timing(device: &Device, data: &mut AppData, frame: usize) -> Result<()> {
+    let timing = &mut data.gpu_timing;
+    if timing.query_pool.is_null() || !timing.pending[frame] {
+        return Ok(());
+    }
+
+    let mut results = [0u8; 16];
+    let result = device.get_query_pool_results(
+        timing.query_pool,
+        (frame * 2) as u32,
+        2,
+        &mut results,
+        8,
+        vk::QueryResultFlags::_64,
+    )?;
+
+    // NOT_READY: la frame n'a pas (encore) été soumise, on réessaiera au prochain passage
+    if result == vk::SuccessCode::NOT_READY {
+        return Ok(());
+    }
+
+    let start = u64::from_ne_bytes(results[..8].try_into()?);
+    let end = u64::from_ne_bytes(results[8..].try_into()?);
+    let ticks = end.wrapping_sub(start) & timing.timestamp_mask;
+
+    timing.pending[frame] = false;
+    timing.frame_time = Some(Duration::from_nanos((ticks as f64 * timing.timestamp_period as f64) as u64));
+
+    Ok(())
+}
+
+/// GPU time of the last measured frame, `None` without timestamps or before the first one.
+pub fn get_gpu_frame_time(data: &AppData) -> Option<Duration> {
+    data.gpu_timing.frame_time
+}
+
+pub unsafe fn destroy_timestamp_query_pool(device: &Device, data: &mut AppData) {
+    device.destroy_query_pool(data.gpu_timing.query_pool, None);
+    data.gpu_timing = GpuTimingData::default();
+}
@@ -0,0 +1,110 @@
+//! Headless mode, for automated screenshots: the window stays hidden and no surface is
+//! created. The frames are rendered to an offscreen image standing in for the swapchain
+//! images, and written to a PNG file by `App::render_to_file`.
+//!
+//! The offscreen image can also be one owned by an app embedding the renderer (a
+//! `RenderTarget`), rendered to by `App::render_to_image`.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+use winit::window::Window;
+
+use crate::{create_image, AppData};
+
+/// Format of the offscreen image, BGRA like most swapchains (swizzled when captured).
+const OFFSCREEN_FORMAT: vk::Format = vk::Format::B8G8R8A8_SRGB;
+
+/// What the headless mode renders.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadlessOutput {
+    /// PNG file the last frame is written to.
+    pub path: PathBuf,
+    pub frame_count: u32,
+}
+
+/// An image owned by the app embedding the renderer, used as the offscreen image.
+///
+/// It must have been created on the logical device of the app, with (at least) the
+/// `COLOR_ATTACHMENT` and `SAMPLED` usages, and not be in use by the GPU when a frame is
+/// rendered to it. Its content on entry is discarded (whatever its layout), and it is left
+/// in the `SHADER_READ_ONLY_OPTIMAL` layout (`get_target_layout`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RenderTarget {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+/// Creates the offscreen image, the size of the (hidden) window, in place of the swapchain,
+/// or uses `data.render_target` if there is one.
+pub unsafe fn create_offscreen_swapchain(
+    window: &Window,
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.swapchain = vk::SwapchainKHR::null();
+    data.swapchain_color_space = vk::ColorSpaceKHR::SRGB_NONLINEAR;
+    data.swapchain_transform = vk::SurfaceTransformFlagsKHR::IDENTITY;
+
+    // L'image appartient à l'app: pas de mémoire à libérer, ni de capture possible
+    if let Some(target) = data.render_target {
+        data.swapchain_format = target.format;
+        data.swapchain_extent = target.extent;
+        data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+        data.swapchain_images = vec![target.image];
+        data.offscreen_image_memory = vk::DeviceMemory::null();
+        return Ok(());
+    }
+
+    let size = window.inner_size();
+
+    data.swapchain_format = OFFSCREEN_FORMAT;
+    data.swapchain_extent = vk::Extent2D { width: size.width.max(1), height: size.height.max(1) };
+    data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        vk::SampleCountFlags::_1,
+        data.swapchain_format,
+        vk::ImageTiling::OPTIMAL,
+        data.swapchain_usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.swapchain_images = vec![image];
+    data.offscreen_image_memory = image_memory;
+
+    Ok(())
+}
+
+/// Destroys the offscreen image, unless it is a `RenderTarget` (without memory of ours).
+pub unsafe fn destroy_offscreen_swapchain(device: &Device, data: &mut AppData) {
+    if data.offscreen_image_memory.is_null() {
+        return;
+    }
+
+    data.swapchain_images
+        .iter()
+        .for_each(|i| device.destroy_image(*i, None));
+    data.allocator.free_image(device, data.offscreen_image_memory);
+    data.offscreen_image_memory = vk::DeviceMemory::null();
+}
+
+/// The layout the main pass leaves the swapchain images in: `PRESENT_SRC_KHR`, or
+/// `SHADER_READ_ONLY_OPTIMAL` for a `RenderTarget`, to be sampled by the app embedding the
+/// renderer.
+pub fn get_target_layout(data: &AppData) -> vk::ImageLayout {
+    if data.render_target.is_some() {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    } else {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    }
+}
@@ -0,0 +1,166 @@
+//! Textures in the KTX2 format, usually block compressed (BC7...) and with their mip levels
+//! already computed, uploaded as is: no decoding and no `generate_mipmaps` blit pass.
+//!
+//! Supercompressed (Basis Universal, Zstandard) files aren't supported, they would need to
+//! be transcoded first.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::buffers::stage;
+use crate::transfer::upload_image;
+use crate::{can_generate_mipmaps, create_image, generate_mipmaps, transition_image_layout, AppData};
+
+/// Alignment of the levels in the staging buffer, a multiple of 4 and of the size of the
+/// compressed blocks (8 or 16 bytes).
+const LEVEL_ALIGNMENT: usize = 16;
+
+/// Whether the texture at `path` is a KTX2 file.
+pub fn is_ktx2(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ktx2"))
+}
+
+/// Creates the texture image from the KTX2 file `data.texture_path`, in the format of the
+/// file. The mip levels of the file are used if there are any, otherwise they are generated
+/// when the format can be blitted.
+pub unsafe fn create_ktx2_texture_image(
+    instance: &Instance,
+    logical_device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let path = data.texture_path.clone();
+    let bytes = fs::read(&path)?;
+    let reader = ktx2::Reader::new(&bytes)
+        .map_err(|e| anyhow!("Invalid KTX2 texture `{}` ({}).", path.display(), e))?;
+    let header = reader.header();
+
+    if header.supercompression_scheme.is_some() {
+        return Err(anyhow!("Supercompressed KTX2 texture `{}` not supported.", path.display()));
+    }
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+        return Err(anyhow!("KTX2 texture `{}` is not a 2D texture.", path.display()));
+    }
+
+    let format = header
+        .format
+        .map(|f| vk::Format::from_raw(f.0.get() as i32))
+        .ok_or_else(|| anyhow!("KTX2 texture `{}` without a Vulkan format.", path.display()))?;
+
+    // Le sampler filtre linéairement
+    let features = instance
+        .get_physical_device_format_properties(data.physical_device, format)
+        .optimal_tiling_features;
+    if !features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) {
+        return Err(anyhow!(
+            "Format {:?} of KTX2 texture `{}` not supported by the device.",
+            format,
+            path.display(),
+        ));
+    }
+
+    let (width, height) = (header.pixel_width, header.pixel_height);
+    let levels = reader.levels().collect::<Vec<_>>();
+
+    // Les formats compressés ne peuvent pas être blittés, leurs mips doivent être dans le fichier
+    let generate = data.mipmaps && levels.len() == 1 && can_generate_mipmaps(instance, data, format);
+
+    let uploaded_levels = if data.mipmaps { levels.len() as u32 } else { 1 };
+    data.mip_levels = if generate {
+        (width.max(height) as f32).log2().floor() as u32 + 1
+    } else {
+        uploaded_levels
+    };
+
+    let offsets = levels[..uploaded_levels as usize]
+        .iter()
+        .scan(0, |offset: &mut usize, level| {
+            let aligned = offset.next_multiple_of(LEVEL_ALIGNMENT);
+            *offset = aligned + level.len();
+            Some(aligned)
+        })
+        .collect::<Vec<_>>();
+    let size = offsets[offsets.len() - 1] + levels[offsets.len() - 1].len();
+
+    let mut bytes = vec![0; size];
+    for (offset, level) in offsets.iter().zip(&levels) {
+        bytes[*offset..*offset + level.len()].copy_from_slice(level);
+    }
+
+    let staging_buffer = stage(instance, logical_device, data, &bytes)?;
+
+    let (texture_image, texture_image_memory) = create_image(
+        instance,
+        logical_device,
+        data,
+        width,
+        height,
+        data.mip_levels,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED |
+            vk::ImageUsageFlags::TRANSFER_DST |
+            vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.texture_image = texture_image;
+    data.texture_image_memory = texture_image_memory;
+    data.texture_format = format;
+
+    // Une copie par niveau, chacun deux fois plus petit que le précédent
+    let regions = offsets
+        .iter()
+        .enumerate()
+        .map(|(level, offset)| {
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(level as u32)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            vk::BufferImageCopy::builder()
+                .buffer_offset(*offset as u64)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(subresource)
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: (width >> level).max(1),
+                    height: (height >> level).max(1),
+                    depth: 1,
+                })
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    upload_image(logical_device, data, staging_buffer, texture_image, data.mip_levels, &regions)?;
+
+    if generate {
+        generate_mipmaps(
+            instance,
+            logical_device,
+            data,
+            texture_image,
+            format,
+            width,
+            height,
+            data.mip_levels,
+        )?;
+    } else {
+        transition_image_layout(
+            logical_device,
+            data,
+            texture_image,
+            format,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            data.mip_levels,
+        )?;
+    }
+
+    Ok(())
+}
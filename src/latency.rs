@@ -0,0 +1,54 @@
+//! Measure of the present-to-acquire latency: how long a presented image takes to be
+//! given back by the presentation engine, which depends on the presentation mode (FIFO
+//! queues the images, MAILBOX replaces the waiting one).
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of samples of the rolling average.
+const LATENCY_SAMPLES: usize = 120;
+
+#[derive(Clone, Debug, Default)]
+pub struct LatencyMeter {
+    /// When each swapchain image was last presented.
+    presented: Vec<Option<Instant>>,
+    samples: VecDeque<Duration>,
+}
+
+impl LatencyMeter {
+    /// Records that the swapchain image `image_index` has been presented.
+    pub fn presented(&mut self, image_index: usize) {
+        if image_index >= self.presented.len() {
+            self.presented.resize(image_index + 1, None);
+        }
+
+        self.presented[image_index] = Some(Instant::now());
+    }
+
+    /// Records that the swapchain image `image_index` has been acquired.
+    pub fn acquired(&mut self, image_index: usize) {
+        let Some(presented) = self.presented.get_mut(image_index).and_then(Option::take) else {
+            return;
+        };
+
+        if self.samples.len() == LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(presented.elapsed());
+    }
+
+    /// The rolling average of the latency, `None` before the first sample.
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+
+    /// Forgets the samples, when the swapchain images are recreated.
+    pub fn reset(&mut self) {
+        self.presented.clear();
+        self.samples.clear();
+    }
+}
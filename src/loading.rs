@@ -0,0 +1,250 @@
+//! Loading of the models and the texture on a background thread (`AppConfig::async_load`),
+//! while the main thread shows a progress bar.
+//!
+//! The loading thread only reads and decodes files: it never sees a Vulkan handle. Every
+//! handle is created on the main thread, which also makes every queue submission (on the
+//! graphics, presentation and transfer queues), so the queues are externally synchronized
+//! without any lock. The meshes and pixels are sent back through a channel and uploaded by
+//! the main thread once the loading is done.
+//!
+//! The window events aren't processed during the loading (the event loop isn't running
+//! yet): closing or resizing the window takes effect once it is done.
+
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrSwapchainExtension;
+
+use crate::ktx::is_ktx2;
+use crate::{
+    begin_secondary_command_buffer, cmd_begin_main_pass, cmd_end_main_pass, get_texture_path, load_model,
+    read_png_texture, AppData, CoordinateSystem, Mesh,
+};
+
+/// Longest wait for a message of the loading thread between two frames of the progress bar.
+const LOADING_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/// Color of the progress bar, drawn over the clear color.
+const PROGRESS_BAR_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+/// Height of the progress bar, in pixels.
+const PROGRESS_BAR_HEIGHT: u32 = 16;
+
+/// What the loading thread reads from the files.
+#[derive(Debug)]
+pub struct LoadedAssets {
+    /// The meshes of the models, without their buffers.
+    pub meshes: Vec<Mesh>,
+    /// The texture referenced by the models, if any.
+    pub model_texture: Option<PathBuf>,
+    /// The texture to use (see `get_texture_path`).
+    pub texture_path: PathBuf,
+    /// Its RGBA pixels, width and height, `None` for a KTX2 texture (uploaded as is).
+    pub texture: Option<(Vec<u8>, u32, u32)>,
+}
+
+#[derive(Debug)]
+enum LoadMessage {
+    /// The stage started and the fraction (0.0 to 1.0) of the loading done before it.
+    Progress(&'static str, f32),
+    Done(Result<LoadedAssets>),
+}
+
+/// The loading thread and the channel of its messages.
+#[derive(Debug)]
+pub struct Loader {
+    receiver: Receiver<LoadMessage>,
+    thread: JoinHandle<()>,
+}
+
+impl Loader {
+    /// Starts loading the models at `model_paths` then their texture (`texture_path`, or
+    /// else the one referenced by the models or the default one).
+    pub fn spawn(
+        model_paths: Vec<PathBuf>,
+        coordinate_system: Option<CoordinateSystem>,
+        texture_path: Option<PathBuf>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let result = load_assets(&sender, &model_paths, coordinate_system, texture_path);
+            // Le thread principal a abandonné s'il ne reçoit plus
+            let _ = sender.send(LoadMessage::Done(result));
+        });
+
+        Self { receiver, thread }
+    }
+}
+
+fn load_assets(
+    sender: &Sender<LoadMessage>,
+    model_paths: &[PathBuf],
+    coordinate_system: Option<CoordinateSystem>,
+    texture_path: Option<PathBuf>,
+) -> Result<LoadedAssets> {
+    // La texture compte comme un modèle de plus
+    let steps = (model_paths.len() + 1) as f32;
+
+    let mut model_texture = None;
+    let mut meshes = Vec::with_capacity(model_paths.len());
+    for (i, path) in model_paths.iter().enumerate() {
+        let _ = sender.send(LoadMessage::Progress("model", i as f32 / steps));
+        meshes.push(load_model(&mut model_texture, path, coordinate_system)?);
+    }
+
+    let _ = sender.send(LoadMessage::Progress("texture", model_paths.len() as f32 / steps));
+    let texture_path = get_texture_path(texture_path.as_ref(), model_texture.as_ref());
+    // Un fichier manquant est signalé par create_texture_image, comme sans chargement en arrière-plan
+    let texture = if texture_path.is_file() && !is_ktx2(&texture_path) {
+        Some(read_png_texture(&texture_path)?)
+    } else {
+        None
+    };
+
+    Ok(LoadedAssets { meshes, model_texture, texture_path, texture })
+}
+
+/// Waits for `loader` to finish, passing its progress to `progress` and drawing it as a
+/// progress bar over `clear_color` (except in headless mode). The command buffers and the
+/// synchronization objects of the frames must have been created.
+pub unsafe fn wait_for_assets(
+    device: &Device,
+    data: &mut AppData,
+    loader: Loader,
+    clear_color: [f32; 4],
+    progress: &dyn Fn(&str, f32),
+) -> Result<LoadedAssets> {
+    let mut fraction = 0.0;
+
+    let result = loop {
+        match loader.receiver.recv_timeout(LOADING_FRAME_INTERVAL) {
+            Ok(LoadMessage::Progress(stage, f)) => {
+                progress(stage, f);
+                fraction = f;
+            }
+            Ok(LoadMessage::Done(result)) => break result,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break Err(anyhow!("The loading thread panicked.")),
+        }
+
+        if !data.headless {
+            render_loading_frame(device, data, clear_color, fraction)?;
+        }
+    };
+
+    let _ = loader.thread.join();
+
+    // Les frames suivantes réutilisent les command buffers et les semaphores
+    device.device_wait_idle()?;
+
+    result
+}
+
+/// Presents a frame with the progress bar, synchronously: the objects of the first frame
+/// in flight are free again when it returns.
+unsafe fn render_loading_frame(
+    device: &Device,
+    data: &mut AppData,
+    clear_color: [f32; 4],
+    fraction: f32,
+) -> Result<()> {
+    let fence = data.in_flight_fences[0];
+    let image_available = data.image_available_semaphores[0];
+    let render_finished = data.render_finished_semaphores[0];
+
+    device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+    // La fenêtre a pu être redimensionnée: la swapchain est recréée après le chargement
+    let image_index = match device.acquire_next_image_khr(data.swapchain, u64::MAX, image_available, vk::Fence::null()) {
+        Ok((image_index, _)) => image_index as usize,
+        Err(vk::ErrorCode::OUT_OF_DATE_KHR) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let command_pool = data.command_pools[image_index];
+    device.reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+
+    let command_buffer = data.command_buffers[image_index];
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+    cmd_begin_main_pass(device, data, command_buffer, image_index, clear_color);
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::SECONDARY)
+        .command_buffer_count(1);
+
+    let secondary_command_buffer = device.allocate_command_buffers(&info)?[0];
+    begin_secondary_command_buffer(device, data, secondary_command_buffer, image_index)?;
+    cmd_clear_progress_bar(device, data, secondary_command_buffer, fraction);
+    device.end_command_buffer(secondary_command_buffer)?;
+
+    device.cmd_execute_commands(command_buffer, &[secondary_command_buffer]);
+    cmd_end_main_pass(device, data, command_buffer, image_index);
+    device.end_command_buffer(command_buffer)?;
+
+    let wait_semaphores = &[image_available];
+    let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+    let command_buffers = &[command_buffer];
+    let signal_semaphores = &[render_finished];
+    let submit_info = vk::SubmitInfo::builder()
+        .wait_semaphores(wait_semaphores)
+        .wait_dst_stage_mask(wait_stages)
+        .command_buffers(command_buffers)
+        .signal_semaphores(signal_semaphores);
+
+    device.reset_fences(&[fence])?;
+    device.queue_submit(data.graphics_queue, &[submit_info], fence)?;
+
+    let swapchains = &[data.swapchain];
+    let image_indices = &[image_index as u32];
+    let present_info = vk::PresentInfoKHR::builder()
+        .wait_semaphores(signal_semaphores)
+        .swapchains(swapchains)
+        .image_indices(image_indices);
+
+    match device.queue_present_khr(data.prensentation_queue, &present_info) {
+        Ok(_) | Err(vk::ErrorCode::OUT_OF_DATE_KHR) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    device.wait_for_fences(&[fence], true, u64::MAX)?;
+    device.free_command_buffers(command_pool, &[secondary_command_buffer]);
+
+    Ok(())
+}
+
+/// Clears the part of the progress bar done (`fraction` of its width), centered in the
+/// frame and 80% as wide.
+unsafe fn cmd_clear_progress_bar(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, fraction: f32) {
+    let extent = data.swapchain_extent;
+    let width = (extent.width as f32 * 0.8 * fraction.clamp(0.0, 1.0)) as u32;
+    let height = PROGRESS_BAR_HEIGHT.min(extent.height);
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    let attachment = vk::ClearAttachment::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .color_attachment(0)
+        .clear_value(vk::ClearValue {
+            color: vk::ClearColorValue { float32: PROGRESS_BAR_COLOR },
+        });
+
+    let rect = vk::ClearRect::builder()
+        .rect(vk::Rect2D {
+            offset: vk::Offset2D {
+                x: (extent.width / 10) as i32,
+                y: ((extent.height - height) / 2) as i32,
+            },
+            extent: vk::Extent2D { width, height },
+        })
+        .base_array_layer(0)
+        .layer_count(1);
+
+    device.cmd_clear_attachments(command_buffer, &[attachment], &[rect]);
+}
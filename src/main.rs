@@ -7,6 +7,31 @@
     )
 ]
 
+mod allocator;
+mod benchmark;
+mod buffers;
+mod camera;
+mod capture;
+mod compute;
+mod depth_resolve;
+mod error;
+mod frame_rate;
+mod gpu_timing;
+mod headless;
+mod ktx;
+mod latency;
+mod loading;
+mod picking;
+mod recording;
+mod skybox;
+mod streaming;
+mod sweep;
+mod text;
+mod texture;
+mod transfer;
+mod visibility;
+mod wireframe;
+
 use std::collections::HashSet;
 use std::ffi::CStr;
 use std::os::raw::c_void;
@@ -16,37 +41,125 @@ use log::*;
 use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::window as vk_window;
 use vulkanalia::prelude::v1_0::*;
-use winit::dpi::LogicalSize;
-use winit::event::{ElementState, VirtualKeyCode, Event, WindowEvent};
+use vulkanalia::Version;
+use winit::dpi::{LogicalSize, PhysicalPosition};
+use winit::event::{DeviceEvent, ElementState, ModifiersState, MouseButton, VirtualKeyCode, Event, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
-use thiserror::Error;
+use winit::monitor::MonitorHandle;
+use winit::window::{Fullscreen, Icon, Window, WindowBuilder};
 
 use vulkanalia::vk::ExtDebugUtilsExtension;
 use vulkanalia::vk::KhrSurfaceExtension;
 use vulkanalia::vk::KhrSwapchainExtension;
+use vulkanalia::vk::{DeviceV1_3, InstanceV1_1};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::hash::{Hash, Hasher};
 use std::io::BufReader;
+use std::path::{Path, PathBuf};
 
 /// Import pour shader
 use std::mem::size_of;
+use std::{iter, slice};
 use nalgebra_glm as glm;
 
 /// Import pour copier la mémoire vertex liste -> mapped memory
 use std::ptr::copy_nonoverlapping as memcpy;
 
-use std::time::Instant;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use std::fs::File;
 
+use allocator::{log_memory_usage, Allocation, Allocator};
+use benchmark::{Benchmark, RenderPath};
+use buffers::{as_bytes, destroy_staging_buffer, stage, upload_buffers, upload_via_staging, StagingBuffer};
+use camera::{Camera, ProjectionSettings};
+use compute::{
+    clear_particle_command_buffers, cmd_update_particles, create_particle_objects, create_particle_swapchain_objects,
+    destroy_particle_objects, destroy_particle_swapchain_objects, has_particles, update_particle_command_buffer,
+    ParticleData,
+};
+use capture::{
+    check_capture, cmd_capture_swapchain_image, destroy_capture_buffers, flush_captures, get_screenshot_path,
+    save_capture,
+    CaptureData, FrameSequence,
+};
+use depth_resolve::{
+    check_depth_resolve, cmd_resolve_depth, create_depth_resolve, destroy_depth_resolve, DEPTH_RESOLVE_EXTENSIONS,
+};
+use error::{SuitabilityError, TutorialError};
+use frame_rate::{FrameLimiter, FrameRateCounter};
+use gpu_timing::{
+    cmd_begin_gpu_timing, cmd_end_gpu_timing, create_timestamp_query_pool,
+    destroy_timestamp_query_pool, get_gpu_frame_time, read_gpu_timing, GpuTimingData,
+};
+use headless::{
+    create_offscreen_swapchain, destroy_offscreen_swapchain, get_target_layout, HeadlessOutput, RenderTarget,
+};
+use ktx::{create_ktx2_texture_image, is_ktx2};
+use recording::{
+    create_recording_objects, destroy_recording_objects, get_max_recording_threads,
+    record_model_command_buffers, RecordingData,
+};
+use latency::LatencyMeter;
+use loading::{wait_for_assets, Loader};
+use streaming::{
+    destroy_streaming_objects, get_texture_view, start_texture_stream, update_texture_stream, StreamingData,
+};
+use picking::{
+    create_picking_objects, destroy_picking_objects, get_color_attachment_formats, get_picking_attachment_descriptions,
+    get_picking_attachment_views, get_picking_barriers, get_picking_blend_attachment, get_picking_clear_value,
+    get_picking_rendering_attachment, read_picked_model, PickingData, NO_MODEL,
+};
+use skybox::{
+    clear_skybox_command_buffers, create_skybox_objects, create_skybox_swapchain_objects, destroy_skybox_objects,
+    destroy_skybox_swapchain_objects, get_skybox_descriptor, has_skybox, update_skybox_command_buffer, SkyboxData,
+};
+use sweep::{QualitySweep, SweepAction, SweepRanges};
+use text::{
+    create_text_objects, create_text_swapchain_objects, destroy_text_objects,
+    clear_text_command_buffers, destroy_text_swapchain_objects, update_text_command_buffer, TextData, TextStyle,
+};
+use texture::{create_second_texture, destroy_second_texture, SecondTexture};
+use transfer::{create_transfer_objects, destroy_transfer_objects, upload_buffer, upload_image, TransferData};
+use visibility::{
+    check_conditional_rendering, cmd_draw_if_visible, compute_bounding_spheres,
+    create_predicate_buffers, destroy_predicate_buffers, get_culled_count, is_model_recorded,
+    update_visibility, VisibilityData,
+};
+use wireframe::{export_wireframe_svg, WireframeStyle};
+
 /// Whether the validation layers should be enabled.
 const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
 /// The name of the validation layers.
 const VALIDATION_LAYER: vk::ExtensionName = vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
 
+/// How many validation messages are kept for `App::recent_validation_messages`.
+const MAX_VALIDATION_MESSAGES: usize = 64;
+
+const WINDOW_TITLE: &str = "The super duper cool Vulkan tutorial (for Rust only)";
+
 const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
-const MAX_FRAMES_IN_FLIGHT: usize = 2;
+/// Frames recorded while the previous ones are rendered, unless configured otherwise.
+const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+/// How often the window title (frame rate and latency) is rebuilt.
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Model loaded by default, Wavefront `.obj` or glTF (`.gltf`/`.glb`) depending on the extension.
+const MODEL_PATH: &str = "resources/viking_room.obj";
+/// Directory the SPIR-V shaders are read from by default.
+const SHADER_DIRECTORY: &str = "shaders";
+/// Texture used by default unless the model references its own.
+const TEXTURE_PATH: &str = "resources/viking_room.png";
+/// Maximum number of models drawn by the instanced path.
+const MAX_INSTANCES: usize = 1024;
+/// Clear colors selected with the number keys (RGBA, linear).
+const CLEAR_COLORS: [[f32; 4]; 4] = [
+    [0.0, 0.0, 0.0, 1.0],
+    [0.1, 0.1, 0.12, 1.0],
+    [0.39, 0.58, 0.93, 1.0],
+    [1.0, 1.0, 1.0, 1.0],
+];
 
 // Pour compiler les shaders sur ubuntu:
 // Télécharger les sources de shaderc sur le github: https://github.com/google/shaderc#downloads
@@ -55,19 +168,49 @@ const MAX_FRAMES_IN_FLIGHT: usize = 2;
 fn main() -> Result<()>{
     pretty_env_logger::init();
 
+    // vulkan-tutorial [--headless <frames> <image.png>] [--async-load] [--material-sets] [modèle...] [texture.png|texture.ktx2]
+    let config = AppConfig::from_args(std::env::args().skip(1))?;
+
     // Window
+    // Cachée en mode headless, elle ne sert qu'à la taille de l'image et aux extensions
+    let headless = config.headless.clone();
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("The super duper cool Vulkan tutorial (for Rust only)")
-        .with_inner_size(LogicalSize::new(1024,768))
+    let window = config
+        .get_window_builder()?
+        .with_visible(headless.is_none())
         .build(&event_loop)?;
 
+    if let Some(output) = headless {
+        let mut app = unsafe { App::create_with_config(&window, config, &|_, _| {})? };
+        unsafe { app.render_to_file(&output.path, output.frame_count)? };
+
+        // Déjà affichées par debug_callback: une capture automatisée échoue en plus
+        let errors = app
+            .recent_validation_messages()
+            .iter()
+            .filter(|m| m.severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+            .count();
+        if errors > 0 {
+            return Err(anyhow!("{} validation error(s) while rendering `{}`.", errors, output.path.display()));
+        }
+
+        return Ok(());
+    }
+
     // App
-    let mut app = unsafe { App::create(&window)? };
-    let mut destroying = false;
+    // L'app est détruite (Drop) à la fermeture de la fenêtre
+    let mut app = Some(unsafe {
+        App::create_with_config(&window, config, &|stage, fraction| {
+            info!("Loading: {} ({:.0}%)", stage, fraction * 100.0)
+        })?
+    });
     let mut minimized = false;
+    let mut cursor = PhysicalPosition::new(0.0, 0.0);
+    // La caméra ne tourne que pendant que le bouton droit est enfoncé
+    let mut looking = false;
+    let mut modifiers = ModifiersState::empty();
 
-    /**
+    /*
      * Les pipes sont pour définir une closure/fonction anonyme
      * Les _ pour marquer la présence de paramètres dont on ne se servira pas.
      *
@@ -77,40 +220,237 @@ fn main() -> Result<()>{
     event_loop.run(move |event, _, control_flow| {
         // ControlFlow::Poll, quand une itération de la boucle event est terminée,
         // en relance une immédiatement même s'il n y a aucun évenement à traiter
-        *control_flow = ControlFlow::Poll;
+        // ControlFlow::Wait attend le prochain évenement: rien à rendre tant que la fenêtre est réduite
+        // Choisi au début de l'itération, MainEventsCleared peut le changer pour celle-ci
+        if let Event::NewEvents(_) = event {
+            *control_flow = if minimized { ControlFlow::Wait } else { ControlFlow::Poll };
+        }
+
+        // Destroy Vulkan app
+        // Les .. permettent d'ignorer le reste des params, permet par exemple
+        // de ne pas avoir à mettre des _ à tout les params non renseignés
+        // run() ne rend jamais la main, l'app doit donc être libérée ici pour que Drop soit appelé
+        if let Event::WindowEvent { event: WindowEvent::CloseRequested, .. } = event {
+            *control_flow = ControlFlow::Exit;
+            app = None;
+        }
+
+        // Vulkan app is being destroyed
+        let Some(app) = app.as_mut() else {
+            return;
+        };
 
         match event {
-            // Render a frame if Vulkan app is not being destroyed
-            Event::MainEventsCleared if !destroying =>
-                unsafe { app.render(&window) }.unwrap(),
+            // Render a frame
+            // Les entrées sont traitées à chaque itération, même quand on ne rend pas
+            // Sans rendu, l'itération suivante attend au lieu de tourner à vide (un évenement
+            // la réveille plus tôt)
+            Event::MainEventsCleared if !minimized && !app.tick_render_divisor() => {
+                *control_flow = ControlFlow::WaitUntil(app.get_next_iteration_deadline());
+            }
+            Event::MainEventsCleared if !minimized => {
+                match unsafe { app.render(&window) } {
+                    // Réinitialisation du pilote (TDR...): tout est recréé sur un nouveau device
+                    Err(TutorialError::DeviceLost) => {
+                        error!("Device lost, recreating the renderer.");
+                        if let Err(e) = unsafe { app.recreate_device(&window) } {
+                            error!("Could not recreate the renderer after the device was lost, exiting: {}", e);
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                    result => result.unwrap(),
+                }
+            }
 
             Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
                 if size.width == 0 || size.height == 0 {
                     minimized = true;
-                } else {
+                    *control_flow = ControlFlow::Wait;
+                } else if minimized {
+                    // La swapchain a la taille d'avant la réduction, pas de redimensionnement en cours
                     minimized = false;
+                    *control_flow = ControlFlow::Poll;
+                    unsafe { app.recreate_swapchain(&window) }.unwrap();
+                } else {
                     app.resized = true;
+                    app.last_resize = Instant::now();
+                }
+            }
+
+            // Changement de moniteur ou de réglage d'échelle: la taille physique change, la
+            // fenêtre ne la prend qu'après l'événement, la swapchain est recréée ensuite
+            Event::WindowEvent { event: WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size }, .. } => {
+                info!(
+                    "Scale factor changed to {} ({}x{} physical).",
+                    scale_factor,
+                    new_inner_size.width,
+                    new_inner_size.height,
+                );
+                app.resized = true;
+            }
+
+            Event::WindowEvent { event: WindowEvent::ModifiersChanged(state), .. } => {
+                modifiers = state;
+            }
+
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, ..}, .. }
+                if input.state == ElementState::Released =>
+            {
+                if let Some(key) = input.virtual_keycode {
+                    app.held_keys.remove(&key);
                 }
             }
 
-            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, ..}, .. } => {
-                if input.state == ElementState::Pressed {
-                    match input.virtual_keycode {
-                        Some(VirtualKeyCode::Left) if app.models > 1 => app.models -= 1,
-                        Some(VirtualKeyCode::Right) if app.models < 4 => app.models += 1,
-                        _ => { }
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, ..}, .. }
+                if input.state == ElementState::Pressed =>
+            {
+                if let Some(key) = input.virtual_keycode {
+                    app.held_keys.insert(key);
+                }
+
+                match input.virtual_keycode {
+                    Some(VirtualKeyCode::PageDown) if app.models > 1 => app.models -= 1,
+                    Some(VirtualKeyCode::PageUp) if app.models < app.config.get_max_models() => app.models += 1,
+                    Some(VirtualKeyCode::Tab) => app.select_next_model(),
+                    Some(VirtualKeyCode::Left) => app.translate_selected_model(glm::vec3(0.0, -0.25, 0.0)),
+                    Some(VirtualKeyCode::Right) => app.translate_selected_model(glm::vec3(0.0, 0.25, 0.0)),
+                    Some(VirtualKeyCode::Up) => app.translate_selected_model(glm::vec3(0.0, 0.0, 0.25)),
+                    Some(VirtualKeyCode::Down) => app.translate_selected_model(glm::vec3(0.0, 0.0, -0.25)),
+                    Some(VirtualKeyCode::F11) => toggle_fullscreen(&window, app, false),
+                    Some(VirtualKeyCode::Return) if modifiers.alt() => toggle_fullscreen(&window, app, false),
+                    Some(VirtualKeyCode::F10) => toggle_fullscreen(&window, app, true),
+                    Some(VirtualKeyCode::M) => select_next_monitor(&window, app),
+                    Some(VirtualKeyCode::I) => app.instanced = !app.instanced,
+                    Some(VirtualKeyCode::J) => unsafe { app.set_particles(!app.particles) }.unwrap(),
+                    Some(VirtualKeyCode::O) => unsafe { app.set_skybox(!app.skybox) }.unwrap(),
+                    Some(VirtualKeyCode::T) => app.select_next_recording_threads(),
+                    Some(VirtualKeyCode::N) => app.select_next_render_divisor(),
+                    Some(VirtualKeyCode::L) => app.select_next_frame_rate_cap(),
+                    Some(VirtualKeyCode::U) => {
+                        let address_mode = match app.data.address_mode {
+                            vk::SamplerAddressMode::REPEAT => vk::SamplerAddressMode::MIRRORED_REPEAT,
+                            vk::SamplerAddressMode::MIRRORED_REPEAT => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                            vk::SamplerAddressMode::CLAMP_TO_EDGE => vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                            _ => vk::SamplerAddressMode::REPEAT,
+                        };
+                        unsafe { app.set_address_mode(address_mode) }.unwrap();
                     }
+                    Some(VirtualKeyCode::X) => unsafe { app.select_next_msaa_samples(&window) }.unwrap(),
+                    Some(VirtualKeyCode::E) => {
+                        if let Err(e) = app.export_wireframe_svg(Path::new("wireframe.svg")) {
+                            warn!("Could not export the wireframe: {}", e);
+                        }
+                    }
+                    Some(VirtualKeyCode::B) => app.start_benchmark(256, 500),
+                    Some(VirtualKeyCode::F) => app.reset_scene(),
+                    Some(VirtualKeyCode::H) => app.show_frame_rate = !app.show_frame_rate,
+                    Some(VirtualKeyCode::V) => unsafe { app.toggle_presentation_mode(&window) }.unwrap(),
+                    Some(VirtualKeyCode::P) => unsafe { app.toggle_wireframe() }.unwrap(),
+                    Some(VirtualKeyCode::Z) => unsafe { app.toggle_depth_prepass() }.unwrap(),
+                    Some(VirtualKeyCode::F12) => app.take_screenshot(&get_screenshot_path()),
+                    Some(VirtualKeyCode::K) => {
+                        let ranges = app.config.quality_sweep.clone();
+                        if let Err(e) = unsafe { app.start_quality_sweep(&window, Path::new("quality_sweep"), &ranges, 120) } {
+                            warn!("Could not start the quality sweep: {}", e);
+                        }
+                    }
+                    Some(VirtualKeyCode::C) => {
+                        if let Err(e) = app.start_recording(Path::new("frames"), 120) {
+                            warn!("Could not start recording: {}", e);
+                        }
+                    }
+                    Some(VirtualKeyCode::R) => {
+                        if let Err(e) = unsafe { app.reload_pipeline() } {
+                            warn!("Could not reload the shaders, keeping the old ones: {}", e);
+                        }
+                    }
+                    Some(VirtualKeyCode::Q) => {
+                        let preset = app.quality.next();
+                        unsafe { app.set_quality_preset(&window, preset) }.unwrap();
+                    }
+                    // Avec Ctrl, la taille du texte plutôt que le champ de vision
+                    Some(VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd) if modifiers.ctrl() => {
+                        app.set_text_style(app.text_style.size + 4.0, app.text_style.smoothing);
+                    }
+                    Some(VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract) if modifiers.ctrl() => {
+                        app.set_text_style(app.text_style.size - 4.0, app.text_style.smoothing);
+                    }
+                    Some(VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd) => {
+                        app.set_fov(app.projection.fov_degrees + 5.0);
+                    }
+                    Some(VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract) => {
+                        app.set_fov(app.projection.fov_degrees - 5.0);
+                    }
+                    Some(VirtualKeyCode::G) => {
+                        // 0 -> 0.25 -> ... -> 1 -> 0
+                        let texture_blend = if app.data.texture_blend >= 1.0 { 0.0 } else { app.data.texture_blend + 0.25 };
+                        app.set_texture_blend(texture_blend);
+                    }
+                    Some(VirtualKeyCode::Space) => app.toggle_animation(),
+                    Some(VirtualKeyCode::Y) => app.rotate_light(30.0),
+                    Some(VirtualKeyCode::LBracket) => app.set_animation_speed(app.animation_speed / 2.0),
+                    Some(VirtualKeyCode::RBracket) => app.set_animation_speed(app.animation_speed * 2.0),
+                    Some(VirtualKeyCode::Back) => unsafe { app.reset(&window) }.unwrap(),
+                    Some(key) if modifiers.alt() && get_digit(key).is_some() => {
+                        match get_digit(key).and_then(DebugView::from_index) {
+                            Some(view) => app.set_debug_view(view),
+                            None => warn!("No debug view {}.", get_digit(key).unwrap()),
+                        }
+                    }
+                    Some(VirtualKeyCode::Key1) => app.set_clear_color(CLEAR_COLORS[0]),
+                    Some(VirtualKeyCode::Key2) => app.set_clear_color(CLEAR_COLORS[1]),
+                    Some(VirtualKeyCode::Key3) => app.set_clear_color(CLEAR_COLORS[2]),
+                    Some(VirtualKeyCode::Key4) => app.set_clear_color(CLEAR_COLORS[3]),
+                    _ => { }
+                }
+            }
+
+            // Une texture PNG déposée sur la fenêtre remplace la première texture
+            Event::WindowEvent { event: WindowEvent::DroppedFile(path), .. } => {
+                if let Err(e) = unsafe { app.stream_texture(&path) } {
+                    warn!("Could not load texture `{}`: {}", path.display(), e);
+                }
+            }
+
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                cursor = position;
+            }
+
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state, button: MouseButton::Right, .. },
+                ..
+            } => {
+                looking = state == ElementState::Pressed;
+            }
+
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. },
+                ..
+            } if !app.resized => {
+                if let Err(e) = unsafe { app.select_model_at(cursor.x as u32, cursor.y as u32) } {
+                    warn!("Could not select a model: {}", e);
                 }
             }
 
-            // Destroy Vulkan app
-            // Les .. permettent d'ignorer le reste des params, permet par exemple
-            // de ne pas avoir à mettre des _ à tout les params non renseignés
-            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
-                destroying = true;
-                *control_flow = ControlFlow::Exit;
-                unsafe { app.logical_device.device_wait_idle().unwrap(); }
-                unsafe { app.destroy(); }
+            // Fin du glisser (si le gestionnaire de fenêtres transmet le relâchement)
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state: ElementState::Released, button: MouseButton::Left, .. },
+                ..
+            } if app.resized => app.finish_resizing(),
+
+            // Le déplacement brut de la souris, qui continue au bord de la fenêtre
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta: (dx, dy) }, .. } if looking => {
+                app.camera.process_mouse(dx, dy);
+            }
+
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Middle, .. },
+                ..
+            } => {
+                match unsafe { app.read_depth_at(cursor.x as u32, cursor.y as u32) } {
+                    Ok(depth) => info!("Depth at ({}, {}): {:.3}", cursor.x as u32, cursor.y as u32, depth),
+                    Err(e) => warn!("Could not read depth: {}", e),
+                }
             }
             _ => {}
         }
@@ -118,74 +458,555 @@ fn main() -> Result<()>{
     });
 }
 
-/// Vulkan App
+/// Initial values of the settings of Vulkan App which can be changed at runtime.
 #[derive(Clone, Debug)]
+struct AppConfig {
+    /// Title of the window, followed by the HDR color space and the frame rate.
+    title: String,
+    /// Initial size of the window, in logical pixels.
+    window_size: LogicalSize<u32>,
+    /// PNG image shown as the icon of the window, `None` for the default one.
+    icon_path: Option<PathBuf>,
+    /// Whether the window can be resized by the user.
+    resizable: bool,
+    /// Model files, Wavefront `.obj` or glTF (`.gltf`/`.glb`) depending on the extension,
+    /// drawn in turn by the models.
+    model_paths: Vec<PathBuf>,
+    /// Whether the model files and the texture are read on a background thread, while a
+    /// progress bar is shown.
+    async_load: bool,
+    /// Texture file, `None` for the one referenced by the model or else the default one.
+    texture_path: Option<PathBuf>,
+    /// Texture blended with the first one, `None` for the first one again.
+    second_texture_path: Option<PathBuf>,
+    /// Weight of the second texture (from 0.0 to 1.0).
+    texture_blend: f32,
+    /// Addressing of the textures outside of [0, 1] (repeat, mirror or clamp).
+    address_mode: vk::SamplerAddressMode,
+    /// Number of models drawn.
+    models: usize,
+    /// Number of models PageUp stops at, at most `MAX_INSTANCES` (the size of the instance
+    /// buffers and of the visibility predicates).
+    max_models: usize,
+    /// Whether the models are drawn with a single instanced draw.
+    instanced: bool,
+    /// Whether each material has its own descriptor sets, bound per model, instead of being
+    /// indexed in the storage buffer of the materials (see `Material`).
+    material_sets: bool,
+    /// Whether particles advanced by a compute shader are drawn along the models.
+    particles: bool,
+    /// Whether the cube map in `resources/skybox` is drawn behind the models.
+    skybox: bool,
+    text_style: TextStyle,
+    /// How long to wait after the last resize event before recreating the swapchain.
+    resize_debounce: Duration,
+    /// Number of swapchain images (3 for triple buffering).
+    image_count: u32,
+    /// Number of frames recorded while the previous ones are rendered, at least 1 (1 waits
+    /// for each frame before recording the next one, for the lowest latency).
+    frames_in_flight: usize,
+    /// Rendering quality, expanded into concrete settings supported by the device.
+    quality: QualityPreset,
+    /// Anisotropy of the texture sampler replacing the one of `quality` (1.0 disables
+    /// anisotropic filtering), clamped to what the device supports.
+    anisotropy: Option<f32>,
+    /// Whether mipmaps are generated for the textures when `quality` uses them (false
+    /// always samples their first level).
+    generate_mipmaps: bool,
+    /// Field of view and clip planes.
+    projection: ProjectionSettings,
+    /// MSAA and anisotropy settings compared by `App::start_quality_sweep`.
+    quality_sweep: SweepRanges,
+    /// Number of threads recording the per-model secondary command buffers.
+    recording_threads: usize,
+    /// Renders only one event loop iteration out of `render_divisor`, to save power on
+    /// static scenes (1 renders every iteration).
+    render_divisor: u32,
+    /// Maximum frame rate, 0 for unlimited.
+    max_frame_rate: u32,
+    /// Stroke of the edges exported by `App::export_wireframe_svg`.
+    wireframe_style: WireframeStyle,
+    /// Axes and handedness of the model file, `None` to assume the usual ones of its
+    /// format (Z-up for OBJ, Y-up for glTF).
+    model_coordinate_system: Option<CoordinateSystem>,
+    /// Which GPU to use when several are available.
+    gpu_preference: GpuPreference,
+    /// Whether to present in an HDR color space if the display supports it.
+    hdr: bool,
+    /// Swapchain format and color space to use if the surface supports them (and HDR isn't
+    /// used), `None` for sRGB. A UNORM format is encoded to sRGB by the shaders.
+    surface_format: Option<(vk::Format, vk::ColorSpaceKHR)>,
+    /// Whether the frames have a depth buffer (false for 2D or overlay rendering, where
+    /// the draw order is enough).
+    use_depth: bool,
+    /// Whether the models are tested against the depth buffer.
+    depth_test: bool,
+    /// Whether the models write to the depth buffer.
+    depth_write: bool,
+    /// Whether the models are blended with their opacity.
+    blending: bool,
+    /// Whether to keep the depth and stencil aspects in separate layouts (Vulkan 1.2),
+    /// if the device supports it.
+    separate_depth_stencil_layouts: bool,
+    /// Subgroup size required for the compute shaders (`None` for the driver's choice),
+    /// clamped to the sizes supported by the device.
+    subgroup_size: Option<u32>,
+    /// Whether to skip the draws of the culled models with conditional rendering, if the
+    /// device supports it (they are culled on the CPU otherwise).
+    conditional_rendering: bool,
+    /// Whether to render with dynamic rendering (Vulkan 1.3) instead of a render pass,
+    /// if the device supports it.
+    dynamic_rendering: bool,
+    /// Directory the SPIR-V shaders are read from.
+    shader_directory: PathBuf,
+    /// Frames to render without surface (nor visible window) to a file, instead of
+    /// running the event loop.
+    headless: Option<HeadlessOutput>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            title: WINDOW_TITLE.to_string(),
+            window_size: LogicalSize::new(1024, 768),
+            icon_path: None,
+            resizable: true,
+            model_paths: vec![PathBuf::from(MODEL_PATH)],
+            async_load: false,
+            texture_path: None,
+            second_texture_path: None,
+            texture_blend: 0.5,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            models: 1,
+            max_models: 16,
+            instanced: false,
+            material_sets: false,
+            particles: false,
+            skybox: false,
+            text_style: TextStyle::default(),
+            resize_debounce: Duration::from_millis(100),
+            image_count: 3,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            quality: QualityPreset::Ultra,
+            anisotropy: None,
+            generate_mipmaps: true,
+            projection: ProjectionSettings::default(),
+            quality_sweep: SweepRanges::default(),
+            recording_threads: 1,
+            render_divisor: 1,
+            max_frame_rate: 0,
+            wireframe_style: WireframeStyle::default(),
+            model_coordinate_system: None,
+            gpu_preference: GpuPreference::PreferDiscrete,
+            hdr: false,
+            surface_format: None,
+            use_depth: true,
+            depth_test: true,
+            depth_write: true,
+            blending: true,
+            separate_depth_stencil_layouts: false,
+            subgroup_size: None,
+            conditional_rendering: false,
+            dynamic_rendering: false,
+            shader_directory: PathBuf::from(SHADER_DIRECTORY),
+            headless: None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// The builder of the window, with the title, size, icon and resizability of the settings.
+    fn get_window_builder(&self) -> Result<WindowBuilder> {
+        let icon = match &self.icon_path {
+            Some(path) => {
+                let (pixels, width, height) = read_png_texture(path)?;
+                let icon = Icon::from_rgba(pixels, width, height)
+                    .map_err(|e| anyhow!("Invalid icon `{}` ({}).", path.display(), e))?;
+                Some(icon)
+            }
+            None => None,
+        };
+
+        Ok(WindowBuilder::new()
+            .with_title(&self.title)
+            .with_inner_size(self.window_size)
+            .with_resizable(self.resizable)
+            .with_window_icon(icon))
+    }
+
+    /// `max_models`, between 1 and `MAX_INSTANCES`.
+    fn get_max_models(&self) -> usize {
+        self.max_models.clamp(1, MAX_INSTANCES)
+    }
+
+    /// `models`, between 1 and `get_max_models`.
+    fn get_models(&self) -> usize {
+        self.models.clamp(1, self.get_max_models())
+    }
+
+    /// The default settings with the model files and the (PNG or KTX2) texture files given as
+    /// (optional) command line arguments, after `--headless <frames> <image.png>` for the headless mode,
+    /// `--async-load` to load them in the background and `--material-sets` for a descriptor set
+    /// per material.
+    fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.peekable();
+
+        if args.next_if(|a| a == "--headless").is_some() {
+            let (Some(frame_count), Some(path)) = (args.next(), args.next()) else {
+                return Err(anyhow!("Usage: --headless <frames> <image.png>."));
+            };
+
+            config.headless = Some(HeadlessOutput {
+                path: PathBuf::from(path),
+                frame_count: frame_count
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid frame count `{}`.", frame_count))?,
+            });
+        }
+
+        config.async_load = args.next_if(|a| a == "--async-load").is_some();
+        config.material_sets = args.next_if(|a| a == "--material-sets").is_some();
+
+        // Les PNG et KTX2 sont la texture, le reste des modèles
+        let (textures, models): (Vec<_>, Vec<_>) = args
+            .map(PathBuf::from)
+            .partition(|p| is_ktx2(p) || p.extension().is_some_and(|e| e.eq_ignore_ascii_case("png")));
+
+        if !models.is_empty() {
+            config.model_paths = models;
+        }
+
+        let mut textures = textures.into_iter();
+        config.texture_path = textures.next();
+        config.second_texture_path = textures.next();
+
+        for path in config.model_paths.iter().chain(&config.texture_path).chain(&config.second_texture_path) {
+            if !path.is_file() {
+                return Err(anyhow!("File `{}` not found.", path.display()));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Vulkan App, destroyed when dropped.
+#[derive(Debug)]
 struct App {
     entry: Entry,
     instance: Instance,
     data: AppData,
     logical_device: Device,
+    config: AppConfig,
     frame: usize,
+    /// Frames presented since the app was created, shown in the text overlay.
+    frame_count: u64,
     resized: bool,
-    start: Instant,
+    /// When the last resize event was received.
+    last_resize: Instant,
+    /// How long to wait after the last resize event before recreating the swapchain
+    /// (zero to recreate it immediately).
+    resize_debounce: Duration,
+    /// Seconds of rotation of the models, which only advance while the animation isn't paused.
+    animation_time: f32,
+    animation_paused: bool,
+    /// Factor applied to the elapsed time before adding it to `animation_time`.
+    animation_speed: f32,
+    /// What `animation_time` advanced by for the current frame, which the particles advance by.
+    animation_step: f32,
+    /// Angle of the directional light around the Z axis, in degrees.
+    light_azimuth: f32,
     models: usize,
+    monitor: usize,
+    text_style: TextStyle,
+    instanced: bool,
+    /// Whether the particles are simulated and drawn (see `compute`).
+    particles: bool,
+    /// Whether the skybox is drawn (see `skybox`).
+    skybox: bool,
+    quality: QualityPreset,
+    recording_threads: usize,
+    render_divisor: u32,
+    /// Event loop iterations since the last rendered frame.
+    skipped_renders: u32,
+    benchmark: Option<Benchmark>,
+    /// Values of `models`, `instanced` and `recording_threads` to restore once the
+    /// benchmark is over.
+    benchmark_saved: (usize, bool, usize),
+    /// File to capture the next frame to.
+    screenshot: Option<PathBuf>,
+    /// Frames being captured by `start_recording`.
+    frame_sequence: Option<FrameSequence>,
+    quality_sweep: Option<QualitySweep>,
+    latency: LatencyMeter,
+    frame_rate: FrameRateCounter,
+    /// Waits after each frame to cap the frame rate.
+    frame_limiter: FrameLimiter,
+    /// Whether the frame rate is shown in the window title.
+    show_frame_rate: bool,
+    /// When the window title was last updated.
+    title_updated: Instant,
+    camera: Camera,
+    projection: ProjectionSettings,
+    /// Keys currently held down, moving the camera.
+    held_keys: HashSet<VirtualKeyCode>,
+    /// When the camera was last moved.
+    last_update: Instant,
+    /// Background color the frames are cleared to (RGBA, linear).
+    clear_color: [f32; 4],
+    /// Placement of each model (before its rotation), the grid of `get_grid_transform`
+    /// being used past the end.
+    model_transforms: Vec<glm::Mat4>,
+    /// Model selected by clicking it or with Tab, moved by `translate_selected_model` and
+    /// tinted by the fragment shader.
+    selected_model: Option<usize>,
+    /// Whether the Vulkan objects have already been destroyed.
+    destroyed: bool,
 }
 
 impl App {
     /// Creates Vulkan app
-    unsafe fn create(window: &Window) -> Result<Self> {
+    unsafe fn create(window: &Window) -> Result<Self, TutorialError> {
+        Self::create_with_progress(window, &|_, _| {})
+    }
+
+    /// Creates Vulkan app, calling `progress` with a label and the fraction completed
+    /// (from 0 to 1) before each major stage.
+    unsafe fn create_with_progress(
+        window: &Window,
+        progress: &dyn Fn(&str, f32),
+    ) -> Result<Self, TutorialError> {
+        Self::create_with_config(window, AppConfig::default(), progress)
+    }
+
+    /// Creates Vulkan app with the settings of `config`.
+    unsafe fn create_with_config(
+        window: &Window,
+        config: AppConfig,
+        progress: &dyn Fn(&str, f32),
+    ) -> Result<Self, TutorialError> {
+        progress("instance", 0.0);
+
+        if config.frames_in_flight == 0 {
+            return Err(anyhow!("At least one frame in flight is needed.").into());
+        }
+
+        // Les fichiers sont lus pendant la création du device et de la swapchain
+        let asset_loader = config.async_load.then(|| {
+            Loader::spawn(
+                config.model_paths.clone(),
+                config.model_coordinate_system,
+                config.texture_path.clone(),
+            )
+        });
 
-        let loader = LibloadingLoader::new(LIBRARY)?;
+        let loader = LibloadingLoader::new(LIBRARY).map_err(|e| anyhow!(e))?;
         let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
-        let mut data = AppData::default();
+        let mut data = AppData {
+            dynamic_rendering: config.dynamic_rendering,
+            hdr: config.hdr,
+            preferred_surface_format: config.surface_format,
+            separate_depth_stencil_layouts: config.separate_depth_stencil_layouts,
+            subgroup_size: config.subgroup_size,
+            conditional_rendering: config.conditional_rendering,
+            use_depth: config.use_depth,
+            depth_test: config.depth_test,
+            depth_write: config.depth_write,
+            blending: config.blending,
+            texture_blend: config.texture_blend,
+            address_mode: config.address_mode,
+            desired_image_count: config.image_count,
+            frames_in_flight: config.frames_in_flight,
+            material_sets: config.material_sets,
+            window_title: config.title.clone(),
+            headless: config.headless.is_some(),
+            shader_directory: config.shader_directory.clone(),
+            ..Default::default()
+        };
 
         let instance = create_instance(window, &entry, &mut data)?;
 
-        data.surface = vk_window::create_surface(&instance, window)?;
+        if !data.headless {
+            data.surface = vk_window::create_surface(&instance, window)?;
+        }
 
-        pick_physical_device(&instance, &mut data)?;
+        progress("device", 0.1);
+        pick_physical_device(&instance, &mut data, config.gpu_preference)?;
+        apply_quality_preset(&instance, &mut data, config.quality)?;
+        data.mipmaps &= config.generate_mipmaps;
+        if let Some(anisotropy) = config.anisotropy {
+            data.max_anisotropy = get_max_anisotropy(&data, anisotropy);
+        }
         let logical_device = create_logical_device(&instance, &mut data)?;
 
+        progress("swapchain", 0.2);
         create_swapchain(window, &instance, &logical_device, &mut data)?;
         create_swapchain_image_views(&logical_device, &mut data)?;
 
         create_render_pass(&instance, &logical_device, &mut data)?;
 
+        progress("pipeline", 0.3);
         create_descriptor_set_layout(&logical_device, &mut data)?;
         create_pipeline(&logical_device, &mut data)?;
         create_command_pools(&instance, &logical_device, &mut data)?;
+        create_transfer_objects(&instance, &logical_device, &mut data)?;
+        create_recording_objects(&instance, &logical_device, &mut data, get_max_recording_threads())?;
+        create_timestamp_query_pool(&instance, &logical_device, &mut data)?;
 
         create_color_objects(&instance, &logical_device, &mut data)?;
         create_depth_objects(&instance, &logical_device, &mut data)?;
+        create_picking_objects(&instance, &logical_device, &mut data)?;
         create_framebuffers(&logical_device, &mut data)?;
 
-        create_texture_image(&instance, &logical_device, &mut data)?;
+        // Avant le chargement, pour afficher sa progression
+        create_command_buffers(&logical_device, &mut data)?;
+        create_sync_objects(&logical_device, &mut data)?;
+
+        // Le modèle peut référencer sa propre texture
+        progress("model", 0.4);
+        let pixels = if let Some(asset_loader) = asset_loader {
+            let assets = wait_for_assets(&logical_device, &mut data, asset_loader, CLEAR_COLORS[0], &|stage, fraction| {
+                progress(stage, 0.4 + 0.4 * fraction)
+            })?;
+            data.meshes = assets.meshes;
+            data.model_texture = assets.model_texture;
+            data.texture_path = assets.texture_path;
+            assets.texture
+        } else {
+            load_models(&mut data, &config.model_paths, config.model_coordinate_system)?;
+            data.texture_path = get_texture_path(config.texture_path.as_ref(), data.model_texture.as_ref());
+            None
+        };
+        compute_bounding_spheres(&mut data);
+
+        progress("texture", 0.6);
+        match pixels {
+            Some((pixels, width, height)) => create_texture_image_from_pixels(
+                &instance,
+                &logical_device,
+                &mut data,
+                TextureEncoding::Srgb,
+                &pixels,
+                width,
+                height,
+            )?,
+            None => create_texture_image(&instance, &logical_device, &mut data, TextureEncoding::Srgb)?,
+        }
         create_texture_image_view(&logical_device, &mut data)?;
+        data.second_texture.path = config.second_texture_path
+            .clone()
+            .unwrap_or_else(|| data.texture_path.clone());
+        create_second_texture(&instance, &logical_device, &mut data)?;
         create_texture_sampler(&logical_device, &mut data)?;
+        create_text_objects(&instance, &logical_device, &mut data)?;
 
-        load_models(&mut data)?;
-        create_vertex_buffer(&instance, &logical_device, &mut data)?;
-        create_index_buffer(&instance, &logical_device, &mut data)?;
+        progress("buffers", 0.8);
+        create_mesh_buffers(&instance, &logical_device, &mut data)?;
+        create_material_buffer(&instance, &logical_device, &mut data)?;
 
         create_uniform_buffers(&instance, &logical_device, &mut data)?;
+        create_instance_buffers(&instance, &logical_device, &mut data)?;
+        create_predicate_buffers(&instance, &logical_device, &mut data)?;
         create_descriptor_pool(&logical_device, &mut data)?;
         create_descriptor_sets(&logical_device, &mut data)?;
+        create_text_swapchain_objects(&instance, &logical_device, &mut data)?;
 
-        create_command_buffers(&logical_device, &mut data)?;
+        let monitor = get_current_monitor_index(window);
+        progress("done", 1.0);
 
-        create_sync_objects(&logical_device, &mut data)?;
+        let mut app = Self {
+            entry,
+            instance,
+            data,
+            logical_device,
+            frame: 0,
+            frame_count: 0,
+            resized: false,
+            last_resize: Instant::now(),
+            resize_debounce: config.resize_debounce,
+            animation_time: 0.0,
+            animation_paused: false,
+            animation_speed: 1.0,
+            animation_step: 0.0,
+            light_azimuth: 0.0,
+            models: config.get_models(),
+            monitor,
+            text_style: config.text_style,
+            instanced: config.instanced,
+            particles: false,
+            skybox: false,
+            quality: config.quality,
+            recording_threads: config.recording_threads,
+            render_divisor: config.render_divisor.max(1),
+            skipped_renders: 0,
+            benchmark: None,
+            benchmark_saved: (config.models, config.instanced, config.recording_threads),
+            screenshot: None,
+            frame_sequence: None,
+            quality_sweep: None,
+            latency: LatencyMeter::default(),
+            frame_rate: FrameRateCounter::default(),
+            frame_limiter: FrameLimiter::new(config.max_frame_rate),
+            show_frame_rate: true,
+            title_updated: Instant::now(),
+            camera: get_initial_camera(),
+            projection: config.projection,
+            held_keys: HashSet::new(),
+            last_update: Instant::now(),
+            clear_color: CLEAR_COLORS[0],
+            model_transforms: get_initial_model_transforms(),
+            selected_model: None,
+            destroyed: false,
+            config,
+        };
 
-        Ok(Self { entry, instance, data, logical_device, frame: 0, resized: false, start: Instant::now(), models: 1})
+        if app.config.particles {
+            app.set_particles(true)?;
+        }
+        if app.config.skybox {
+            app.set_skybox(true)?;
+        }
+
+        log_memory_usage(&app.instance, &app.data)?;
+
+        Ok(app)
     }
 
     /// Renders a frame for Vulkan app
-    unsafe fn render(&mut self, window: &Window) -> Result<()> {
+    unsafe fn render(&mut self, window: &Window) -> Result<(), TutorialError> {
+        if let Some(benchmark) = &self.benchmark {
+            self.models = benchmark.models;
+            self.instanced = benchmark.path() == RenderPath::Instanced;
+            self.recording_threads = match benchmark.path() {
+                RenderPath::MultiThreaded => get_max_recording_threads(),
+                _ => 1,
+            };
+        }
+
+        // Déplacement proportionnel au temps écoulé, indépendant du framerate
+        let dt = self.last_update.elapsed().as_secs_f32();
+        self.last_update = Instant::now();
+        for key in &self.held_keys {
+            self.camera.process_keyboard(*key, dt);
+        }
+
+        // Une seule horloge pour l'UBO et les push constants des modèles
+        self.animation_step = if self.animation_paused { 0.0 } else { dt * self.animation_speed };
+        self.animation_time += self.animation_step;
+
         self.logical_device.wait_for_fences(
             &[self.data.in_flight_fences[self.frame]],
             true,
             u64::MAX,
         )?;
 
+        // La dernière frame soumise avec ces queries est finie
+        read_gpu_timing(&self.logical_device, &mut self.data, self.frame)?;
+
+        // Et son descriptor set peut être réécrit
+        update_texture_stream(&self.logical_device, &mut self.data, self.frame)?;
+
         let result = self
             .logical_device
             .acquire_next_image_khr(
@@ -197,24 +1018,34 @@ impl App {
             );
 
         //Récupération de l'index d'une image disponnible
-        let image_index = match result {
-            Ok((image_index, _)) => image_index as usize,
-            Err(vk::ErrorCode::OUT_OF_DATE_KHR) => return self.recreate_swapchain(window),
-            Err(e) => return Err(anyhow!(e)),
+        // La fence n'a pas encore été reset: elle reste signalée si on s'arrête ici
+        // Une image SUBOPTIMAL est quand même rendue et présentée, la swapchain n'est recréée
+        // qu'après la présentation (une fois la frame soumise, recreate_swapchain attend le device)
+        let (image_index, acquire_suboptimal) = match result {
+            Ok((image_index, code)) => (image_index as usize, code == vk::SuccessCode::SUBOPTIMAL_KHR),
+            Err(vk::ErrorCode::OUT_OF_DATE_KHR) => return Ok(self.recreate_swapchain(window)?),
+            Err(e) => return Err(e.into()),
         };
 
-        if !self.data.images_in_flight[image_index as usize].is_null() {
+        self.latency.acquired(image_index);
+
+        if !self.data.images_in_flight[image_index].is_null() {
             self.logical_device.wait_for_fences(
-                &[self.data.images_in_flight[image_index as usize]],
+                &[self.data.images_in_flight[image_index]],
                 true,
                 u64::MAX,
             )?;
         }
 
-        self.data.images_in_flight[image_index as usize] = self.data.in_flight_fences[self.frame];
+        self.data.images_in_flight[image_index] = self.data.in_flight_fences[self.frame];
+
+        // La dernière frame de cette image est finie, sa capture peut être lue
+        save_capture(&self.logical_device, &mut self.data, image_index)?;
 
+        let recording_start = Instant::now();
         self.update_command_buffer(image_index)?;
-        self.update_uniform_buffer(image_index)?;
+        let recording = recording_start.elapsed();
+        self.update_uniform_buffer(self.frame)?;
 
         //Spécifique quelle sémaphore il faut attendre avant que l'execution ne commence
         let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
@@ -231,6 +1062,8 @@ impl App {
             .command_buffers(command_buffers)
             .signal_semaphores(signal_semaphores);
 
+        // Reset juste avant la soumission: aucun retour anticipé ne peut laisser la fence
+        // non signalée sans travail soumis pour la signaler, sinon la prochaine attente bloque
         self.logical_device.reset_fences(&[self.data.in_flight_fences[self.frame]])?;
 
         self.logical_device.queue_submit(
@@ -250,259 +1083,1549 @@ impl App {
         let result= self.logical_device.queue_present_khr(
             self.data.prensentation_queue, &presentation_info
         );
-        let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
-            || result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+        self.latency.presented(image_index);
+        // Une swapchain OUT_OF_DATE n'est plus utilisable, mais une swapchain SUBOPTIMAL
+        // peut continuer à servir le temps que l'utilisateur finisse de redimensionner
+        let out_of_date = result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+        let changed = self.resized || acquire_suboptimal || result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR);
 
-        if self.resized || changed {
+        if out_of_date || (changed && !self.is_resizing()) {
             self.recreate_swapchain(window)?;
         } else if let Err(e) = result{
-            return Err(anyhow!(e));
+            return Err(e.into());
+        }
+
+        self.frame = (self.frame + 1) % self.data.frames_in_flight;
+        self.frame_count += 1;
+
+        // Après la présentation, l'attente n'ajoute pas de latence à la frame présentée
+        self.frame_limiter.wait();
+        self.frame_rate.frame();
+        self.update_window_title(window);
+
+        if let Some(benchmark) = &mut self.benchmark {
+            if benchmark.frame(recording) {
+                println!("{}", benchmark.report());
+                (self.models, self.instanced, self.recording_threads) = self.benchmark_saved;
+                self.benchmark = None;
+            }
         }
 
-        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        if let Some(sweep) = &mut self.quality_sweep {
+            match sweep.frame(self.data.msaa_samples, self.data.max_anisotropy) {
+                SweepAction::None => {}
+                SweepAction::Apply(samples, anisotropy) => self.set_sampling(window, samples, anisotropy)?,
+                SweepAction::Screenshot(path) => self.take_screenshot(&path),
+                SweepAction::Finished => {
+                    info!("Quality sweep written to `{}`.", sweep.write_summary()?.display());
+                    self.quality_sweep = None;
+                    self.set_quality_preset(window, self.quality)?;
+                }
+            }
+        }
 
         Ok(())
     }
 
-    unsafe fn update_uniform_buffer(
-        &self,
-        image_index: usize
-    ) -> Result<()> {
-        let time = self.start.elapsed().as_secs_f32();
+    /// Restores every runtime setting to its value from `config` (or its default for the
+    /// ones changed only from the keyboard), and the scene to its initial state.
+    unsafe fn reset(&mut self, window: &Window) -> Result<()> {
+        self.benchmark = None;
+        self.reconfigure(window, &self.config.clone())?;
+
+        // Réglages changés au clavier, qui ne sont pas dans la configuration
+        if self.data.polygon_mode != vk::PolygonMode::FILL || self.data.depth_prepass {
+            self.data.polygon_mode = vk::PolygonMode::FILL;
+            self.data.depth_prepass = false;
+            self.recreate_pipeline()?;
+        }
+        self.clear_color = CLEAR_COLORS[0];
+        self.animation_paused = false;
+        self.animation_speed = 1.0;
+        self.light_azimuth = 0.0;
+        self.show_frame_rate = true;
+        self.data.debug_view = DebugView::default();
+        self.reset_scene();
 
-        let model = glm::rotate(
-            &glm::identity(),
-            time * glm::radians(&glm::vec1(90.0))[0],
-            &glm::vec3(0.0, 0.0, 1.0),
-        );
+        info!("Settings reset to their defaults.");
 
-        let view = glm::look_at(
-            &glm::vec3(6.0, 2.0, 2.0),
-            &glm::vec3(0.0, 0.0, 0.0),
-            &glm::vec3(0.0, 0.0, 1.0),
-        );
+        Ok(())
+    }
 
-        let mut proj = glm::perspective_rh_zo(
-            self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32,
-            glm::radians(&glm::vec1(45.0))[0],
-            0.1,
-            10.0,
-        );
+    /// Switches to the settings of `config`, recreating only the resources affected by
+    /// the ones which changed.
+    ///
+    /// The settings chosen when creating the instance and the device (GPU, HDR, Vulkan
+    /// features) rebuild the whole renderer, keeping the window. If that fails, the app
+    /// can't be used anymore.
+    unsafe fn reconfigure(&mut self, window: &Window, config: &AppConfig) -> Result<()> {
+        self.logical_device.device_wait_idle()?;
 
-        proj[(1,1)] *= -1.0;
+        let rebuild = config.gpu_preference != self.config.gpu_preference
+            || config.hdr != self.config.hdr
+            || config.separate_depth_stencil_layouts != self.config.separate_depth_stencil_layouts
+            || config.subgroup_size != self.config.subgroup_size
+            || config.model_coordinate_system != self.config.model_coordinate_system
+            || config.model_paths != self.config.model_paths
+            || config.texture_path != self.config.texture_path
+            || config.second_texture_path != self.config.second_texture_path
+            || config.shader_directory != self.config.shader_directory
+            || config.conditional_rendering != self.config.conditional_rendering
+            || config.frames_in_flight != self.config.frames_in_flight
+            || config.material_sets != self.config.material_sets
+            || config.dynamic_rendering != self.config.dynamic_rendering;
+
+        if rebuild {
+            // La surface ne peut avoir qu'une swapchain: l'ancien renderer est détruit d'abord
+            self.destroy();
+            *self = Self::create_with_config(window, config.clone(), &|_, _| {})?;
+            info!("Renderer rebuilt with the new settings.");
+            return Ok(());
+        }
 
-        let ubo = UniformBufferObject { view, proj };
+        // Ces réglages sont relus à chaque frame, rien à recréer
+        self.models = config.get_models();
+        self.instanced = config.instanced;
+        self.recording_threads = config.recording_threads;
+        self.render_divisor = config.render_divisor.max(1);
+        self.frame_limiter.set_max_fps(config.max_frame_rate);
+        self.projection = config.projection;
+        self.text_style = config.text_style;
+        self.resize_debounce = config.resize_debounce;
+        self.data.texture_blend = config.texture_blend;
+
+        self.data.window_title = config.title.clone();
+        window.set_title(&get_window_title(&self.data));
+        window.set_resizable(config.resizable);
+
+        if config.particles != self.particles {
+            self.set_particles(config.particles)?;
+        }
+        if config.skybox != self.skybox {
+            self.set_skybox(config.skybox)?;
+        }
 
-        let memory = self.logical_device.map_memory(
-            self.data.uniform_buffers_memory[image_index],
-            0,
-            size_of::<UniformBufferObject>() as u64,
-            vk::MemoryMapFlags::empty(),
-        )?;
+        // MSAA, mipmaps et mode de présentation, comparés à l'état courant: X et V les
+        // changent sans changer de preset
+        let (samples, anisotropy, mipmaps, presentation_mode) = config.quality.settings();
+        let support = SwapchainSupport::get(&self.instance, &self.data, self.data.physical_device)?;
+        let quality_changed = self.quality != config.quality
+            || self.data.msaa_samples != get_max_msaa_samples(&self.data, samples)
+            || self.data.mipmaps != (mipmaps && config.generate_mipmaps)
+            || self.data.presentation_mode != get_swapchain_presentation_mode(&support.presentation_modes, presentation_mode);
+        self.config.generate_mipmaps = config.generate_mipmaps;
+        if quality_changed {
+            self.set_quality_preset(window, config.quality)?;
+        }
 
-        memcpy(&ubo, memory.cast(), 1);
+        // Le preset a remis son anisotropie
+        let anisotropy = config.anisotropy.unwrap_or(anisotropy);
+        if get_max_anisotropy(&self.data, anisotropy) != self.data.max_anisotropy {
+            self.set_anisotropy(anisotropy)?;
+        }
 
-        self.logical_device.unmap_memory(self.data.uniform_buffers_memory[image_index]);
+        if (config.depth_test, config.depth_write) != (self.data.depth_test, self.data.depth_write) {
+            self.set_depth_state(config.depth_test, config.depth_write)?;
+        }
 
-        Ok(())
-    }
+        if config.blending != self.data.blending {
+            self.set_blending(config.blending)?;
+        }
 
-    unsafe fn update_command_buffer(&mut self, image_index: usize) -> Result<()> {
-        let command_pool = self.data.command_pools[image_index];
-        self.logical_device.reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+        if config.address_mode != self.data.address_mode {
+            self.set_address_mode(config.address_mode)?;
+        }
 
-        let command_buffer = self.data.command_buffers[image_index];
+        if config.use_depth != self.data.use_depth {
+            self.set_use_depth(window, config.use_depth)?;
+        }
 
-        let info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        if config.surface_format != self.data.preferred_surface_format {
+            self.data.preferred_surface_format = config.surface_format;
+            self.recreate_swapchain(window)?;
+        }
 
-        self.logical_device.begin_command_buffer(command_buffer, &info)?;
+        if config.image_count != self.data.desired_image_count {
+            self.data.desired_image_count = config.image_count;
+            self.recreate_swapchain(window)?;
+        }
 
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(self.data.swapchain_extent);
+        self.config = config.clone();
 
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
-            },
-        };
+        self.logical_device.device_wait_idle()?;
 
-        let depth_clear_value = vk::ClearValue {
-            depth_stencil: vk::ClearDepthStencilValue {
-                depth: 1.0,
-                stencil: 0,
-            },
-        };
+        Ok(())
+    }
 
-        let clear_values = &[color_clear_value, depth_clear_value];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.data.render_pass)
-            .framebuffer(self.data.framebuffers[image_index])
-            .render_area(render_area)
-            .clear_values(clear_values);
+    /// Recreates the whole renderer (device, swapchain and every resource) after the device
+    /// was lost, keeping the window, the settings of `config` and the scene.
+    ///
+    /// If it fails, the old renderer is already destroyed and the app can't be used anymore.
+    unsafe fn recreate_device(&mut self, window: &Window) -> Result<()> {
+        // Les objets d'un device perdu peuvent toujours être détruits
+        self.destroy();
 
-        self.logical_device.cmd_begin_render_pass(
-            command_buffer,
-            &info,
-            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
-        );
+        let app = Self::create_with_config(window, self.config.clone(), &|_, _| {})?;
+        let (camera, model_transforms, animation_time) =
+            (self.camera, self.model_transforms.clone(), self.animation_time);
 
-        let secondary_command_buffer = (0..self.models)
-            .map(|i| self.update_secondary_command_buffer(image_index, i))
-            .collect::<Result<Vec<_>, _>>()?;
-        self.logical_device.cmd_execute_commands(command_buffer, &secondary_command_buffer[..]);
+        *self = app;
+        self.camera = camera;
+        self.model_transforms = model_transforms;
+        self.animation_time = animation_time;
 
-        self.logical_device.cmd_end_render_pass(command_buffer);
-        self.logical_device.end_command_buffer(command_buffer)?;
+        info!("Renderer recreated after the device was lost.");
 
         Ok(())
     }
 
-    unsafe fn update_secondary_command_buffer(
-        &mut self,
-        image_index: usize,
-        model_index: usize,
-    ) -> Result<vk::CommandBuffer> {
-        self.data.secondary_command_buffers.resize_with(image_index + 1, Vec::new);
+    /// Switches to another quality preset, recreating the texture (mipmaps and
+    /// anisotropy) and the swapchain (MSAA and presentation mode).
+    unsafe fn set_quality_preset(&mut self, window: &Window, preset: QualityPreset) -> Result<()> {
+        self.logical_device.device_wait_idle()?;
 
-        let command_buffers = &mut self.data.secondary_command_buffers[image_index];
+        self.logical_device.destroy_sampler(self.data.texture_sampler, None);
+        self.logical_device.destroy_image_view(self.data.texture_image_view, None);
+        self.logical_device.destroy_image(self.data.texture_image, None);
+        self.data.allocator.free_image(&self.logical_device, self.data.texture_image_memory);
+        destroy_second_texture(&self.logical_device, &mut self.data);
 
-        while model_index >= command_buffers.len() {
-            let allocate_info = vk::CommandBufferAllocateInfo::builder()
-                .command_pool(self.data.command_pools[image_index])
-                .level(vk::CommandBufferLevel::SECONDARY)
-                .command_buffer_count(1);
+        apply_quality_preset(&self.instance, &mut self.data, preset)?;
+        self.data.mipmaps &= self.config.generate_mipmaps;
+        self.quality = preset;
 
-            let command_buffer = self.logical_device.allocate_command_buffers(&allocate_info)?[0];
-            command_buffers.push(command_buffer);
-        }
+        create_texture_image(&self.instance, &self.logical_device, &mut self.data, TextureEncoding::Srgb)?;
+        create_texture_image_view(&self.logical_device, &mut self.data)?;
+        create_second_texture(&self.instance, &self.logical_device, &mut self.data)?;
+        create_texture_sampler(&self.logical_device, &mut self.data)?;
 
-        let command_buffer = command_buffers[model_index];
+        // Les descriptor sets référencent la texture, ils sont recréés avec la swapchain
+        self.recreate_swapchain(window)
+    }
 
-        let y = (((model_index % 2) as f32) * 2.5) - 1.25;
-        let z = (((model_index / 2) as f32) * -2.0) + 1.0;
+    /// Toggles VSync, switching between the FIFO presentation mode and MAILBOX (or else
+    /// IMMEDIATE), which also compares their latency.
+    unsafe fn toggle_presentation_mode(&mut self, window: &Window) -> Result<()> {
+        let previous = self.data.presentation_mode;
+        let latency = self.latency.average();
 
-        let model = glm::translate(
-            &glm::identity(),
-            &glm::vec3(0.0, y, z),
-        );
+        let support = SwapchainSupport::get(&self.instance, &self.data, self.data.physical_device)?;
 
-        let time = self.start.elapsed().as_secs_f32();
+        // VSync désactivée: MAILBOX, ou IMMEDIATE (avec tearing) si elle n'est pas disponible
+        let requested = if previous == vk::PresentModeKHR::FIFO {
+            [vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE]
+                .into_iter()
+                .find(|m| support.presentation_modes.contains(m))
+                .unwrap_or(vk::PresentModeKHR::FIFO)
+        } else {
+            vk::PresentModeKHR::FIFO
+        };
 
-        let model = glm::rotate(
-            &model,
-            time * glm::radians(&glm::vec1(90.0))[0],
-            &glm::vec3(0.0, 0.0, 1.0)
-        );
+        self.data.presentation_mode = get_swapchain_presentation_mode(&support.presentation_modes, requested);
 
-        let (_, model_bytes, _) = model.as_slice().align_to::<u8>();
+        match latency {
+            Some(latency) => info!(
+                "Presentation mode {:?} -> {:?} (present-to-acquire latency was {:.2} ms).",
+                previous,
+                self.data.presentation_mode,
+                latency.as_secs_f64() * 1000.0,
+            ),
+            None => info!("Presentation mode {:?} -> {:?}.", previous, self.data.presentation_mode),
+        }
 
-        let opacity = (model_index + 1) as f32 * 0.25;
-        let opacity_bytes = &opacity.to_ne_bytes()[..];
+        self.recreate_swapchain(window)
+    }
 
-        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
-            .render_pass(self.data.render_pass)
-            .subpass(0)
-            .framebuffer(self.data.framebuffers[image_index]);
-        let info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
-            .inheritance_info(&inheritance_info);
+    /// Changes the MSAA samples and the anisotropy (clamped to what the device supports)
+    /// independently of the quality preset, recreating the sampler and the swapchain.
+    unsafe fn set_sampling(
+        &mut self,
+        window: &Window,
+        samples: vk::SampleCountFlags,
+        anisotropy: f32,
+    ) -> Result<()> {
+        self.logical_device.device_wait_idle()?;
 
-        self.logical_device.begin_command_buffer(command_buffer, &info)?;
+        self.data.requested_msaa_samples = samples;
+        self.data.msaa_samples = get_max_msaa_samples(&self.data, samples);
+        self.data.max_anisotropy = get_max_anisotropy(&self.data, anisotropy);
 
-        self.logical_device.cmd_bind_pipeline(
-            command_buffer, vk::PipelineBindPoint::GRAPHICS, self.data.pipeline
-        );
+        self.logical_device.destroy_sampler(self.data.texture_sampler, None);
+        create_texture_sampler(&self.logical_device, &mut self.data)?;
 
-        self.logical_device.cmd_bind_vertex_buffers(
-            command_buffer,
-            0,
-            &[self.data.vertex_buffer],
-            &[0]
-        );
-        self.logical_device.cmd_bind_index_buffer(
-            command_buffer,
-            self.data.index_buffer,
-            0,
-            vk::IndexType::UINT32
-        );
-        self.logical_device.cmd_bind_descriptor_sets(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            self.data.pipeline_layout,
-            0,
-            &[self.data.descriptor_sets[image_index]],
-            &[],
-        );
+        info!("{:?} MSAA samples, {}x anisotropy.", self.data.msaa_samples, self.data.max_anisotropy);
 
-        //Pour matrice model
-        self.logical_device.cmd_push_constants(
-            command_buffer,
-            self.data.pipeline_layout,
-            vk::ShaderStageFlags::VERTEX,
-            0,
-            model_bytes,
-        );
+        self.recreate_swapchain(window)
+    }
 
-        //Pour opacity
-        self.logical_device.cmd_push_constants(
+    /// Changes the anisotropy of the texture sampler (clamped to what the device supports,
+    /// 1.0 disabling anisotropic filtering), recreating the sampler and the descriptor sets
+    /// referencing it.
+    unsafe fn set_anisotropy(&mut self, anisotropy: f32) -> Result<()> {
+        self.logical_device.device_wait_idle()?;
+
+        self.data.max_anisotropy = get_max_anisotropy(&self.data, anisotropy);
+
+        self.logical_device.destroy_sampler(self.data.texture_sampler, None);
+        create_texture_sampler(&self.logical_device, &mut self.data)?;
+
+        self.logical_device.destroy_descriptor_pool(self.data.descriptor_pool, None);
+        create_descriptor_pool(&self.logical_device, &mut self.data)?;
+        create_descriptor_sets(&self.logical_device, &mut self.data)?;
+
+        info!("{}x anisotropy.", self.data.max_anisotropy);
+
+        Ok(())
+    }
+
+    /// Changes the addressing of the textures outside of [0, 1], recreating the sampler and
+    /// writing it to the descriptor sets.
+    unsafe fn set_address_mode(&mut self, address_mode: vk::SamplerAddressMode) -> Result<()> {
+        self.logical_device.device_wait_idle()?;
+
+        self.data.address_mode = address_mode;
+
+        self.logical_device.destroy_sampler(self.data.texture_sampler, None);
+        create_texture_sampler(&self.logical_device, &mut self.data)?;
+        write_texture_descriptors(&self.logical_device, &self.data);
+
+        info!("Texture address mode: {:?}.", address_mode);
+
+        Ok(())
+    }
+
+    /// Renders every combination of `ranges` for `frames` frames, writing a screenshot of
+    /// each and a summary of their frame times to `directory`. The quality preset is
+    /// restored afterwards.
+    unsafe fn start_quality_sweep(
+        &mut self,
+        window: &Window,
+        directory: &Path,
+        ranges: &SweepRanges,
+        frames: u32,
+    ) -> Result<()> {
+        if self.quality_sweep.is_some() {
+            return Ok(());
+        }
+
+        let sweep = QualitySweep::new(directory, ranges, frames)?;
+        let Some((samples, anisotropy)) = sweep.first() else {
+            return Ok(());
+        };
+
+        info!("Sweeping quality settings into `{}`.", directory.display());
+        self.set_sampling(window, samples, anisotropy)?;
+        self.quality_sweep = Some(sweep);
+
+        Ok(())
+    }
+
+    /// Enables or disables the depth test and the depth write of the models, recreating
+    /// their pipelines unless the depth state is dynamic.
+    unsafe fn set_depth_state(&mut self, test: bool, write: bool) -> Result<()> {
+        self.data.depth_test = test;
+        self.data.depth_write = write;
+
+        if !has_dynamic_depth_state(&self.data) {
+            self.recreate_pipeline()?;
+        }
+
+        info!("Depth test {}, depth write {}.", test, write);
+
+        Ok(())
+    }
+
+    /// Switches between drawing the models filled and in wireframe, if the device supports it.
+    unsafe fn toggle_wireframe(&mut self) -> Result<()> {
+        if !self.data.fill_mode_non_solid {
+            warn!("Wireframe is not supported by the device (no `fillModeNonSolid`).");
+            return Ok(());
+        }
+
+        self.data.polygon_mode = if self.data.polygon_mode == vk::PolygonMode::FILL {
+            vk::PolygonMode::LINE
+        } else {
+            vk::PolygonMode::FILL
+        };
+
+        self.recreate_pipeline()?;
+
+        info!("Polygon mode: {:?}.", self.data.polygon_mode);
+
+        Ok(())
+    }
+
+    /// Switches between drawing the models in a single pass and with a depth pre-pass,
+    /// to compare their frame times when there is a lot of overdraw.
+    unsafe fn toggle_depth_prepass(&mut self) -> Result<()> {
+        if !self.data.use_depth && !self.data.depth_prepass {
+            warn!("No depth pre-pass without a depth buffer.");
+            return Ok(());
+        }
+
+        self.data.depth_prepass = !self.data.depth_prepass;
+
+        self.recreate_pipeline()?;
+
+        // Les moyennes de l'autre mode n'ont plus de sens
+        self.frame_rate.reset();
+
+        info!("Depth pre-pass {}.", if self.data.depth_prepass { "enabled" } else { "disabled" });
+
+        Ok(())
+    }
+
+    /// Adds or removes the depth buffer, recreating the render pass, the pipelines and the
+    /// framebuffers with the swapchain. The depth pre-pass needs it and is disabled with it.
+    unsafe fn set_use_depth(&mut self, window: &Window, use_depth: bool) -> Result<()> {
+        self.data.use_depth = use_depth;
+        if !use_depth {
+            self.data.depth_prepass = false;
+        }
+
+        // Le format de profondeur des pipelines change, recreate_swapchain les recrée
+        self.recreate_swapchain(window)?;
+
+        info!("Depth buffer {}.", if use_depth { "enabled" } else { "disabled" });
+
+        Ok(())
+    }
+
+    /// Enables or disables the alpha blending of the models, recreating their pipelines.
+    unsafe fn set_blending(&mut self, blending: bool) -> Result<()> {
+        self.data.blending = blending;
+        self.recreate_pipeline()?;
+
+        info!("Blending {}.", if blending { "enabled" } else { "disabled" });
+
+        Ok(())
+    }
+
+    /// Recreates the graphics pipelines (and their layout) for a change of their state.
+    unsafe fn recreate_pipeline(&mut self) -> Result<()> {
+        self.logical_device.device_wait_idle()?;
+
+        get_graphics_pipelines(&self.data)
+            .iter()
+            .for_each(|p| self.logical_device.destroy_pipeline(*p, None));
+        self.logical_device.destroy_pipeline_layout(self.data.pipeline_layout, None);
+        create_pipeline(&self.logical_device, &mut self.data)?;
+
+        Ok(())
+    }
+
+    /// Recreates the graphics pipelines with the shaders read again from the shader
+    /// directory. If the new shaders can't be used, the old pipelines are kept.
+    unsafe fn reload_pipeline(&mut self) -> Result<()> {
+        self.logical_device.device_wait_idle()?;
+
+        let old = (get_graphics_pipelines(&self.data), self.data.pipeline_layout);
+
+        if let Err(e) = create_pipeline(&self.logical_device, &mut self.data) {
+            // Seuls les objets déjà recréés avant l'erreur sont détruits
+            let new = (get_graphics_pipelines(&self.data), self.data.pipeline_layout);
+            for (new, old) in new.0.iter().zip(&old.0) {
+                if new != old {
+                    self.logical_device.destroy_pipeline(*new, None);
+                }
+            }
+            if new.1 != old.1 {
+                self.logical_device.destroy_pipeline_layout(new.1, None);
+            }
+
+            [
+                self.data.pipeline,
+                self.data.instanced_pipeline,
+                self.data.depth_prepass_pipeline,
+                self.data.depth_prepass_instanced_pipeline,
+            ] = old.0;
+            self.data.pipeline_layout = old.1;
+            return Err(e);
+        }
+
+        old.0
+            .iter()
+            .for_each(|p| self.logical_device.destroy_pipeline(*p, None));
+        self.logical_device.destroy_pipeline_layout(old.1, None);
+
+        // Les command buffers sont réenregistrés à chaque frame, avec les nouveaux pipelines
+        info!("Shaders reloaded.");
+
+        Ok(())
+    }
+
+    /// Reads back the depth under the pixel (`x`, `y`) of the last rendered frame,
+    /// linearized into a distance from the camera between the near and far planes.
+    ///
+    /// Multisampled images can't be copied: with MSAA the first sample is read (see
+    /// `depth_resolve`).
+    unsafe fn read_depth_at(&mut self, x: u32, y: u32) -> Result<f32> {
+        if !self.data.use_depth {
+            return Err(anyhow!("There is no depth buffer."));
+        }
+
+        let extent = self.data.swapchain_extent;
+        let (x, y) = (x.min(extent.width - 1), y.min(extent.height - 1));
+
+        self.logical_device.device_wait_idle()?;
+
+        // Avec MSAA le premier échantillon est résolu dans une image à un seul échantillon,
+        // qui est celle copiée (déjà en TRANSFER_SRC_OPTIMAL)
+        let resolve = if self.data.msaa_samples != vk::SampleCountFlags::_1 {
+            Some(create_depth_resolve(&self.instance, &self.logical_device, &mut self.data)?)
+        } else {
+            None
+        };
+
+        let format = self.data.depth_format;
+        let (buffer, buffer_memory) = create_buffer(
+            &self.instance,
+            &self.logical_device,
+            &mut self.data,
+            size_of::<u32>() as u64,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let command_buffer = begin_single_time_commands(&self.logical_device, &self.data)?;
+
+        if let Some(resolve) = &resolve {
+            cmd_resolve_depth(&self.logical_device, &self.data, resolve, command_buffer);
+        } else {
+            self.cmd_transition_depth_for_readback(command_buffer);
+        }
+
+        let image = resolve.as_ref().map_or(self.data.depth_image, |r| r.image);
+
+        // Une copie ne porte que sur un aspect, même avec un format combiné
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D { x: x as i32, y: y as i32, z: 0 })
+            .image_extent(vk::Extent3D { width: 1, height: 1, depth: 1 });
+
+        self.logical_device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        );
+
+        end_single_time_commands(&self.logical_device, &self.data, command_buffer)?;
+
+        if let Some(resolve) = &resolve {
+            destroy_depth_resolve(&self.logical_device, &mut self.data, resolve);
+        }
+
+        let memory = self.logical_device.map_memory(
+            buffer_memory.memory,
+            buffer_memory.offset,
+            size_of::<u32>() as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        let texel = *memory.cast::<u32>();
+        self.logical_device.unmap_memory(buffer_memory.memory);
+
+        self.logical_device.destroy_buffer(buffer, None);
+        self.data.allocator.free(&self.logical_device, buffer_memory);
+
+        // D24 est un entier normalisé sur les 24 bits de poids faible, D32 un float
+        let depth = match format {
+            vk::Format::D24_UNORM_S8_UINT => (texel & 0x00FF_FFFF) as f32 / 16_777_215.0,
+            _ => f32::from_bits(texel),
+        };
+
+        // Inverse de perspective_rh_zo: depth = far * (z - near) / (z * (far - near))
+        let ProjectionSettings { near, far, .. } = self.projection;
+        Ok(near * far / (far - depth * (far - near)))
+    }
+
+    /// Transitions the single sample depth image of the last frame for its copy.
+    unsafe fn cmd_transition_depth_for_readback(&self, command_buffer: vk::CommandBuffer) {
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(get_depth_barrier_aspect_mask(&self.data))
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        // Pas besoin de revenir au layout d'origine: la render pass part de UNDEFINED
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(get_depth_layout(&self.data))
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.data.depth_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+        self.logical_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+    }
+
+    /// Selects the model drawn at the pixel (`x`, `y`) of the last rendered frame, tinted
+    /// from the next frame on, or clears the selection if there is none.
+    unsafe fn select_model_at(&mut self, x: u32, y: u32) -> Result<()> {
+        let extent = self.data.swapchain_extent;
+        let (x, y) = (x.min(extent.width - 1), y.min(extent.height - 1));
+
+        self.logical_device.device_wait_idle()?;
+
+        self.selected_model = read_picked_model(&self.instance, &self.logical_device, &mut self.data, x, y)?;
+
+        match self.selected_model {
+            Some(index) => info!("Selected model {}.", index),
+            None => info!("No model selected."),
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the first texture with the one at `path` without waiting for its upload,
+    /// drawing a placeholder texture meanwhile.
+    unsafe fn stream_texture(&mut self, path: &Path) -> Result<()> {
+        start_texture_stream(&self.instance, &self.logical_device, &mut self.data, path)
+    }
+
+    /// The most recent validation messages (at most `MAX_VALIDATION_MESSAGES`), oldest first.
+    fn recent_validation_messages(&self) -> Vec<ValidationMessage> {
+        self.data.validation_messages
+            .lock()
+            .map(|m| m.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether resize events are still arriving, in which case recreating the swapchain is deferred.
+    fn is_resizing(&self) -> bool {
+        self.last_resize.elapsed() < self.resize_debounce
+    }
+
+    /// Ends the resize without waiting for `resize_debounce`, so the swapchain is recreated
+    /// by the next frame.
+    fn finish_resizing(&mut self) {
+        if let Some(instant) = Instant::now().checked_sub(self.resize_debounce) {
+            self.last_resize = instant;
+        }
+    }
+
+    /// Shows the frame rate (if `show_frame_rate`) and the present-to-acquire latency in
+    /// the window title, rebuilt at most every `TITLE_UPDATE_INTERVAL`.
+    fn update_window_title(&mut self, window: &Window) {
+        if self.title_updated.elapsed() < TITLE_UPDATE_INTERVAL {
+            return;
+        }
+        self.title_updated = Instant::now();
+
+        let mut title = get_window_title(&self.data);
+
+        if let (true, Some(fps), Some(frame_time)) =
+            (self.show_frame_rate, self.frame_rate.fps(), self.frame_rate.frame_time())
+        {
+            title += &format!(" - {:.0} FPS ({:.1} ms)", fps, frame_time.as_secs_f64() * 1000.0);
+
+            if self.frame_limiter.max_fps() > 0 {
+                title += &format!(", capped at {}", self.frame_limiter.max_fps());
+            }
+
+            if let Some(gpu_frame_time) = self.gpu_frame_time() {
+                title += &format!(", GPU {:.2} ms", gpu_frame_time.as_secs_f64() * 1000.0);
+            }
+        }
+
+        if let Some(latency) = self.latency.average() {
+            title += &format!(
+                " - {:?}, present-to-acquire {:.2} ms",
+                self.data.presentation_mode,
+                latency.as_secs_f64() * 1000.0,
+            );
+        }
+
+        window.set_title(&title);
+    }
+
+    /// The logical device, on which the images given to `render_to_image` must be created.
+    fn device(&self) -> &Device {
+        &self.logical_device
+    }
+
+    /// Properties and limits of the physical device used, for tools querying what it supports.
+    fn physical_device_properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.data.physical_device_properties
+    }
+
+    /// Features of the physical device used (not all of them are enabled).
+    fn physical_device_features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.data.physical_device_features
+    }
+
+    /// How long the GPU took to execute the last measured frame (see `gpu_timing`).
+    fn gpu_frame_time(&self) -> Option<Duration> {
+        get_gpu_frame_time(&self.data)
+    }
+
+    /// Captures the next frame to `path` (as a PNG).
+    fn take_screenshot(&mut self, path: &Path) {
+        self.screenshot = Some(path.to_path_buf());
+    }
+
+    /// Renders `frame_count` frames to the offscreen image of the headless mode, and writes
+    /// the last one to `path` (as a PNG).
+    unsafe fn render_to_file(&mut self, path: &Path, frame_count: u32) -> Result<()> {
+        if !self.data.headless {
+            return Err(anyhow!("Rendering to a file requires the headless mode."));
+        }
+
+        let frame_count = frame_count.max(1);
+        for frame in 0..frame_count {
+            if frame + 1 == frame_count {
+                self.take_screenshot(path);
+            }
+
+            self.render_offscreen_frame()?;
+        }
+
+        flush_captures(&self.logical_device, &mut self.data);
+
+        Ok(())
+    }
+
+    /// Renders a frame into `target`, an image of the app embedding the renderer (see
+    /// `RenderTarget` for its usages and layouts), instead of the offscreen image of the
+    /// headless mode. The frame is finished when this returns.
+    ///
+    /// The swapchain objects are recreated when the image, its format or its extent change.
+    unsafe fn render_to_image(
+        &mut self,
+        window: &Window,
+        target: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        if !self.data.headless {
+            return Err(anyhow!("Rendering to an image requires the headless mode."));
+        }
+        if extent.width == 0 || extent.height == 0 {
+            return Err(anyhow!("Can't render to an empty image."));
+        }
+
+        let target = RenderTarget { image: target, format, extent };
+        if self.data.render_target != Some(target) {
+            self.data.render_target = Some(target);
+            self.recreate_swapchain(window)?;
+        }
+
+        self.render_offscreen_frame()
+    }
+
+    /// Renders a frame into the single offscreen image of the headless mode, and waits for it.
+    unsafe fn render_offscreen_frame(&mut self) -> Result<()> {
+        // Une seule image: chaque frame attend la fin de la précédente, sans sémaphore
+        // puisqu'il n'y a ni acquisition ni présentation
+        self.update_command_buffer(0)?;
+        self.update_uniform_buffer(self.frame)?;
+
+        let command_buffers = &[self.data.command_buffers[0]];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+
+        let fence = self.data.in_flight_fences[self.frame];
+        self.logical_device.reset_fences(&[fence])?;
+        self.logical_device.queue_submit(self.data.graphics_queue, &[submit_info], fence)?;
+        self.logical_device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+        self.frame = (self.frame + 1) % self.data.frames_in_flight;
+
+        Ok(())
+    }
+
+    /// Captures the next `frame_count` frames to `frame_0001.png`, `frame_0002.png`... in
+    /// `directory`, which is created if needed.
+    fn start_recording(&mut self, directory: &Path, frame_count: u32) -> Result<()> {
+        check_capture(&self.data)?;
+
+        info!("Recording {} frames to `{}`.", frame_count, directory.display());
+        self.frame_sequence = Some(FrameSequence::new(directory, frame_count)?);
+
+        Ok(())
+    }
+
+    /// The file to capture the frame being recorded to, if any.
+    fn get_capture_path(&mut self) -> Option<PathBuf> {
+        if let Some(path) = self.screenshot.take() {
+            return Some(path);
+        }
+
+        let path = self.frame_sequence.as_mut()?.next_path();
+        if path.is_none() {
+            info!("Recording finished.");
+            self.frame_sequence = None;
+        }
+
+        path
+    }
+
+    /// Renders `models` models with each render path for `frames` frames and prints the comparison.
+    fn start_benchmark(&mut self, models: usize, frames: u32) {
+        if self.benchmark.is_some() {
+            return;
+        }
+
+        info!("Benchmarking {} models over {} frames per render path.", models, frames);
+        self.benchmark_saved = (self.models, self.instanced, self.recording_threads);
+        self.benchmark = Some(Benchmark::new(
+            models.min(MAX_INSTANCES),
+            frames,
+            get_max_recording_threads(),
+        ));
+    }
+
+    /// View and projection matrices of the camera.
+    fn get_view_projection(&self) -> (glm::Mat4, glm::Mat4) {
+        let view = self.camera.view_matrix();
+
+        // Avec une surface tournée d'un quart de tour, l'écran a la largeur et la hauteur inversées
+        let rotation = get_surface_rotation(self.data.swapchain_transform);
+        let extent = self.data.swapchain_extent;
+        let aspect = if matches!(rotation, 90 | 270) {
+            extent.height as f32 / extent.width as f32
+        } else {
+            extent.width as f32 / extent.height as f32
+        };
+
+        let mut proj = glm::perspective_rh_zo(
+            aspect,
+            glm::radians(&glm::vec1(self.projection.fov_degrees))[0],
+            self.projection.near,
+            self.projection.far,
+        );
+
+        // Vulkan a l'axe Y inversé par rapport à OpenGL
+        proj[(1,1)] *= -1.0;
+
+        // Le compositeur affiche l'image tournée, on la dessine donc tournée en sens inverse
+        if rotation != 0 {
+            let pre_rotation = glm::rotate(
+                &glm::identity(),
+                glm::radians(&glm::vec1(rotation as f32))[0],
+                &glm::vec3(0.0, 0.0, 1.0),
+            );
+            proj = pre_rotation * proj;
+        }
+
+        (view, proj)
+    }
+
+    /// Writes the wireframe of the models as currently seen to an SVG file at `path`.
+    fn export_wireframe_svg(&self, path: &Path) -> Result<()> {
+        let (view, proj) = self.get_view_projection();
+        let positions = self.data.meshes
+            .iter()
+            .map(|m| m.vertices.iter().map(|v| v.pos).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let meshes = (0..self.models)
+            .map(|i| {
+                let mesh_index = i % self.data.meshes.len();
+                let mvp = proj * view * self.get_model_matrix(i);
+                (&positions[mesh_index][..], &self.data.meshes[mesh_index].indices[..], mvp)
+            })
+            .collect::<Vec<_>>();
+
+        export_wireframe_svg(
+            path,
+            &meshes,
+            self.data.swapchain_extent.width as f32,
+            self.data.swapchain_extent.height as f32,
+            &self.config.wireframe_style,
+        )?;
+
+        info!("Exported the wireframe to `{}`.", path.display());
+
+        Ok(())
+    }
+
+    /// Writes the uniform buffer of the frame in flight `frame`, whose previous submission
+    /// must be finished (its fence waited on).
+    unsafe fn update_uniform_buffer(
+        &self,
+        frame: usize
+    ) -> Result<()> {
+        let time = self.animation_time;
+
+        let model = glm::rotate(
+            &glm::identity(),
+            time * glm::radians(&glm::vec1(90.0))[0],
+            &glm::vec3(0.0, 0.0, 1.0),
+        );
+
+        let (view, proj) = self.get_view_projection();
+
+        // Vers la lumière, 45° au dessus de l'horizon
+        let azimuth = self.light_azimuth.to_radians();
+        let elevation = 45f32.to_radians();
+        let light_direction = glm::vec4(
+            elevation.cos() * azimuth.cos(),
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            0.0,
+        );
+
+        let ubo = UniformBufferObject { view, proj, light_direction, light_color: LIGHT_COLOR };
+
+        let uniform_buffer_memory = self.data.uniform_buffers_memory[frame];
+        let memory = self.logical_device.map_memory(
+            uniform_buffer_memory.memory,
+            uniform_buffer_memory.offset,
+            size_of::<UniformBufferObject>() as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+
+        memcpy(&ubo, memory.cast(), 1);
+
+        self.logical_device.unmap_memory(uniform_buffer_memory.memory);
+
+        Ok(())
+    }
+
+    unsafe fn update_command_buffer(&mut self, image_index: usize) -> Result<()> {
+        let command_pool = self.data.command_pools[image_index];
+        self.logical_device.reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+
+        let command_buffer = self.data.command_buffers[image_index];
+
+        let info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        self.logical_device.begin_command_buffer(command_buffer, &info)?;
+        cmd_begin_gpu_timing(&self.logical_device, &mut self.data, command_buffer, self.frame);
+
+        // Le dispatch se fait hors de la render pass
+        if self.particles {
+            cmd_update_particles(&self.logical_device, &self.data, command_buffer, self.animation_step);
+        }
+
+        cmd_begin_main_pass(&self.logical_device, &self.data, command_buffer, image_index, self.clear_color);
+
+        let mut secondary_command_buffer = if self.instanced {
+            vec![self.update_instanced_command_buffer(image_index)?]
+        } else {
+            let models = (0..self.models)
+                .map(|i| self.get_model_matrix(i))
+                .collect::<Vec<_>>();
+
+            let (view, proj) = self.get_view_projection();
+            update_visibility(&self.logical_device, &mut self.data, image_index, &(proj * view), &models)?;
+
+            let mut command_buffers = if self.data.depth_prepass {
+                vec![update_depth_prepass_command_buffer(
+                    &self.logical_device,
+                    &mut self.data,
+                    image_index,
+                    self.frame,
+                    &models,
+                )?]
+            } else {
+                vec![]
+            };
+
+            let model_command_buffers = if self.recording_threads > 1 {
+                record_model_command_buffers(
+                    &self.logical_device,
+                    &mut self.data,
+                    image_index,
+                    self.frame,
+                    &models,
+                    self.selected_model,
+                    self.recording_threads,
+                )?
+            } else {
+                (0..self.models)
+                    .map(|i| {
+                        if is_model_recorded(&self.data, i) {
+                            self.update_secondary_command_buffer(image_index, i).map(Some)
+                        } else {
+                            Ok(None)
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+
+            // Les modèles hors du frustum n'ont pas de command buffer
+            command_buffers.extend(
+                self.get_draw_order(&models)
+                    .into_iter()
+                    .filter_map(|i| model_command_buffers[i]),
+            );
+
+            command_buffers
+        };
+
+        // Le skybox en premier, les modèles passent devant
+        if self.skybox {
+            secondary_command_buffer.insert(
+                0,
+                update_skybox_command_buffer(&self.logical_device, &mut self.data, image_index, self.frame)?,
+            );
+        }
+
+        if self.particles {
+            let (view, proj) = self.get_view_projection();
+            secondary_command_buffer.push(update_particle_command_buffer(
+                &self.logical_device,
+                &mut self.data,
+                image_index,
+                &(proj * view),
+            )?);
+        }
+
+        // Le texte en dernier pour qu'il soit par dessus les modèles
+        let mut text = format!("Frame: {}", self.frame_count);
+        if let Some(fps) = self.frame_rate.fps() {
+            text += &format!(" ({:.0} FPS)", fps);
+        }
+
+        text += &format!("\nModels: {}", self.models);
+        if self.instanced {
+            text += " (instanced)";
+        } else {
+            if self.recording_threads > 1 {
+                text += &format!(" ({} threads)", self.recording_threads);
+            }
+            // La visibilité n'est calculée que sans instancing
+            text += &format!(", {} culled", get_culled_count(&self.data));
+        }
+
+        match self.selected_model {
+            Some(index) => text += &format!("\nSelected: {}", index),
+            None => text += "\nSelected: none",
+        }
+        secondary_command_buffer.push(update_text_command_buffer(
+            &self.logical_device,
+            &mut self.data,
+            image_index,
+            &text,
+            glm::vec2(16.0, 16.0),
+            &self.text_style,
+        )?);
+
+        self.logical_device.cmd_execute_commands(command_buffer, &secondary_command_buffer[..]);
+
+        cmd_end_main_pass(&self.logical_device, &self.data, command_buffer, image_index);
+
+        if let Some(path) = self.get_capture_path() {
+            if let Err(e) = cmd_capture_swapchain_image(
+                &self.instance,
+                &self.logical_device,
+                &mut self.data,
+                command_buffer,
+                image_index,
+                path,
+            ) {
+                warn!("Could not capture the frame: {}", e);
+            }
+        }
+
+        cmd_end_gpu_timing(&self.logical_device, &self.data, command_buffer, self.frame);
+        self.logical_device.end_command_buffer(command_buffer)?;
+
+        Ok(())
+    }
+
+    unsafe fn update_secondary_command_buffer(
+        &mut self,
+        image_index: usize,
+        model_index: usize,
+    ) -> Result<vk::CommandBuffer> {
+        self.data.secondary_command_buffers.resize_with(image_index + 1, Vec::new);
+
+        let command_buffers = &mut self.data.secondary_command_buffers[image_index];
+
+        while model_index >= command_buffers.len() {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(self.data.command_pools[image_index])
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .command_buffer_count(1);
+
+            let command_buffer = self.logical_device.allocate_command_buffers(&allocate_info)?[0];
+            command_buffers.push(command_buffer);
+        }
+
+        let command_buffer = command_buffers[model_index];
+        let model = self.get_model_matrix(model_index);
+
+        record_model_command_buffer(
+            &self.logical_device,
+            &self.data,
+            command_buffer,
+            image_index,
+            self.frame,
+            model_index,
+            &model,
+            self.selected_model,
+        )?;
+
+        Ok(command_buffer)
+    }
+
+    /// Counts an event loop iteration, returning whether a frame should be rendered in it.
+    fn tick_render_divisor(&mut self) -> bool {
+        self.skipped_renders += 1;
+        if self.skipped_renders < self.render_divisor {
+            return false;
+        }
+
+        self.skipped_renders = 0;
+        true
+    }
+
+    /// When the event loop iteration after a skipped one should start: the frames being
+    /// spread over `render_divisor` iterations, each waits for its share of a frame time.
+    fn get_next_iteration_deadline(&self) -> Instant {
+        match self.frame_rate.frame_time() {
+            Some(frame_time) => self.last_update + frame_time * self.skipped_renders / self.render_divisor,
+            None => Instant::now(),
+        }
+    }
+
+    /// Cycles the requested MSAA samples between 1, 2, 4 and 8 (clamped to what the device
+    /// supports), recreating the swapchain to apply them.
+    unsafe fn select_next_msaa_samples(&mut self, window: &Window) -> Result<()> {
+        let samples = match self.data.requested_msaa_samples {
+            vk::SampleCountFlags::_1 => vk::SampleCountFlags::_2,
+            vk::SampleCountFlags::_2 => vk::SampleCountFlags::_4,
+            vk::SampleCountFlags::_4 => vk::SampleCountFlags::_8,
+            _ => vk::SampleCountFlags::_1,
+        };
+
+        self.set_sampling(window, samples, self.data.max_anisotropy)
+    }
+
+    /// Cycles between rendering every iteration of the event loop, and one out of 2, 4 or 8.
+    fn select_next_render_divisor(&mut self) {
+        self.render_divisor = if self.render_divisor >= 8 { 1 } else { self.render_divisor * 2 };
+        self.skipped_renders = 0;
+
+        info!("Rendering one frame out of {}.", self.render_divisor);
+    }
+
+    /// Cycles the frame rate cap between unlimited, 30, 60 and 120 FPS.
+    fn select_next_frame_rate_cap(&mut self) {
+        let max_fps = match self.frame_limiter.max_fps() {
+            0 => 30,
+            30 => 60,
+            60 => 120,
+            _ => 0,
+        };
+        self.frame_limiter.set_max_fps(max_fps);
+
+        match max_fps {
+            0 => info!("Frame rate unlimited."),
+            _ => info!("Frame rate capped at {} FPS.", max_fps),
+        }
+    }
+
+    /// Doubles the number of recording threads, back to 1 after the maximum.
+    fn select_next_recording_threads(&mut self) {
+        let max = get_max_recording_threads();
+        self.recording_threads = if self.recording_threads >= max {
+            1
+        } else {
+            (self.recording_threads * 2).min(max)
+        };
+
+        info!("Recording on {} thread(s).", self.recording_threads);
+    }
+
+    /// Indices of the models (`models` being their model matrices) in the order they are
+    /// drawn: back to front (from the camera) when blending, so that the transparent models
+    /// are composited over the ones behind them.
+    fn get_draw_order(&self, models: &[glm::Mat4]) -> Vec<usize> {
+        let mut order = (0..models.len()).collect::<Vec<_>>();
+
+        if self.data.blending {
+            let distance = |i: usize| glm::distance2(&self.camera.position, &models[i].column(3).xyz());
+            order.sort_by(|a, b| distance(*b).total_cmp(&distance(*a)));
+        }
+
+        order
+    }
+
+    /// Model matrix of the model at `model_index`, placed by `model_transforms`.
+    fn get_model_matrix(&self, model_index: usize) -> glm::Mat4 {
+        let model = self.model_transforms
+            .get(model_index)
+            .copied()
+            .unwrap_or_else(|| get_grid_transform(model_index));
+
+        let time = self.animation_time;
+
+        glm::rotate(
+            &model,
+            time * glm::radians(&glm::vec1(90.0))[0],
+            &glm::vec3(0.0, 0.0, 1.0)
+        )
+    }
+
+    /// Records a single secondary command buffer drawing every model with one instanced draw.
+    unsafe fn update_instanced_command_buffer(
+        &mut self,
+        image_index: usize,
+    ) -> Result<vk::CommandBuffer> {
+        self.data.secondary_command_buffers.resize_with(image_index + 1, Vec::new);
+
+        if self.data.secondary_command_buffers[image_index].is_empty() {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(self.data.command_pools[image_index])
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .command_buffer_count(1);
+
+            let command_buffer = self.logical_device.allocate_command_buffers(&allocate_info)?[0];
+            self.data.secondary_command_buffers[image_index].push(command_buffer);
+        }
+
+        let command_buffer = self.data.secondary_command_buffers[image_index][0];
+
+        // Regroupées par mesh, chaque mesh étant dessiné avec ses instances à la suite
+        let mesh_count = self.data.meshes.len();
+        let mut instances = vec![];
+        let mut draws = vec![];
+        for mesh_index in 0..mesh_count {
+            let first_instance = instances.len() as u32;
+            instances.extend(
+                (mesh_index..self.models.min(MAX_INSTANCES))
+                    .step_by(mesh_count)
+                    .map(|i| InstanceData { model: self.get_model_matrix(i), model_index: i as u32 }),
+            );
+            draws.push((mesh_index, first_instance, instances.len() as u32 - first_instance));
+        }
+
+        let instance_buffer_memory = self.data.instance_buffers_memory[image_index];
+        let memory = self.logical_device.map_memory(
+            instance_buffer_memory.memory,
+            instance_buffer_memory.offset,
+            (size_of::<InstanceData>() * instances.len()) as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+
+        memcpy(instances.as_ptr(), memory.cast(), instances.len());
+
+        self.logical_device.unmap_memory(instance_buffer_memory.memory);
+
+        // Pas d'opacité ni de matériau par instance: tout est opaque, avec le premier matériau
+        let opacity_bytes = &1.0f32.to_ne_bytes()[..];
+        let material_index_bytes = &0u32.to_ne_bytes()[..];
+        let texture_blend_bytes = &self.data.texture_blend.to_ne_bytes()[..];
+        let selected_model_bytes = &get_selected_model_index(self.selected_model).to_ne_bytes()[..];
+        let debug_view_bytes = &(self.data.debug_view as u32).to_ne_bytes()[..];
+
+        begin_secondary_command_buffer(&self.logical_device, &self.data, command_buffer, image_index)?;
+
+        self.logical_device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.pipeline_layout,
+            0,
+            &[self.data.descriptor_sets[self.frame]],
+            &[],
+        );
+
+        self.logical_device.cmd_push_constants(
             command_buffer,
             self.data.pipeline_layout,
             vk::ShaderStageFlags::FRAGMENT,
-            64,
-            opacity_bytes, //opacité de 0.2
+            68,
+            opacity_bytes,
+        );
+
+        self.logical_device.cmd_push_constants(
+            command_buffer,
+            self.data.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            72,
+            material_index_bytes,
+        );
+
+        self.logical_device.cmd_push_constants(
+            command_buffer,
+            self.data.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            76,
+            texture_blend_bytes,
+        );
+
+        self.logical_device.cmd_push_constants(
+            command_buffer,
+            self.data.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            80,
+            selected_model_bytes,
         );
 
-        self.logical_device.cmd_draw_indexed(
-            command_buffer,
-            self.data.indices.len() as u32,
-            1,
-            0,
-            0,
-            0
-        );
+        self.logical_device.cmd_push_constants(
+            command_buffer,
+            self.data.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            84,
+            debug_view_bytes,
+        );
+
+        // La pre-pass dessine toutes les instances avant la passe principale
+        let pipelines = if self.data.depth_prepass {
+            vec![self.data.depth_prepass_instanced_pipeline, self.data.instanced_pipeline]
+        } else {
+            vec![self.data.instanced_pipeline]
+        };
+
+        for pipeline in pipelines {
+            self.logical_device.cmd_bind_pipeline(
+                command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline
+            );
+            if pipeline == self.data.instanced_pipeline {
+                cmd_set_depth_state(&self.logical_device, &self.data, command_buffer);
+            }
+
+            for &(mesh_index, first_instance, instance_count) in &draws {
+                let mesh = &self.data.meshes[mesh_index];
+                if instance_count == 0 {
+                    continue;
+                }
+
+                self.logical_device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &[mesh.vertex_buffer, self.data.instance_buffers[image_index]],
+                    &[0, 0]
+                );
+                self.logical_device.cmd_bind_index_buffer(
+                    command_buffer,
+                    mesh.index_buffer,
+                    0,
+                    mesh.index_type
+                );
+
+                self.logical_device.cmd_draw_indexed(
+                    command_buffer,
+                    mesh.indices.len() as u32,
+                    instance_count,
+                    0,
+                    0,
+                    first_instance
+                );
+            }
+        }
+
+        self.logical_device.end_command_buffer(command_buffer)?;
+
+        Ok(command_buffer)
+    }
+
+    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+        // Pendant un passage en plein écran la fenêtre peut avoir une taille nulle, pour laquelle
+        // aucune swapchain ne peut être créée: on réessaie à la frame suivante
+        let size = window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            self.resized = true;
+            return Ok(());
+        }
+
+        self.resized = false;
+        self.latency.reset();
+        self.logical_device.device_wait_idle()?;
+        self.destroy_swapchain();
+
+        create_swapchain(window, &self.instance, &self.logical_device, &mut self.data)?;
+        if self.data.swapchain_images.len() != self.data.command_pools.len() {
+            recreate_image_command_pools(&self.instance, &self.logical_device, &mut self.data)?;
+        }
+        create_swapchain_image_views(&self.logical_device, &mut self.data)?;
+        create_render_pass(&self.instance, &self.logical_device, &mut self.data)?;
+
+        // Le viewport et le scissor étant dynamiques, un simple redimensionnement garde les
+        // pipelines (la nouvelle render pass est compatible avec l'ancienne)
+        if get_pipeline_targets(&self.data) != self.data.pipeline_targets {
+            self.recreate_pipeline()?;
+        }
+
+        create_color_objects(&self.instance, &self.logical_device, &mut self.data)?;
+        create_depth_objects(&self.instance, &self.logical_device, &mut self.data)?;
+        create_picking_objects(&self.instance, &self.logical_device, &mut self.data)?;
+
+        create_framebuffers(&self.logical_device, &mut self.data)?;
+        create_uniform_buffers(&self.instance, &self.logical_device, &mut self.data)?;
+        create_instance_buffers(&self.instance, &self.logical_device, &mut self.data)?;
+        create_predicate_buffers(&self.instance, &self.logical_device, &mut self.data)?;
+        create_descriptor_pool(&self.logical_device, &mut self.data)?;
+        create_descriptor_sets(&self.logical_device, &mut self.data)?;
+        create_text_swapchain_objects(&self.instance, &self.logical_device, &mut self.data)?;
+        create_particle_swapchain_objects(&self.logical_device, &mut self.data)?;
+        create_skybox_swapchain_objects(&self.logical_device, &mut self.data)?;
+
+        create_command_buffers(&self.logical_device, &mut self.data)?;
+        debug_assert_eq!(self.data.command_buffers.len(), self.data.swapchain_images.len());
+
+        // Après device_wait_idle plus aucune image n'est utilisée, et les nouvelles images
+        // n'ont rien à voir avec les anciennes. Il peut y en avoir plus ou moins que
+        // frames_in_flight: une image est attendue via la fence de la frame qui l'utilise
+        self.data.images_in_flight = vec![vk::Fence::null(); self.data.swapchain_images.len()];
+
+        Ok(())
+    }
+
+    /// Pauses or resumes the rotation of the models.
+    fn toggle_animation(&mut self) {
+        self.animation_paused = !self.animation_paused;
+        info!("Animation {}.", if self.animation_paused { "paused" } else { "resumed" });
+    }
+
+    /// Turns the directional light around the Z axis by `degrees`.
+    fn rotate_light(&mut self, degrees: f32) {
+        self.light_azimuth = (self.light_azimuth + degrees).rem_euclid(360.0);
+        info!("Light azimuth: {}°.", self.light_azimuth);
+    }
+
+    /// Changes the speed of the rotation of the models (1.0 being a quarter turn per second).
+    fn set_animation_speed(&mut self, speed: f32) {
+        self.animation_speed = speed.clamp(0.125, 8.0);
+        info!("Animation speed: x{}.", self.animation_speed);
+    }
+
+    /// Shows or hides the particles, created the first time they are shown. If they can't
+    /// be (no compute on the graphics queue, missing shaders), they stay hidden.
+    unsafe fn set_particles(&mut self, particles: bool) -> Result<()> {
+        if particles && !has_particles(&self.data) {
+            self.logical_device.device_wait_idle()?;
+
+            if let Err(e) = create_particle_objects(&self.instance, &self.logical_device, &mut self.data) {
+                destroy_particle_objects(&self.logical_device, &mut self.data);
+                warn!("Could not create the particles: {}", e);
+                return Ok(());
+            }
+        }
+
+        self.particles = particles;
+        info!("Particles {}.", if particles { "shown" } else { "hidden" });
+
+        Ok(())
+    }
+
+    /// Shows or hides the skybox, created the first time it is shown. If it can't be
+    /// (missing faces or shaders), it stays hidden.
+    unsafe fn set_skybox(&mut self, skybox: bool) -> Result<()> {
+        if skybox && !has_skybox(&self.data) {
+            self.logical_device.device_wait_idle()?;
+
+            if let Err(e) = create_skybox_objects(&self.instance, &self.logical_device, &mut self.data) {
+                destroy_skybox_objects(&self.logical_device, &mut self.data);
+                warn!("Could not create the skybox: {}", e);
+                return Ok(());
+            }
+
+            // Le cube map est lié aux descriptor sets des modèles
+            write_texture_descriptors(&self.logical_device, &self.data);
+        }
+
+        self.skybox = skybox;
+        info!("Skybox {}.", if skybox { "shown" } else { "hidden" });
+
+        Ok(())
+    }
+
+    /// Changes what the models show, pushed to the fragment shader with each draw.
+    fn set_debug_view(&mut self, view: DebugView) {
+        self.data.debug_view = view;
+        info!("Debug view: {:?}.", view);
+    }
 
-        self.logical_device.end_command_buffer(command_buffer)?;
+    /// Changes the weight of the second texture, from 0.0 (first texture only) to 1.0.
+    fn set_texture_blend(&mut self, texture_blend: f32) {
+        self.data.texture_blend = texture_blend.clamp(0.0, 1.0);
+        info!("Second texture weight: {}.", self.data.texture_blend);
+    }
 
-        Ok(command_buffer)
+    /// Changes the vertical field of view, in degrees.
+    fn set_fov(&mut self, fov_degrees: f32) {
+        self.projection.fov_degrees = fov_degrees.clamp(10.0, 120.0);
+        info!("Field of view: {}°.", self.projection.fov_degrees);
     }
 
-    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
-        self.logical_device.device_wait_idle()?;
-        self.destroy_swapchain();
+    /// Puts the camera and the models back where they were when the app was created (the
+    /// settings are restored by `reset`). The uniform buffer, written each frame, follows.
+    fn reset_scene(&mut self) {
+        self.camera = get_initial_camera();
+        self.model_transforms = get_initial_model_transforms();
+        self.selected_model = None;
+        self.animation_time = 0.0;
 
-        create_swapchain(window, &self.instance, &self.logical_device, &mut self.data)?;
-        create_swapchain_image_views(&self.logical_device, &mut self.data)?;
-        create_render_pass(&self.instance, &self.logical_device, &mut self.data)?;
-        create_pipeline(&self.logical_device, &mut self.data)?;
+        info!("Scene reset.");
+    }
 
-        create_color_objects(&self.instance, &self.logical_device, &mut self.data)?;
-        create_depth_objects(&self.instance, &self.logical_device, &mut self.data)?;
+    /// Selects the next of the models shown, for `translate_selected_model`.
+    fn select_next_model(&mut self) {
+        // Le premier modèle si aucun n'est sélectionné
+        let index = self.selected_model.map_or(0, |i| (i + 1) % self.models);
+        self.selected_model = Some(index);
+        info!("Selected model: {}.", index);
+    }
 
-        create_framebuffers(&self.logical_device, &mut self.data)?;
-        create_uniform_buffers(&self.instance, &self.logical_device, &mut self.data)?;
-        create_descriptor_pool(&self.logical_device, &mut self.data)?;
-        create_descriptor_sets(&self.logical_device, &mut self.data)?;
+    /// Moves the selected model by `offset`, in world space.
+    fn translate_selected_model(&mut self, offset: glm::Vec3) {
+        let Some(index) = self.selected_model else {
+            return;
+        };
+        if index >= self.model_transforms.len() {
+            let start = self.model_transforms.len();
+            self.model_transforms.extend((start..=index).map(get_grid_transform));
+        }
 
-        create_command_buffers(&self.logical_device, &mut self.data)?;
+        self.model_transforms[index] = glm::translate(&glm::identity(), &offset) * self.model_transforms[index];
+    }
 
-        self.data
-            .images_in_flight
-            .resize(self.data.swapchain_images.len(), vk::Fence::null())
-        ;
+    /// Changes the background color (RGBA, linear), from the next frame.
+    fn set_clear_color(&mut self, color: [f32; 4]) {
+        self.clear_color = color;
+        info!("Clear color: {:?}.", color);
+    }
 
-        Ok(())
+    /// Changes the font size (in pixels) and edge smoothing of the text overlay.
+    fn set_text_style(&mut self, size: f32, smoothing: f32) {
+        self.text_style.size = size.max(1.0);
+        self.text_style.smoothing = smoothing.max(0.0);
+        info!("Text size: {} pixels, smoothing: {}.", self.text_style.size, self.text_style.smoothing);
     }
 
     unsafe fn destroy_swapchain(&mut self) {
+        destroy_capture_buffers(&self.logical_device, &mut self.data);
+        destroy_text_swapchain_objects(&self.logical_device, &mut self.data);
+        destroy_particle_swapchain_objects(&self.logical_device, &mut self.data);
+        destroy_skybox_swapchain_objects(&self.logical_device, &mut self.data);
+        destroy_predicate_buffers(&self.logical_device, &mut self.data);
+
         self.logical_device.destroy_image_view(self.data.color_image_view, None);
-        self.logical_device.free_memory(self.data.color_image_memory, None);
+        self.data.allocator.free_image(&self.logical_device, self.data.color_image_memory);
         self.logical_device.destroy_image(self.data.color_image, None);
 
-        self.logical_device.destroy_image_view(self.data.depth_image_view, None);
-        self.logical_device.free_memory(self.data.depth_image_memory, None);
-        self.logical_device.destroy_image(self.data.depth_image, None);
+        if self.data.use_depth {
+            self.logical_device.destroy_image_view(self.data.depth_image_view, None);
+            self.data.allocator.free_image(&self.logical_device, self.data.depth_image_memory);
+            self.logical_device.destroy_image(self.data.depth_image, None);
+        }
+
+        destroy_picking_objects(&self.logical_device, &mut self.data);
 
         self.logical_device.destroy_descriptor_pool(self.data.descriptor_pool, None);
 
@@ -510,41 +2633,71 @@ impl App {
             .iter()
             .for_each(|b| self.logical_device.destroy_buffer(*b, None));
         self.data.uniform_buffers_memory
+            .drain(..)
+            .for_each(|m| self.data.allocator.free(&self.logical_device, m));
+        self.data.instance_buffers
             .iter()
-            .for_each(|m| self.logical_device.free_memory(*m, None));
+            .for_each(|b| self.logical_device.destroy_buffer(*b, None));
+        self.data.instance_buffers_memory
+            .drain(..)
+            .for_each(|m| self.data.allocator.free(&self.logical_device, m));
 
         self.data.framebuffers
             .iter()
             .for_each(|f| self.logical_device.destroy_framebuffer(*f, None));
 
-        self.logical_device.destroy_pipeline(self.data.pipeline, None);
-        self.logical_device.destroy_pipeline_layout(self.data.pipeline_layout, None);
         self.logical_device.destroy_render_pass(self.data.render_pass, None);
         self.data.swapchain_image_views
             .iter()
             .for_each(|v| self.logical_device.destroy_image_view(*v, None));
-        self.logical_device.destroy_swapchain_khr(self.data.swapchain, None);
+
+        if self.data.headless {
+            destroy_offscreen_swapchain(&self.logical_device, &mut self.data);
+        } else {
+            self.logical_device.destroy_swapchain_khr(self.data.swapchain, None);
+        }
 
     }
 
-    /// Destroys Vulkan app
+    /// Destroys Vulkan app, once: called again (or by `drop`), it does nothing.
     #[rustfmt::skip]
     unsafe fn destroy(&mut self) {
+        if self.destroyed {
+            return;
+        }
+        self.destroyed = true;
+
         self.destroy_swapchain();
 
+        get_graphics_pipelines(&self.data)
+            .iter()
+            .for_each(|p| self.logical_device.destroy_pipeline(*p, None));
+        self.logical_device.destroy_pipeline_layout(self.data.pipeline_layout, None);
+
         self.data.command_pools
             .iter()
             .for_each(|p| self.logical_device.destroy_command_pool(*p, None));
+        destroy_recording_objects(&self.logical_device, &mut self.data);
+        destroy_timestamp_query_pool(&self.logical_device, &mut self.data);
+        destroy_text_objects(&self.logical_device, &mut self.data);
+        destroy_particle_objects(&self.logical_device, &mut self.data);
+        destroy_skybox_objects(&self.logical_device, &mut self.data);
+        destroy_streaming_objects(&self.logical_device, &mut self.data);
         self.logical_device.destroy_sampler(self.data.texture_sampler, None);
         self.logical_device.destroy_image_view(self.data.texture_image_view, None);
         self.logical_device.destroy_image(self.data.texture_image, None);
-        self.logical_device.free_memory(self.data.texture_image_memory, None);
+        self.data.allocator.free_image(&self.logical_device, self.data.texture_image_memory);
+        destroy_second_texture(&self.logical_device, &mut self.data);
 
         self.logical_device.destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
-        self.logical_device.destroy_buffer(self.data.index_buffer, None);
-        self.logical_device.free_memory(self.data.index_buffer_memory, None);
-        self.logical_device.destroy_buffer(self.data.vertex_buffer, None);
-        self.logical_device.free_memory(self.data.vertex_buffer_memory, None);
+        self.logical_device.destroy_buffer(self.data.material_buffer, None);
+        self.data.allocator.free(&self.logical_device, self.data.material_buffer_memory);
+        for mesh in &self.data.meshes {
+            self.logical_device.destroy_buffer(mesh.index_buffer, None);
+            self.data.allocator.free(&self.logical_device, mesh.index_buffer_memory);
+            self.logical_device.destroy_buffer(mesh.vertex_buffer, None);
+            self.data.allocator.free(&self.logical_device, mesh.vertex_buffer_memory);
+        }
 
         self.data.in_flight_fences
             .iter()
@@ -557,6 +2710,9 @@ impl App {
             .for_each(|s| self.logical_device.destroy_semaphore(*s, None));
 
         self.logical_device.destroy_command_pool(self.data.command_pool, None);
+        destroy_transfer_objects(&self.logical_device, &mut self.data);
+        destroy_staging_buffer(&self.logical_device, &mut self.data);
+        self.data.allocator.destroy(&self.logical_device);
         self.logical_device.destroy_device(None);
         self.instance.destroy_surface_khr(self.data.surface, None);
 
@@ -568,14 +2724,85 @@ impl App {
     }
 }
 
+impl Drop for App {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.destroyed {
+                // On détruit quand même si le device est perdu
+                if let Err(e) = self.logical_device.device_wait_idle() {
+                    warn!("Could not wait for the device before destroying it: {}", e);
+                }
+            }
+            self.destroy();
+        }
+    }
+}
+
+/// The camera of a new scene, looking at the models from above.
+fn get_initial_camera() -> Camera {
+    Camera::looking_at(glm::vec3(6.0, 2.0, 2.0), glm::vec3(0.0, 0.0, 0.0))
+}
+
+/// The placement of the models of a new scene, the first ones on the default grid.
+fn get_initial_model_transforms() -> Vec<glm::Mat4> {
+    (0..4).map(get_grid_transform).collect()
+}
+
+/// Placement of the model at `model_index` on the default grid: 2 by 2 squares of models,
+/// each one behind the previous one.
+fn get_grid_transform(model_index: usize) -> glm::Mat4 {
+    let x = ((model_index / 4) as f32) * -2.5;
+    let y = (((model_index % 2) as f32) * 2.5) - 1.25;
+    let z = ((((model_index / 2) % 2) as f32) * -2.0) + 1.0;
+
+    glm::translate(&glm::identity(), &glm::vec3(x, y, z))
+}
+
+
+/// The Vulkan version the instance is created with, the lowest one the enabled features need.
+fn get_instance_api_version(data: &AppData) -> u32 {
+    if data.dynamic_rendering {
+        vk::make_version(1, 3, 0)
+    } else if data.separate_depth_stencil_layouts {
+        vk::make_version(1, 2, 0)
+    } else if data.subgroup_size.is_some() || data.conditional_rendering {
+        vk::make_version(1, 1, 0)
+    } else {
+        vk::make_version(1, 0, 0)
+    }
+}
 
 unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) -> Result<Instance> {
+    // Les fonctionnalités optionnelles demandent une version minimale, sinon on reste en 1.0
+    if data.dynamic_rendering && entry.version()? < Version::new(1, 3, 0) {
+        warn!("Vulkan 1.3 is not available, falling back to render passes.");
+        data.dynamic_rendering = false;
+    }
+
+    if data.separate_depth_stencil_layouts && entry.version()? < Version::new(1, 2, 0) {
+        warn!("Vulkan 1.2 is not available, falling back to combined depth/stencil layouts.");
+        data.separate_depth_stencil_layouts = false;
+    }
+
+    // Vulkan 1.1 pour interroger les propriétés de l'extension (get_physical_device_properties2)
+    if data.subgroup_size.is_some() && entry.version()? < Version::new(1, 1, 0) {
+        warn!("Vulkan 1.1 is not available, using the default subgroup size.");
+        data.subgroup_size = None;
+    }
+
+    if data.conditional_rendering && entry.version()? < Version::new(1, 1, 0) {
+        warn!("Vulkan 1.1 is not available, falling back to CPU culling.");
+        data.conditional_rendering = false;
+    }
+
+    let api_version = get_instance_api_version(data);
+
     let application_info = vk::ApplicationInfo::builder()
         .application_name(b"Vulkan Tutorial\0")
         .application_version(vk::make_version(1,0,0))
         .engine_name(b"No engine")
         .engine_version(vk::make_version(1,0,0))
-        .api_version(vk::make_version(1,0,0));
+        .api_version(api_version);
 
     // Layers
 
@@ -604,16 +2831,39 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
         extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
     }
 
+    // Sans cette extension la surface ne propose que des color spaces SDR
+    if data.hdr {
+        let available_extensions = entry
+            .enumerate_instance_extension_properties(None)?
+            .iter()
+            .map(|e| e.extension_name)
+            .collect::<HashSet<_>>();
+
+        if available_extensions.contains(&vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name) {
+            extensions.push(vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name.as_ptr());
+        } else {
+            warn!("HDR color spaces are not supported, falling back to SDR.");
+            data.hdr = false;
+        }
+    }
+
 
     let mut info = vk::InstanceCreateInfo::builder()
         .application_info(&application_info)
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions);
 
+    // Le callback filtre aussi, le masque n'étant pas respecté par toutes les implémentations
+    let min_severity = *MIN_VALIDATION_SEVERITY.get_or_init(get_min_validation_severity);
+    let severities = vk::DebugUtilsMessageSeverityFlagsEXT::all() &
+        vk::DebugUtilsMessageSeverityFlagsEXT::from_bits_truncate(!(min_severity.bits() - 1));
+
+    // Le callback reçoit un pointeur vers la file des messages, qui vit aussi longtemps que AppData
     let mut debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-        .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
+        .message_severity(severities)
         .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
         .user_callback(Some(debug_callback));
+    debug_info.user_data = Arc::as_ptr(&data.validation_messages) as *mut c_void;
 
     if VALIDATION_ENABLED {
         info = info.push_next(&mut debug_info);
@@ -633,19 +2883,69 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
 struct AppData{
     surface: vk::SurfaceKHR,
     messenger: vk::DebugUtilsMessengerEXT,
+    /// The most recent validation messages, filled by `debug_callback`.
+    validation_messages: Arc<Mutex<VecDeque<ValidationMessage>>>,
     physical_device: vk::PhysicalDevice,
+    /// Properties (limits included) and features of `physical_device`, queried once when
+    /// it is picked.
+    physical_device_properties: vk::PhysicalDeviceProperties,
+    physical_device_features: vk::PhysicalDeviceFeatures,
+    /// MSAA samples asked for, `msaa_samples` being clamped to what the device supports.
+    requested_msaa_samples: vk::SampleCountFlags,
     msaa_samples: vk::SampleCountFlags,
+    /// Max anisotropy of the texture sampler (1.0 disables anisotropic filtering).
+    max_anisotropy: f32,
+    /// Addressing of the texture sampler outside of [0, 1], on every axis.
+    address_mode: vk::SamplerAddressMode,
+    /// Whether mipmaps are generated for the texture.
+    mipmaps: bool,
+    presentation_mode: vk::PresentModeKHR,
     graphics_queue: vk::Queue,
     prensentation_queue: vk::Queue,
     swapchain_format: vk::Format,
+    swapchain_color_space: vk::ColorSpaceKHR,
+    /// Whether HDR color spaces may be selected for the swapchain.
+    hdr: bool,
+    /// Format and color space preferred to sRGB for the swapchain.
+    preferred_surface_format: Option<(vk::Format, vk::ColorSpaceKHR)>,
     swapchain_extent: vk::Extent2D,
+    swapchain_usage: vk::ImageUsageFlags,
+    /// Rotation the presentation engine applies to the swapchain images, which the
+    /// projection compensates for (see `get_surface_rotation`).
+    swapchain_transform: vk::SurfaceTransformFlagsKHR,
     swapchain: vk::SwapchainKHR,
     swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
+    /// Whether dynamic rendering is used instead of `render_pass` and `framebuffers`.
+    dynamic_rendering: bool,
+    /// Whether the depth aspect has its own layouts (see `get_depth_layout`).
+    separate_depth_stencil_layouts: bool,
+    /// Whether the models are tested against and write to the depth buffer.
+    depth_test: bool,
+    depth_write: bool,
+    /// Whether the models are blended with their opacity (source alpha, one minus source
+    /// alpha). They are then drawn back to front, since a transparent model writing its
+    /// depth hides the models behind it drawn after it. Intersecting models, and models
+    /// behind others with the depth pre-pass (which only keeps the closest fragments),
+    /// can't be blended correctly.
+    blending: bool,
+    /// Subgroup size required for the compute shaders, if supported.
+    subgroup_size: Option<u32>,
+    /// Whether the draws are skipped with conditional rendering (see `visibility`).
+    conditional_rendering: bool,
     render_pass: vk::RenderPass,
     descriptor_set_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    instanced_pipeline: vk::Pipeline,
+    /// Whether the models are drawn depth only first, then with an `EQUAL` depth test so
+    /// each pixel is shaded once (see `record_depth_prepass_command_buffer`).
+    depth_prepass: bool,
+    /// Depth only pipelines of the pre-pass, null without it.
+    depth_prepass_pipeline: vk::Pipeline,
+    depth_prepass_instanced_pipeline: vk::Pipeline,
+    /// The swapchain state the graphics pipelines were created for (see `get_pipeline_targets`).
+    pipeline_targets: PipelineTargets,
     framebuffers: Vec<vk::Framebuffer>,
     command_pool: vk::CommandPool,
     color_image: vk::Image,
@@ -654,28 +2954,100 @@ struct AppData{
     command_pools: Vec<vk::CommandPool>,
     command_buffers: Vec<vk::CommandBuffer>,
     secondary_command_buffers: Vec<Vec<vk::CommandBuffer>>,
+    depth_prepass_command_buffers: Vec<vk::CommandBuffer>,
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
     in_flight_fences: Vec<vk::Fence>,
     images_in_flight: Vec<vk::Fence>,
-    vertices: Vec<Vertex>,
-    indices: Vec<u32>,
-    vertex_buffer: vk::Buffer,
-    vertex_buffer_memory: vk::DeviceMemory,
-    index_buffer: vk::Buffer,
-    index_buffer_memory: vk::DeviceMemory,
+    /// A mesh per model file, the model `i` drawing the mesh `i % meshes.len()`.
+    meshes: Vec<Mesh>,
+    materials: Vec<Material>,
+    /// Whether the models bind `material_descriptor_sets` instead of pushing their material
+    /// index.
+    material_sets: bool,
+    /// Offset between two materials in `material_buffer`, aligned for the descriptor sets of
+    /// the materials.
+    material_stride: vk::DeviceSize,
+    material_buffer: vk::Buffer,
+    material_buffer_memory: Allocation,
     uniform_buffers: Vec<vk::Buffer>,
-    uniform_buffers_memory: Vec<vk::DeviceMemory>,
+    uniform_buffers_memory: Vec<Allocation>,
+    instance_buffers: Vec<vk::Buffer>,
+    instance_buffers_memory: Vec<Allocation>,
     descriptor_pool: vk::DescriptorPool,
     descriptor_sets: Vec<vk::DescriptorSet>,
+    /// With `material_sets`, the sets of each frame in flight then material, the same as
+    /// `descriptor_sets` but for the material buffer, restricted to the material.
+    material_descriptor_sets: Vec<vk::DescriptorSet>,
     mip_levels: u32,
     texture_image: vk::Image,
     texture_image_memory: vk::DeviceMemory,
+    /// Format of the texture image, the one of the file for KTX2 textures.
+    texture_format: vk::Format,
     texture_image_view: vk::ImageView,
     texture_sampler: vk::Sampler,
+    /// Texture blended with the first one (see `texture`).
+    second_texture: SecondTexture,
+    /// Weight of the second texture, pushed to the fragment shader.
+    texture_blend: f32,
+    /// What the fragment shader of the models outputs, pushed with the texture weight.
+    debug_view: DebugView,
+    /// The attachment the models write their index to, read back to select them.
+    picking: PickingData,
+    /// Whether the frames have a depth buffer, the depth objects being null otherwise.
+    use_depth: bool,
+    /// Format of the depth image, chosen with the physical device.
+    depth_format: vk::Format,
+    /// Whether `depth_format` also has a stencil aspect (`D32_SFLOAT_S8_UINT`,
+    /// `D24_UNORM_S8_UINT`).
+    depth_has_stencil: bool,
+    /// Whether the multisampled depth image can be resolved for `App::read_depth_at` (see
+    /// `depth_resolve`).
+    depth_resolve: bool,
     depth_image: vk::Image,
     depth_image_memory: vk::DeviceMemory,
-    depth_image_view: vk::ImageView
+    depth_image_view: vk::ImageView,
+    /// Title of the window, before what `get_window_title` adds.
+    window_title: String,
+    /// Whether there is no surface, the offscreen image (of `offscreen_image_memory`)
+    /// being the only "swapchain" image (see `headless`).
+    headless: bool,
+    offscreen_image_memory: vk::DeviceMemory,
+    /// Image of the app embedding the renderer used as the offscreen image, by
+    /// `App::render_to_image`.
+    render_target: Option<RenderTarget>,
+    /// Directory the SPIR-V shaders are read from (see `read_shader`).
+    shader_directory: PathBuf,
+    /// Memory of the buffers (the images have their own allocations).
+    allocator: Allocator,
+    /// Source of the uploads to device local memory (see `buffers`).
+    staging: StagingBuffer,
+    /// The texture uploaded at runtime without waiting for it (see `streaming`).
+    streaming: StreamingData,
+    /// Queue the uploads are submitted to (see `transfer`).
+    transfer: TransferData,
+    /// Base color texture referenced by the model, if any.
+    model_texture: Option<PathBuf>,
+    /// Texture actually used, the configured one or else `model_texture` or the default one.
+    texture_path: PathBuf,
+    text: TextData,
+    particles: ParticleData,
+    skybox: SkyboxData,
+    recording: RecordingData,
+    visibility: VisibilityData,
+    capture: CaptureData,
+    gpu_timing: GpuTimingData,
+    /// `LINE` to draw the models in wireframe, if `fill_mode_non_solid` is supported.
+    polygon_mode: vk::PolygonMode,
+    fill_mode_non_solid: bool,
+    /// Number of swapchain images asked for, clamped to the ones supported by the surface.
+    desired_image_count: u32,
+    /// Number of frames in flight, which have their own sync objects, uniform buffer and
+    /// descriptor set.
+    frames_in_flight: usize,
+    /// Whether the device supports anisotropic filtering, `max_anisotropy` staying at 1.0
+    /// otherwise.
+    sampler_anisotropy: bool,
 }
 
 /////// LOGICAL DEVICE ///////
@@ -684,16 +3056,29 @@ unsafe fn create_logical_device(
     instance: &Instance,
     data: &mut AppData,
 ) -> Result<Device> {
-    let extensions = DEVICE_EXTENSIONS
+    let mut extensions = DEVICE_EXTENSIONS
         .iter()
         .map(|n| n.as_ptr())
         .collect::<Vec<_>>();
 
+    if data.subgroup_size.is_some() {
+        extensions.push(vk::EXT_SUBGROUP_SIZE_CONTROL_EXTENSION.name.as_ptr());
+    }
+
+    if data.conditional_rendering {
+        extensions.push(vk::EXT_CONDITIONAL_RENDERING_EXTENSION.name.as_ptr());
+    }
+
+    if data.depth_resolve {
+        extensions.extend(DEPTH_RESOLVE_EXTENSIONS.iter().map(|n| n.as_ptr()));
+    }
+
     let indices = QueueFamilyIndices::get(instance,data, data.physical_device)?;
 
     let mut unique_indices = HashSet::new();
     unique_indices.insert(indices.graphics);
     unique_indices.insert(indices.presentation);
+    unique_indices.insert(indices.transfer);
 
     let queue_priorities = &[1.0];
     let queue_infos = unique_indices
@@ -712,14 +3097,43 @@ unsafe fn create_logical_device(
     };
 
     let features = vk::PhysicalDeviceFeatures::builder()
-        .sampler_anisotropy(true);
+        .sampler_anisotropy(data.sampler_anisotropy)
+        .fill_mode_non_solid(data.fill_mode_non_solid);
 
-    let info = vk::DeviceCreateInfo::builder()
+    let mut info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions)
         .enabled_features(&features);
 
+    // Avec Vulkan13Features dans la chaîne, les features de 1.3 ne peuvent pas être passées à part
+    let mut vulkan_1_3_features = vk::PhysicalDeviceVulkan13Features::builder()
+        .dynamic_rendering(true)
+        .subgroup_size_control(data.subgroup_size.is_some());
+
+    let mut subgroup_size_control_features = vk::PhysicalDeviceSubgroupSizeControlFeaturesEXT::builder()
+        .subgroup_size_control(true);
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut vulkan_1_3_features);
+    } else if data.subgroup_size.is_some() {
+        info = info.push_next(&mut subgroup_size_control_features);
+    }
+
+    let mut vulkan_1_2_features = vk::PhysicalDeviceVulkan12Features::builder()
+        .separate_depth_stencil_layouts(true);
+
+    if data.separate_depth_stencil_layouts {
+        info = info.push_next(&mut vulkan_1_2_features);
+    }
+
+    let mut conditional_rendering_features = vk::PhysicalDeviceConditionalRenderingFeaturesEXT::builder()
+        .conditional_rendering(true);
+
+    if data.conditional_rendering {
+        info = info.push_next(&mut conditional_rendering_features);
+    }
+
     let device = instance.create_device(data.physical_device, &info, None)?;
 
     data.graphics_queue = device.get_device_queue(indices.graphics, 0);
@@ -730,22 +3144,159 @@ unsafe fn create_logical_device(
 
 /////// PHYSICAL DEVICE ///////
 
-unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<()> {
-    for physical_device in  instance.enumerate_physical_devices()? {
+/// Environment variable forcing the physical device, by its index in the order of the
+/// driver (as logged with the ranking), to test a specific GPU.
+const DEVICE_INDEX_VARIABLE: &str = "VK_TUTORIAL_DEVICE_INDEX";
+
+/// Which kind of GPU to pick when several are suitable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum GpuPreference {
+    /// The first suitable device, in the order of the driver.
+    Any,
+    /// A discrete GPU, for performance.
+    PreferDiscrete,
+    /// An integrated GPU, to save battery on laptops.
+    PreferLowPower,
+}
+
+impl GpuPreference {
+    /// Score of a device, the highest score being picked: its type first, then the
+    /// maximum size of its 2D images.
+    fn score(self, properties: &vk::PhysicalDeviceProperties) -> (u32, u32) {
+        let preferred = match self {
+            Self::Any => return (0, 0),
+            Self::PreferDiscrete => vk::PhysicalDeviceType::DISCRETE_GPU,
+            Self::PreferLowPower => vk::PhysicalDeviceType::INTEGRATED_GPU,
+        };
+
+        let type_score = match properties.device_type {
+            t if t == preferred => 3,
+            vk::PhysicalDeviceType::DISCRETE_GPU | vk::PhysicalDeviceType::INTEGRATED_GPU => 2,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 1,
+            _ => 0,
+        };
+
+        (type_score, properties.limits.max_image_dimension_2d)
+    }
+
+    /// Why a device of type `device_type` was picked, for the logs.
+    fn rationale(self, device_type: vk::PhysicalDeviceType) -> &'static str {
+        match (self, device_type) {
+            (Self::Any, _) => "first suitable device",
+            (Self::PreferDiscrete, vk::PhysicalDeviceType::DISCRETE_GPU) =>
+                "discrete GPU preferred for performance",
+            (Self::PreferLowPower, vk::PhysicalDeviceType::INTEGRATED_GPU) =>
+                "integrated GPU preferred to save power",
+            _ => "no suitable device of the preferred type",
+        }
+    }
+}
+
+unsafe fn pick_physical_device(
+    instance: &Instance,
+    data: &mut AppData,
+    preference: GpuPreference,
+) -> Result<()> {
+    let mut candidates = vec![];
+
+    for (index, physical_device) in instance.enumerate_physical_devices()?.into_iter().enumerate() {
         let properties = instance.get_physical_device_properties(physical_device);
 
         if let Err(error) = check_physical_device(instance, data, physical_device) {
-            warn!("Skipping physical device (`{}`): {}", properties.device_name, error);
-        } else {
-            info!("Selected physical device (`{}`).", properties.device_name);
-            data.physical_device = physical_device;
-            data.msaa_samples = get_max_msaa_samples(instance, data);
+            warn!("Skipping physical device {} (`{}`): {}", index, properties.device_name, error);
+            continue;
+        }
 
-            return Ok(());
+        candidates.push((index, physical_device, properties));
+    }
+
+    // Tri stable: à score égal on garde l'ordre du driver
+    candidates.sort_by_key(|(_, _, p)| std::cmp::Reverse(preference.score(p)));
+
+    info!("Suitable physical devices, best first:");
+    for (index, _, properties) in &candidates {
+        info!(
+            "  {}: `{}` ({:?}, 2D images up to {}), score {:?}",
+            index,
+            properties.device_name,
+            properties.device_type,
+            properties.limits.max_image_dimension_2d,
+            preference.score(properties),
+        );
+    }
+
+    let (physical_device, properties, rationale) = match std::env::var(DEVICE_INDEX_VARIABLE) {
+        Ok(value) => {
+            let index = value
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Invalid {} (`{}`).", DEVICE_INDEX_VARIABLE, value))?;
+            let (_, physical_device, properties) = candidates
+                .iter()
+                .find(|(i, _, _)| *i == index)
+                .ok_or_else(|| {
+                    anyhow!(TutorialError::NoSuitableDevice(format!("no device {} ({}).", index, DEVICE_INDEX_VARIABLE)))
+                })?;
+
+            (*physical_device, *properties, format!("forced by {}", DEVICE_INDEX_VARIABLE))
+        }
+        Err(_) => {
+            let (_, physical_device, properties) = candidates
+                .first()
+                .ok_or_else(|| anyhow!(TutorialError::NoSuitableDevice("none found.".to_string())))?;
+
+            (*physical_device, *properties, preference.rationale(properties.device_type).to_string())
+        }
+    };
+
+    info!(
+        "Selected physical device (`{}`, {:?}): {}.",
+        properties.device_name,
+        properties.device_type,
+        rationale,
+    );
+
+    data.physical_device = physical_device;
+    data.physical_device_properties = properties;
+    data.physical_device_features = instance.get_physical_device_features(physical_device);
+    // Le device ne change pas: le format est choisi une fois, pas à chaque swapchain
+    data.depth_format = get_depth_format(instance, data)?;
+    data.depth_has_stencil = get_depth_aspect_mask(data.depth_format).contains(vk::ImageAspectFlags::STENCIL);
+    data.fill_mode_non_solid = data.physical_device_features.fill_mode_non_solid == vk::TRUE;
+    data.sampler_anisotropy = data.physical_device_features.sampler_anisotropy == vk::TRUE;
+
+    if data.dynamic_rendering && !check_dynamic_rendering(instance, data) {
+        warn!("Dynamic rendering is not supported, falling back to render passes.");
+        data.dynamic_rendering = false;
+    }
+
+    if let Some(requested) = data.subgroup_size {
+        data.subgroup_size = get_subgroup_size(instance, physical_device, requested)?;
+
+        match data.subgroup_size {
+            Some(size) => info!("Compute shaders use a subgroup size of {}.", size),
+            None => warn!("Subgroup size control is not supported, using the default subgroup size."),
+        }
+    }
+
+    if data.conditional_rendering && !check_conditional_rendering(instance, physical_device)? {
+        warn!("Conditional rendering is not supported, falling back to CPU culling.");
+        data.conditional_rendering = false;
+    }
+
+    data.depth_resolve = check_depth_resolve(instance, physical_device)?;
+
+    if data.separate_depth_stencil_layouts {
+        if !check_separate_depth_stencil_layouts(instance, data) {
+            warn!("Separate depth/stencil layouts are not supported, using combined layouts.");
+            data.separate_depth_stencil_layouts = false;
+        } else if !data.dynamic_rendering && data.depth_has_stencil {
+            // Il faudrait VkAttachmentDescriptionStencilLayout, qui n'existe qu'avec les render pass 2
+            warn!("Combined depth/stencil formats need dynamic rendering for separate layouts.");
+            data.separate_depth_stencil_layouts = false;
         }
     }
 
-    Err(anyhow!("Failed to find suitable physical device."))
+    Ok(())
 }
 
 unsafe fn check_physical_device(
@@ -753,18 +3304,44 @@ unsafe fn check_physical_device(
     data: &AppData,
     physical_device: vk::PhysicalDevice,
 ) -> Result<()> {
+    // Toutes les exigences sont vérifiées pour les lister ensemble, seules les erreurs
+    // Vulkan arrêtent la vérification
+    let mut missing = vec![];
+    let mut check = |result: Result<()>| match result {
+        Err(e) => match e.downcast::<SuitabilityError>() {
+            Ok(SuitabilityError(requirements)) => {
+                missing.extend(requirements);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Ok(()) => Ok(()),
+    };
 
-    QueueFamilyIndices::get(instance, data, physical_device)?;
-    check_physical_device_extensions(instance, physical_device)?;
+    check(QueueFamilyIndices::get(instance, data, physical_device).map(|_| ()))?;
+    check(check_physical_device_extensions(instance, physical_device))?;
 
     let support = SwapchainSupport::get(instance, data, physical_device)?;
-    if support.formats.is_empty() || support.presentation_modes.is_empty() {
-        return Err(anyhow!(SuitabilityError("Insufficient swapchain support.")));
+    if !data.headless && support.formats.is_empty() {
+        missing.push("surface formats".to_string());
+    }
+    if !data.headless && support.presentation_modes.is_empty() {
+        missing.push("presentation modes".to_string());
+    }
+
+    if !missing.is_empty() {
+        return Err(anyhow!(SuitabilityError(missing)));
     }
 
+    // Optionnels: sans, les textures sont filtrées sans anisotropie et le mode fil de fer
+    // n'est simplement pas disponible
     let features = instance.get_physical_device_features(physical_device);
     if features.sampler_anisotropy != vk::TRUE {
-        return Err(anyhow!(SuitabilityError("No sampler anisotropy.")));
+        info!("Physical device without anisotropic filtering (no `samplerAnisotropy`).");
+    }
+
+    if features.fill_mode_non_solid != vk::TRUE {
+        info!("Physical device without wireframe support (no `fillModeNonSolid`).");
     }
 
     Ok(())
@@ -780,10 +3357,16 @@ unsafe fn check_physical_device_extensions(
         .map(|e| e.extension_name)
         .collect::<HashSet<_>>();
 
-    if DEVICE_EXTENSIONS.iter().all(|e| extensions.contains(e)) {
+    let missing = DEVICE_EXTENSIONS
+        .iter()
+        .filter(|e| !extensions.contains(e))
+        .map(|e| format!("device extension `{}`", e))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
         Ok(())
-    }else {
-        Err(anyhow!(SuitabilityError("Missing required device extensions.")))
+    } else {
+        Err(anyhow!(SuitabilityError(missing)))
     }
 }
 
@@ -802,6 +3385,15 @@ impl SwapchainSupport {
         data: &AppData,
         physical_device: vk::PhysicalDevice,
     ) -> Result<Self> {
+        // Sans surface, rien n'est jamais présenté
+        if data.headless {
+            return Ok(Self {
+                capabilities: vk::SurfaceCapabilitiesKHR::default(),
+                formats: vec![],
+                presentation_modes: vec![vk::PresentModeKHR::FIFO],
+            });
+        }
+
         Ok(Self {
             capabilities: instance
                 .get_physical_device_surface_capabilities_khr(
@@ -830,21 +3422,59 @@ unsafe fn create_swapchain(
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
+    if data.headless {
+        return create_offscreen_swapchain(window, instance, device, data);
+    }
+
     let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
     let support = SwapchainSupport::get(instance, data, data.physical_device)?;
 
-    let surface_format = get_swapchain_surface_format(&support.formats);
-    let presentation_mode = get_swapchain_presentation_mode(&support.presentation_modes);
+    let surface_format = get_swapchain_surface_format(&support.formats, data.hdr, data.preferred_surface_format);
+    // Le format préféré peut ne pas être supporté
+    if (surface_format.format, surface_format.color_space) != (data.swapchain_format, data.swapchain_color_space) {
+        info!("Swapchain format: {:?} ({:?}).", surface_format.format, surface_format.color_space);
+    }
+    let presentation_mode = get_swapchain_presentation_mode(
+        &support.presentation_modes,
+        data.presentation_mode,
+    );
     let extent = get_swapchain_extent(window, support.capabilities);
 
+    // La fenêtre est en pixels logiques, la swapchain en pixels physiques
+    let size = window.inner_size();
+    let scale_factor = window.scale_factor();
+    let logical_size = size.to_logical::<f64>(scale_factor);
+    info!(
+        "Swapchain extent: {}x{} (window: {}x{} logical, {}x{} physical, scale factor {}).",
+        extent.width,
+        extent.height,
+        logical_size.width,
+        logical_size.height,
+        size.width,
+        size.height,
+        scale_factor,
+    );
+
     data.swapchain_format = surface_format.format;
+    data.swapchain_color_space = surface_format.color_space;
     data.swapchain_extent = extent;
 
-    let mut image_count = support.capabilities.min_image_count + 1;
-    if support.capabilities.max_image_count != 0
-        && image_count > support.capabilities.max_image_count
-    {
-        image_count = support.capabilities.max_image_count;
+    let transform = support.capabilities.current_transform;
+    if transform != data.swapchain_transform {
+        info!("Surface transform: {:?}.", transform);
+    }
+    data.swapchain_transform = transform;
+
+    // max_image_count à 0: pas de limite
+    let mut image_count = data.desired_image_count.max(support.capabilities.min_image_count);
+    if support.capabilities.max_image_count != 0 {
+        image_count = image_count.min(support.capabilities.max_image_count);
+    }
+
+    // TRANSFER_SRC pour les captures, si la surface le permet
+    data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    if support.capabilities.supported_usage_flags.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+        data.swapchain_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
     }
 
     let mut queue_family_indices = vec![];
@@ -863,11 +3493,12 @@ unsafe fn create_swapchain(
         .image_color_space(surface_format.color_space)
         .image_extent(extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(data.swapchain_usage)
         .image_sharing_mode(image_sharing_mode)
         .queue_family_indices(&queue_family_indices)
-        // Veut dire qu'on ne souhaite pas appliquer de transformation
-        .pre_transform(support.capabilities.current_transform)
+        // La transformation courante de la surface: le compositeur n'a rien à faire,
+        // c'est la projection qui fait la rotation
+        .pre_transform(transform)
         //Spécifie si le channel alpha (aucune idée de ce que c'est)
         // doit être utilisé pour se fondre avec les autres fenêtre
         // Ici non
@@ -879,9 +3510,22 @@ unsafe fn create_swapchain(
         // Pour l'instant on ne fait rien
         .old_swapchain(vk::SwapchainKHR::null());
 
-    data.swapchain = device.create_swapchain_khr(&info, None)?;
+    // Certains drivers annoncent MAILBOX mais échouent à créer la swapchain avec:
+    // FIFO est toujours disponible
+    data.swapchain = match device.create_swapchain_khr(&info, None) {
+        Ok(swapchain) => swapchain,
+        Err(e) if presentation_mode != vk::PresentModeKHR::FIFO => {
+            warn!("Could not create the swapchain in {:?} ({}), falling back to FIFO.", presentation_mode, e);
+
+            // Gardé pour le titre et les prochaines recréations
+            data.presentation_mode = vk::PresentModeKHR::FIFO;
+            device.create_swapchain_khr(&info.present_mode(vk::PresentModeKHR::FIFO), None)?
+        }
+        Err(e) => return Err(e.into()),
+    };
     data.swapchain_images = device.get_swapchain_images_khr(data.swapchain)?;
 
+    window.set_title(&get_window_title(data));
 
     Ok(())
 }
@@ -905,107 +3549,339 @@ unsafe fn create_swapchain_image_views(
         })
         .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(())
-}
+    Ok(())
+}
+
+/// Picks HDR10 or else scRGB (linear extended sRGB) if `hdr`, and otherwise `preferred` or
+/// else sRGB (or else the first format).
+fn get_swapchain_surface_format(
+    formats: &[vk::SurfaceFormatKHR],
+    hdr: bool,
+    preferred: Option<(vk::Format, vk::ColorSpaceKHR)>,
+) -> vk::SurfaceFormatKHR {
+    let hdr_formats = [
+        (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+        (vk::Format::A2R10G10B10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+        (vk::Format::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+    ];
+
+    let hdr_format = hdr_formats
+        .iter()
+        .filter(|_| hdr)
+        .find_map(|(format, color_space)| {
+            formats
+                .iter()
+                .cloned()
+                .find(|f| f.format == *format && f.color_space == *color_space)
+        });
+
+    let find = |(format, color_space): (vk::Format, vk::ColorSpaceKHR)| {
+        formats
+            .iter()
+            .cloned()
+            .find(|f| f.format == format && f.color_space == color_space)
+    };
+
+    hdr_format
+        .or_else(|| preferred.and_then(find))
+        .or_else(|| find((vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR)))
+        .unwrap_or_else(|| formats[0])
+}
+
+/// The window title, with the HDR color space if one is used.
+fn get_window_title(data: &AppData) -> String {
+    if data.swapchain_color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
+        data.window_title.clone()
+    } else {
+        format!("{} (HDR {:?})", data.window_title, data.swapchain_color_space)
+    }
+}
+
+/// Value of the `OUTPUT_TRANSFER` specialization constant of the fragment shaders:
+/// 1 to encode the output with the PQ curve of HDR10, 2 to encode it to sRGB (for a UNORM
+/// format in the sRGB color space), 0 to output linear colors (encoded by the swapchain
+/// format for sRGB, as is for scRGB).
+fn get_output_transfer(data: &AppData) -> u32 {
+    let srgb_formats = [
+        vk::Format::B8G8R8A8_SRGB,
+        vk::Format::R8G8B8A8_SRGB,
+        vk::Format::A8B8G8R8_SRGB_PACK32,
+    ];
+
+    if data.swapchain_color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT {
+        1
+    } else if data.swapchain_color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        && !srgb_formats.contains(&data.swapchain_format)
+    {
+        2
+    } else {
+        0
+    }
+}
+
+/// Picks `preferred` if supported, FIFO otherwise (which is always available).
+fn get_swapchain_presentation_mode(
+    presentation_mode: &[vk::PresentModeKHR],
+    preferred: vk::PresentModeKHR,
+) -> vk::PresentModeKHR {
+    presentation_mode
+        .iter()
+        .cloned()
+        .find(|m| *m == preferred)
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
+/// The size of the swapchain images in physical pixels, clamped to what the surface supports
+/// and at least 1×1 (a minimized window can report 0×0).
+fn get_swapchain_extent(
+    window: &Window,
+    capabilities: vk::SurfaceCapabilitiesKHR,
+) -> vk::Extent2D {
+    // current_extent est la taille de la surface (celle de l'écran en plein écran), mais peut
+    // être en retard d'un événement sur un changement de moniteur: on la borne aussi
+    let size = if capabilities.current_extent.width != u32::MAX {
+        capabilities.current_extent
+    } else {
+        // La taille de la fenêtre est dans l'orientation de l'écran, les images dans celle de la surface
+        let mut size = window.inner_size();
+        if matches!(get_surface_rotation(capabilities.current_transform), 90 | 270) {
+            (size.width, size.height) = (size.height, size.width);
+        }
+
+        vk::Extent2D { width: size.width, height: size.height }
+    };
+
+    let clamp = |min: u32, max: u32, v: u32| min.max(max.min(v));
+    let extent = vk::Extent2D::builder()
+        .width(clamp(
+            capabilities.min_image_extent.width,
+            capabilities.max_image_extent.width,
+            size.width,
+        ))
+        .height(clamp(
+            capabilities.min_image_extent.height,
+            capabilities.max_image_extent.height,
+            size.height
+        ))
+        .build();
+
+    vk::Extent2D { width: extent.width.max(1), height: extent.height.max(1) }
+}
+
+/// The clockwise rotation (in degrees) of `transform`, the mirrored transforms being
+/// handled like their rotation alone.
+fn get_surface_rotation(transform: vk::SurfaceTransformFlagsKHR) -> u32 {
+    if transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_90
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_90,
+    ) {
+        90
+    } else if transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_180
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_180,
+    ) {
+        180
+    } else if transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_270
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_270,
+    ) {
+        270
+    } else {
+        0
+    }
+}
+
+/////// FULLSCREEN ///////
+
+/// Index, among `window.available_monitors()`, of the monitor the window is on.
+fn get_current_monitor_index(window: &Window) -> usize {
+    let current = window.current_monitor();
+    window
+        .available_monitors()
+        .position(|m| Some(&m) == current.as_ref())
+        .unwrap_or(0)
+}
+
+/// Monitor selected for fullscreen, falling back to the window's current one
+/// if it has been unplugged since it was selected.
+fn get_selected_monitor(window: &Window, app: &App) -> Option<MonitorHandle> {
+    window
+        .available_monitors()
+        .nth(app.monitor)
+        .or_else(|| window.current_monitor())
+}
+
+fn get_fullscreen(monitor: MonitorHandle, exclusive: bool) -> Option<Fullscreen> {
+    if exclusive {
+        // Plus grande résolution, puis plus grand taux de rafraichissement
+        monitor
+            .video_modes()
+            .max_by_key(|m| (m.size().width * m.size().height, m.refresh_rate_millihertz()))
+            .map(Fullscreen::Exclusive)
+    } else {
+        Some(Fullscreen::Borderless(Some(monitor)))
+    }
+}
+
+/// Switches between windowed and (borderless or exclusive) fullscreen on the selected monitor.
+fn toggle_fullscreen(window: &Window, app: &mut App, exclusive: bool) {
+    let fullscreen = if window.fullscreen().is_some() {
+        None
+    } else {
+        get_selected_monitor(window, app).and_then(|m| get_fullscreen(m, exclusive))
+    };
+
+    window.set_fullscreen(fullscreen);
+    app.resized = true;
+}
+
+/// Selects the next available monitor, moving the window there if it is already fullscreen.
+fn select_next_monitor(window: &Window, app: &mut App) {
+    let monitors = window.available_monitors().collect::<Vec<_>>();
+    if monitors.is_empty() {
+        return;
+    }
+
+    app.monitor = (app.monitor + 1) % monitors.len();
+    let monitor = monitors[app.monitor].clone();
+    info!(
+        "Selected monitor {}/{} (`{}`).",
+        app.monitor + 1,
+        monitors.len(),
+        monitor.name().unwrap_or_default(),
+    );
+
+    if let Some(fullscreen) = window.fullscreen() {
+        let exclusive = matches!(fullscreen, Fullscreen::Exclusive(_));
+        window.set_fullscreen(get_fullscreen(monitor, exclusive));
+        app.resized = true;
+    }
+}
+
+/////// PIPELINE ///////
+unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    // matrice model + index du modèle (voir `picking`)
+    let vert_push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(68);
+
+    // opacity + index du matériau + poids de la deuxième texture + modèle sélectionné + vue de debug
+    let frag_push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(68)
+        .size(20);
+
+    let set_layouts = &[data.descriptor_set_layout];
+    let push_constant_ranges = &[vert_push_constant_range, frag_push_constant_range];
+
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
 
-fn get_swapchain_surface_format(
-    formats: &[vk::SurfaceFormatKHR],
-) -> vk::SurfaceFormatKHR {
-    formats
-        .iter()
-        .cloned()
-        .find(|f| {
-            f.format == vk::Format::B8G8R8A8_SRGB
-                && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        })
-        .unwrap_or_else(|| formats[0])
-}
+    let vert = read_shader(data, "vert.spv")?;
+    let instanced_vert = read_shader(data, "instanced_vert.spv")?;
+    let frag = read_shader(data, "frag.spv")?;
 
-fn get_swapchain_presentation_mode(
-    presentation_mode: &[vk::PresentModeKHR],
-) -> vk::PresentModeKHR {
-    presentation_mode
-        .iter()
-        .cloned()
-        .find(|m| *m == vk::PresentModeKHR::MAILBOX)
-        .unwrap_or(vk::PresentModeKHR::FIFO)
-}
+    data.pipeline = create_graphics_pipeline(device, data, &vert[..], Some(&frag[..]), false)?;
+    data.instanced_pipeline = create_graphics_pipeline(device, data, &instanced_vert[..], Some(&frag[..]), true)?;
 
-fn get_swapchain_extent(
-    window: &Window,
-    capabilities: vk::SurfaceCapabilitiesKHR,
-) -> vk::Extent2D {
-    if capabilities.current_extent.width != u32::max_value() {
-        capabilities.current_extent
+    // Les mêmes vertex shaders, pour que les profondeurs soient identiques (test EQUAL)
+    if data.depth_prepass {
+        data.depth_prepass_pipeline = create_graphics_pipeline(device, data, &vert[..], None, false)?;
+        data.depth_prepass_instanced_pipeline = create_graphics_pipeline(device, data, &instanced_vert[..], None, true)?;
     } else {
-        let size = window.inner_size();
-        let clamp = |min: u32, max: u32, v: u32| min.max(max.min(v));
-        vk::Extent2D::builder()
-            .width(clamp(
-                capabilities.min_image_extent.width,
-                capabilities.max_image_extent.width,
-                size.width,
-            ))
-            .height(clamp(
-                capabilities.min_image_extent.height,
-                capabilities.max_image_extent.height,
-                size.height
-            ))
-            .build()
+        data.depth_prepass_pipeline = vk::Pipeline::null();
+        data.depth_prepass_instanced_pipeline = vk::Pipeline::null();
     }
+
+    data.pipeline_targets = get_pipeline_targets(data);
+
+    Ok(())
 }
 
-/////// PIPELINE ///////
-unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
-    let vert = include_bytes!("../shaders/vert.spv");
-    let frag = include_bytes!("../shaders/frag.spv");
+/// The formats and sample count of the attachments, and the output transfer.
+type PipelineTargets = (vk::Format, vk::Format, vk::SampleCountFlags, u32);
+
+/// What the graphics pipelines depend on in the swapchain, to recreate them with it only
+/// when it changes (the extent is dynamic state).
+fn get_pipeline_targets(data: &AppData) -> PipelineTargets {
+    (data.swapchain_format, get_depth_attachment_format(data), data.msaa_samples, get_output_transfer(data))
+}
+
+/// The graphics pipelines using `data.pipeline_layout`, null ones included.
+fn get_graphics_pipelines(data: &AppData) -> [vk::Pipeline; 4] {
+    [
+        data.pipeline,
+        data.instanced_pipeline,
+        data.depth_prepass_pipeline,
+        data.depth_prepass_instanced_pipeline,
+    ]
+}
 
-    let vert_shader_module = create_shader_module(device, &vert[..])?;
-    let frag_shader_module = create_shader_module(device, &frag[..])?;
+/// Creates a pipeline using `data.pipeline_layout`, reading the model matrix
+/// from a per-instance vertex buffer instead of a push constant if `instanced` is set.
+///
+/// Without `frag`, the pipeline only writes the depth, for the depth pre-pass.
+unsafe fn create_graphics_pipeline(
+    device: &Device,
+    data: &AppData,
+    vert: &[u8],
+    frag: Option<&[u8]>,
+    instanced: bool,
+) -> Result<vk::Pipeline> {
+    let vert_shader_module = create_shader_module(device, vert)?;
+    let frag_shader_module = frag.map(|f| create_shader_module(device, f)).transpose()?;
 
     let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
         .module(vert_shader_module)
         .name(b"main\0");
 
-    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
-        .stage(vk::ShaderStageFlags::FRAGMENT)
-        .module(frag_shader_module)
-        .name(b"main\0");
+    let output_transfer = get_output_transfer(data).to_ne_bytes();
+    let map_entries = &[vk::SpecializationMapEntry::builder()
+        .constant_id(0)
+        .offset(0)
+        .size(size_of::<u32>())];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&output_transfer);
+
+    let frag_stage = frag_shader_module.map(|module| {
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(module)
+            .name(b"main\0")
+            .specialization_info(&specialization_info)
+    });
+
+    let mut binding_descriptions = vec![Vertex::binding_description()];
+    let mut attribute_descriptions = Vertex::attribute_description().to_vec();
 
-    let binding_descriptions = &[Vertex::binding_description()];
-    let attribute_descriptions = Vertex::attribute_description();
+    if instanced {
+        binding_descriptions.push(InstanceData::binding_description());
+        attribute_descriptions.extend_from_slice(&InstanceData::attribute_description());
+    }
 
     let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
-        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_binding_descriptions(&binding_descriptions)
         .vertex_attribute_descriptions(&attribute_descriptions);
 
     let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
         .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
         .primitive_restart_enable(false);
 
-    let viewport = vk::Viewport::builder()
-        .x(0.0)
-        .y(0.0)
-        .width(data.swapchain_extent.width as f32)
-        .height(data.swapchain_extent.height as f32)
-        .min_depth(0.0)
-        .max_depth(1.0);
-
-    let scissor = vk::Rect2D::builder()
-        .offset(vk::Offset2D {x: 0, y: 0})
-        .extent(data.swapchain_extent);
-
-    let viewports = &[viewport];
-    let scissors = &[scissor];
+    // Le viewport et le scissor sont dynamiques (voir `cmd_set_viewport_and_scissor`)
     let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-        .viewports(viewports)
-        .scissors(scissors);
+        .viewport_count(1)
+        .scissor_count(1);
 
     let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
         .depth_clamp_enable(false)
         .rasterizer_discard_enable(false)
-        .polygon_mode(vk::PolygonMode::FILL)
+        .polygon_mode(data.polygon_mode)
         .line_width(1.0)
         .cull_mode(vk::CullModeFlags::BACK)
         .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
@@ -1016,17 +3892,30 @@ unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
         .sample_shading_enable(false)
         .rasterization_samples(data.msaa_samples);
 
+    // Après la pre-pass, seuls les fragments à la profondeur déjà écrite passent, sans
+    // la réécrire
+    let (depth_test, depth_write, depth_compare_op) = match frag {
+        None => (true, true, vk::CompareOp::LESS),
+        Some(_) if data.depth_prepass => (data.depth_test, false, vk::CompareOp::EQUAL),
+        Some(_) => (data.depth_test, data.depth_write, vk::CompareOp::LESS),
+    };
+
+    // Ignorés s'ils font partie des états dynamiques (voir `cmd_set_depth_state`)
     let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_test_enable(depth_test)
+        .depth_write_enable(depth_write)
+        .depth_compare_op(depth_compare_op)
         .depth_bounds_test_enable(false)
         .stencil_test_enable(false);
 
 
     let attachment = vk::PipelineColorBlendAttachmentState::builder()
-        .color_write_mask(vk::ColorComponentFlags::all())
-        .blend_enable(true)
+        .color_write_mask(if frag.is_some() {
+            vk::ColorComponentFlags::all()
+        } else {
+            vk::ColorComponentFlags::empty()
+        })
+        .blend_enable(frag.is_some() && data.blending)
         .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
         .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
         .color_blend_op(vk::BlendOp::ADD)
@@ -1034,35 +3923,25 @@ unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
         .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
         .alpha_blend_op(vk::BlendOp::ADD);
 
-    let attachments = &[attachment];
+    // La pre-pass n'écrit pas non plus les indices des modèles
+    let attachments = &[attachment.build(), get_picking_blend_attachment(frag.is_some())];
     let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
         .logic_op_enable(false)
         .logic_op(vk::LogicOp::COPY)
         .attachments(attachments)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    let vert_push_constant_range = vk::PushConstantRange::builder()
-        .stage_flags(vk::ShaderStageFlags::VERTEX)
-        .offset(0)
-        .size(64);
-
-    let frag_push_constant_range = vk::PushConstantRange::builder()
-        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-        .offset(64)
-        .size(4);
-
-    let set_layouts = &[data.descriptor_set_layout];
-    let push_constant_ranges = &[vert_push_constant_range, frag_push_constant_range];
-
-    let layout_info = vk::PipelineLayoutCreateInfo::builder()
-        .set_layouts(set_layouts)
-        .push_constant_ranges(push_constant_ranges);
-
-    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+    // La pre-pass garde son état de profondeur
+    let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    if has_dynamic_depth_state(data) && frag.is_some() {
+        dynamic_states.extend([vk::DynamicState::DEPTH_TEST_ENABLE, vk::DynamicState::DEPTH_WRITE_ENABLE]);
+    }
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+        .dynamic_states(&dynamic_states);
 
-    let stages = &[vert_stage, frag_stage];
-    let info = vk::GraphicsPipelineCreateInfo::builder()
-        .stages(stages)
+    let stages = std::iter::once(vert_stage).chain(frag_stage).collect::<Vec<_>>();
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
         .vertex_input_state(&vertex_input_state) //Fixed function stage
         .input_assembly_state(&input_assembly_state)
         .viewport_state(&viewport_state)
@@ -1070,19 +3949,32 @@ unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
         .multisample_state(&multisample_state)
         .depth_stencil_state(&depth_stencil_state)
         .color_blend_state(&color_blend_state) // Fin fixed function stage
+        .dynamic_state(&dynamic_state)
         .layout(data.pipeline_layout)
         .render_pass(data.render_pass)
         .subpass(0);
 
+    // Sans render pass, le pipeline doit connaître les formats des attachments
+    let color_attachment_formats = &get_color_attachment_formats(data);
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_attachment_format(data));
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
     //Peut créer plusieurs pipeline
-    data.pipeline = device.create_graphics_pipelines(
+    let pipeline = device.create_graphics_pipelines(
         vk::PipelineCache::null(), &[info], None)?.0;
 
 
     device.destroy_shader_module(vert_shader_module, None);
-    device.destroy_shader_module(frag_shader_module, None);
+    if let Some(frag_shader_module) = frag_shader_module {
+        device.destroy_shader_module(frag_shader_module, None);
+    }
 
-    Ok(())
+    Ok(pipeline)
 }
 
 /////// RENDER PASS ///////
@@ -1091,6 +3983,14 @@ unsafe fn create_render_pass(
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
+    // Le dynamic rendering décrit les attachments au moment de dessiner
+    if data.dynamic_rendering {
+        return Ok(());
+    }
+
+    // Sans MSAA on dessine directement dans l'image de la swapchain, sans resolve
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+
     //Réprésente le seul color buffer attachment qu'on utilisera (représenté par une image de la swapchain)
     let color_attachment = vk::AttachmentDescription::builder()
         .format(data.swapchain_format)
@@ -1105,7 +4005,11 @@ unsafe fn create_render_pass(
         .initial_layout(vk::ImageLayout::UNDEFINED)
         //Indique que l'image sera présenté à la swapchain
         //Final layout = le layout vers lesquel transitionné après la render_pass
-        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        .final_layout(if multisampled {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        } else {
+            get_target_layout(data)
+        });
 
     let color_resolve_attachment = vk::AttachmentDescription::builder()
         .format(data.swapchain_format)
@@ -1115,17 +4019,18 @@ unsafe fn create_render_pass(
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        .final_layout(get_target_layout(data));
 
     let depth_stencil_attachment = vk::AttachmentDescription::builder()
-        .format(get_depth_format(instance, data)?)
+        .format(data.depth_format)
         .samples(data.msaa_samples)
         .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+        // Gardé pour pouvoir relire la profondeur (read_depth_at)
+        .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        .final_layout(get_depth_layout(data));
 
     // SUBPASS
     let color_attachment_ref = vk::AttachmentReference::builder()
@@ -1135,54 +4040,420 @@ unsafe fn create_render_pass(
 
     let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
         .attachment(1)
-        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        .layout(get_depth_layout(data));
+
+    // Sans profondeur la résolution prend l'index de l'attachment de profondeur
+    let color_resolve_index = if data.use_depth { 2 } else { 1 };
+    let color_resolve_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(color_resolve_index)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    // Les indices des modèles (voir `picking`) suivent, résolus eux aussi avec MSAA
+    let picking_index = if multisampled { color_resolve_index + 1 } else { color_resolve_index };
+    let picking_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(picking_index)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let picking_resolve_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(picking_index + 1)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[color_attachment_ref, picking_attachment_ref];
+    let resolve_attachments = &[color_resolve_attachment_ref, picking_resolve_attachment_ref];
+
+    let mut subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments);
+
+    if data.use_depth {
+        subpass = subpass.depth_stencil_attachment(&depth_stencil_attachment_ref);
+    }
+
+    if multisampled {
+        subpass = subpass.resolve_attachments(resolve_attachments);
+    }
+
+    // RENDER PASS
+
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+        )
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+        )
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+
+    let mut attachments = vec![color_attachment.build()];
+    if data.use_depth {
+        attachments.push(depth_stencil_attachment.build());
+    }
+    if multisampled {
+        attachments.push(color_resolve_attachment.build());
+    }
+    attachments.extend(get_picking_attachment_descriptions(data));
+
+    let subpasses = &[subpass];
+    let dependencies = &[dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    data.render_pass = device.create_render_pass(&info, None)?;
+
+    Ok(())
+}
+
+
+/////// DYNAMIC RENDERING ///////
+
+/// Whether the picked device supports Vulkan 1.3 with the dynamic rendering feature.
+unsafe fn check_dynamic_rendering(instance: &Instance, data: &AppData) -> bool {
+    if Version::from(data.physical_device_properties.api_version) < Version::new(1, 3, 0) {
+        return false;
+    }
+
+    let mut vulkan_1_3_features = vk::PhysicalDeviceVulkan13Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut vulkan_1_3_features);
+    instance.get_physical_device_features2(data.physical_device, &mut features);
+
+    vulkan_1_3_features.dynamic_rendering == vk::TRUE
+}
+
+/// Builds a barrier moving the whole `image` to `new_layout`.
+fn get_attachment_barrier(
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .build()
+}
+
+/// Begins the main pass into the swapchain image `image_index`, cleared to `clear_color`,
+/// with the render pass or dynamic rendering. Its content is recorded in secondary command
+/// buffers (`begin_secondary_command_buffer`).
+unsafe fn cmd_begin_main_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    clear_color: [f32; 4],
+) {
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain_extent);
+
+    let color_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: clear_color,
+        },
+    };
+
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    };
+
+    if data.dynamic_rendering {
+        begin_dynamic_rendering(
+            device,
+            data,
+            command_buffer,
+            image_index,
+            color_clear_value,
+            depth_clear_value,
+        );
+    } else {
+        // Une valeur par attachment, ignorée pour ceux qui ne sont pas effacés
+        let mut clear_values = vec![color_clear_value];
+        if data.use_depth {
+            clear_values.push(depth_clear_value);
+        }
+        if data.msaa_samples != vk::SampleCountFlags::_1 {
+            clear_values.push(color_clear_value);
+        }
+        clear_values.push(get_picking_clear_value());
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(data.render_pass)
+            .framebuffer(data.framebuffers[image_index])
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &info,
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+        );
+    }
+}
+
+/// Ends the main pass begun by `cmd_begin_main_pass`.
+unsafe fn cmd_end_main_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    if data.dynamic_rendering {
+        end_dynamic_rendering(device, data, command_buffer, image_index);
+    } else {
+        device.cmd_end_render_pass(command_buffer);
+    }
+}
+
+/// Equivalent of beginning the render pass: transitions the attachments (which the
+/// render pass did through its initial layouts) then begins rendering into them.
+unsafe fn begin_dynamic_rendering(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    color_clear_value: vk::ClearValue,
+    depth_clear_value: vk::ClearValue,
+) {
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+    let swapchain_image = data.swapchain_images[image_index];
+    let swapchain_image_view = data.swapchain_image_views[image_index];
+
+    // UNDEFINED: le contenu de la frame précédente est jeté, comme avec la render pass
+    let mut barriers = vec![
+        get_attachment_barrier(
+            swapchain_image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        ),
+    ];
+
+    // Avec des layouts séparés, le stencil (inutilisé) n'a pas besoin de transition
+    if data.use_depth {
+        barriers.push(get_attachment_barrier(
+            data.depth_image,
+            get_depth_barrier_aspect_mask(data),
+            vk::ImageLayout::UNDEFINED,
+            get_depth_layout(data),
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ));
+    }
+
+    barriers.extend(get_picking_barriers(data));
+
+    if multisampled {
+        barriers.push(get_attachment_barrier(
+            data.color_image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        ));
+    }
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+
+    // Avec MSAA on dessine dans color_image, résolue dans l'image de la swapchain
+    let mut color_attachment = vk::RenderingAttachmentInfo::builder()
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(color_clear_value);
+
+    color_attachment = if multisampled {
+        color_attachment
+            .image_view(data.color_image_view)
+            .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+            .resolve_image_view(swapchain_image_view)
+            .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+    } else {
+        color_attachment.image_view(swapchain_image_view)
+    };
+
+    let depth_attachment = vk::RenderingAttachmentInfo::builder()
+        .image_view(data.depth_image_view)
+        .image_layout(get_depth_layout(data))
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        // Gardé pour pouvoir relire la profondeur (read_depth_at)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(depth_clear_value);
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain_extent);
+
+    let color_attachments = &[color_attachment.build(), get_picking_rendering_attachment(data)];
+    let mut info = vk::RenderingInfo::builder()
+        .flags(vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS)
+        .render_area(render_area)
+        .layer_count(1)
+        .color_attachments(color_attachments);
+
+    if data.use_depth {
+        info = info.depth_attachment(&depth_attachment);
+    }
+
+    device.cmd_begin_rendering(command_buffer, &info);
+}
+
+/// Equivalent of ending the render pass: ends rendering then transitions the
+/// swapchain image for presentation, or for sampling with a `RenderTarget` (the render pass
+/// did it through its final layout).
+unsafe fn end_dynamic_rendering(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    device.cmd_end_rendering(command_buffer);
+
+    let barrier = get_attachment_barrier(
+        data.swapchain_images[image_index],
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        get_target_layout(data),
+        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        vk::AccessFlags::empty(),
+    );
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+}
+
+/////// COMPUTE ///////
+
+/// The closest subgroup size to `requested` which compute shaders can require on the
+/// device, `None` if it can't require one.
+unsafe fn get_subgroup_size(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    requested: u32,
+) -> Result<Option<u32>> {
+    let extensions = instance
+        .enumerate_device_extension_properties(physical_device, None)?
+        .iter()
+        .map(|e| e.extension_name)
+        .collect::<HashSet<_>>();
+
+    if !extensions.contains(&vk::EXT_SUBGROUP_SIZE_CONTROL_EXTENSION.name) {
+        return Ok(None);
+    }
+
+    let mut subgroup_size_control_features = vk::PhysicalDeviceSubgroupSizeControlFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut subgroup_size_control_features);
+    instance.get_physical_device_features2(physical_device, &mut features);
+
+    let mut subgroup_size_control_properties = vk::PhysicalDeviceSubgroupSizeControlPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup_size_control_properties);
+    instance.get_physical_device_properties2(physical_device, &mut properties);
+
+    if subgroup_size_control_features.subgroup_size_control != vk::TRUE
+        || !subgroup_size_control_properties.required_subgroup_size_stages.contains(vk::ShaderStageFlags::COMPUTE)
+    {
+        return Ok(None);
+    }
+
+    // Les tailles de subgroup sont toujours des puissances de 2
+    Ok(Some(requested.next_power_of_two().clamp(
+        subgroup_size_control_properties.min_subgroup_size,
+        subgroup_size_control_properties.max_subgroup_size,
+    )))
+}
 
-    let color_resolve_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(2)
-        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+/// Creates a compute pipeline running `bytecode`, with the configured subgroup size if any.
+unsafe fn create_compute_pipeline(
+    device: &Device,
+    data: &AppData,
+    layout: vk::PipelineLayout,
+    bytecode: &[u8],
+) -> Result<vk::Pipeline> {
+    let shader_module = create_shader_module(device, bytecode)?;
 
-    let color_attachments = &[color_attachment_ref];
-    let resolve_attachments = &[color_resolve_attachment_ref];
+    let mut subgroup_size_info = vk::PipelineShaderStageRequiredSubgroupSizeCreateInfoEXT::builder()
+        .required_subgroup_size(data.subgroup_size.unwrap_or(0));
 
-    let subpass = vk::SubpassDescription::builder()
-        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-        .color_attachments(color_attachments)
-        .depth_stencil_attachment(&depth_stencil_attachment_ref)
-        .resolve_attachments(resolve_attachments);
+    let mut stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
 
-    // RENDER PASS
+    if data.subgroup_size.is_some() {
+        stage = stage.push_next(&mut subgroup_size_info);
+    }
 
-    let dependency = vk::SubpassDependency::builder()
-        .src_subpass(vk::SUBPASS_EXTERNAL)
-        .dst_subpass(0)
-        .src_stage_mask(
-            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
-        )
-        .src_access_mask(vk::AccessFlags::empty())
-        .dst_stage_mask(
-            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
-        )
-        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(layout);
 
-    let attachments = &[
-        color_attachment,
-        depth_stencil_attachment,
-        color_resolve_attachment,
-    ];
-    let subpasses = &[subpass];
-    let dependencies = &[dependency];
-    let info = vk::RenderPassCreateInfo::builder()
-        .attachments(attachments)
-        .subpasses(subpasses)
-        .dependencies(dependencies);
+    let pipeline = device.create_compute_pipelines(vk::PipelineCache::null(), &[info], None)?.0;
 
-    data.render_pass = device.create_render_pass(&info, None)?;
+    device.destroy_shader_module(shader_module, None);
 
-    Ok(())
+    Ok(pipeline)
 }
 
-
 /////// SHADER ///////
+
+/// Reads the SPIR-V file `name` from the shader directory, when the pipelines are created,
+/// so the shaders can be changed without rebuilding the app.
+fn read_shader(data: &AppData, name: &str) -> Result<Vec<u8>> {
+    let path = data.shader_directory.join(name);
+    let bytecode = std::fs::read(&path).map_err(|e| {
+        anyhow!(TutorialError::Shader(format!("could not read `{}` ({}).", path.display(), e)))
+    })?;
+
+    // Le SPIR-V est une suite de mots de 32 bits
+    if bytecode.len() % 4 != 0 {
+        return Err(anyhow!(TutorialError::Shader(format!(
+            "`{}` is not valid SPIR-V, its size ({} bytes) is not a multiple of 4.",
+            path.display(),
+            bytecode.len(),
+        ))));
+    }
+
+    Ok(bytecode)
+}
 unsafe fn create_shader_module(
     device: &Device,
     bytecode: &[u8],
@@ -1191,7 +4462,7 @@ unsafe fn create_shader_module(
     let (prefix, code, suffix) = bytecode.align_to::<u32>();
 
     if !prefix.is_empty() || !suffix.is_empty() {
-        return Err(anyhow!("Shader bytecode is not properly aligned"));
+        return Err(anyhow!(TutorialError::Shader("bytecode not properly aligned.".to_string())));
     }
 
     let info = vk::ShaderModuleCreateInfo::builder()
@@ -1207,6 +4478,11 @@ unsafe fn create_shader_module(
 struct QueueFamilyIndices {
     graphics: u32,
     presentation: u32,
+    /// A family with `TRANSFER` but not `GRAPHICS` if there is one, `graphics` otherwise.
+    transfer: u32,
+    /// `graphics` if it also supports `COMPUTE`: the particles are advanced in the command
+    /// buffers of the frames.
+    compute: Option<u32>,
 }
 
 impl QueueFamilyIndices {
@@ -1221,8 +4497,9 @@ impl QueueFamilyIndices {
             .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
             .map(|i| i as u32);
 
-        let mut presentation = None;
-        for (index, properties) in properties.iter().enumerate() {
+        // Sans surface, la file graphique fait l'affaire
+        let mut presentation = graphics.filter(|_| data.headless);
+        for (index, properties) in properties.iter().enumerate().filter(|_| !data.headless) {
             if instance.get_physical_device_surface_support_khr(
                 physical_device,
                 index as u32,
@@ -1233,10 +4510,23 @@ impl QueueFamilyIndices {
             }
         }
 
+        let transfer = properties
+            .iter()
+            .position(|p| p.queue_flags.contains(vk::QueueFlags::TRANSFER) && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .map(|i| i as u32);
+
         if let (Some(graphics), Some(presentation)) = (graphics, presentation) {
-            Ok(Self { graphics, presentation })
+            let compute = Some(graphics)
+                .filter(|i| properties[*i as usize].queue_flags.contains(vk::QueueFlags::COMPUTE));
+
+            Ok(Self { graphics, presentation, transfer: transfer.unwrap_or(graphics), compute })
         } else {
-            Err(anyhow!(SuitabilityError("Missing required queue families.")))
+            let missing = [(graphics, "graphics queue family"), (presentation, "presentation queue family")]
+                .iter()
+                .filter(|(index, _)| index.is_none())
+                .map(|(_, requirement)| requirement.to_string())
+                .collect();
+            Err(anyhow!(SuitabilityError(missing)))
         }
     }
 }
@@ -1244,13 +4534,30 @@ impl QueueFamilyIndices {
 
 /////// FRAMEBUFFER ///////
 unsafe fn create_framebuffers(device: &Device, data: &mut AppData) -> Result<()> {
+    if data.dynamic_rendering {
+        data.framebuffers.clear();
+        return Ok(());
+    }
+
     data.framebuffers = data.swapchain_image_views
         .iter()
         .map(|i| {
-            let attachments = &[data.color_image_view, data.depth_image_view, *i];
+            // Même ordre que les attachments de la render pass
+            let mut attachments = if data.msaa_samples == vk::SampleCountFlags::_1 {
+                vec![*i]
+            } else {
+                vec![data.color_image_view]
+            };
+            if data.use_depth {
+                attachments.push(data.depth_image_view);
+            }
+            if data.msaa_samples != vk::SampleCountFlags::_1 {
+                attachments.push(*i);
+            }
+            attachments.extend(get_picking_attachment_views(data));
             let create_info = vk::FramebufferCreateInfo::builder()
                 .render_pass(data.render_pass)
-                .attachments(attachments)
+                .attachments(&attachments)
                 .width(data.swapchain_extent.width)
                 .height(data.swapchain_extent.height)
                 .layers(1);
@@ -1283,6 +4590,36 @@ unsafe fn create_command_pools(
 
     Ok(())
 }
+/// Recreates the command pools of the swapchain images, whose number changed with the
+/// swapchain, and the ones of the recording threads. The command buffers allocated from
+/// them are freed with them.
+unsafe fn recreate_image_command_pools(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.command_pools
+        .drain(..)
+        .for_each(|p| device.destroy_command_pool(p, None));
+    data.command_buffers.clear();
+    data.secondary_command_buffers.clear();
+    data.depth_prepass_command_buffers.clear();
+    clear_text_command_buffers(data);
+    clear_particle_command_buffers(data);
+    clear_skybox_command_buffers(data);
+
+    let num_images = data.swapchain_images.len();
+    for _ in 0..num_images {
+        let command_pool = create_command_pool(instance, device, data)?;
+        data.command_pools.push(command_pool);
+    }
+
+    destroy_recording_objects(device, data);
+    create_recording_objects(instance, device, data, get_max_recording_threads())?;
+
+    Ok(())
+}
+
 unsafe fn create_command_pool(
     instance: &Instance,
     device: &Device,
@@ -1297,10 +4634,13 @@ unsafe fn create_command_pool(
     Ok(device.create_command_pool(&info, None)?)
 }
 
+/// Allocates the primary command buffer of each swapchain image which doesn't have one yet:
+/// after a swapchain recreation keeping the command pools, the previous ones are reused
+/// (they are re-recorded each frame).
 unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<()> {
     let num_images = data.swapchain_images.len();
 
-    for image_index in 0..num_images {
+    for image_index in data.command_buffers.len()..num_images {
         let allocate_info = vk::CommandBufferAllocateInfo::builder()
             .command_pool(data.command_pools[image_index])
             .level(vk::CommandBufferLevel::PRIMARY)
@@ -1310,7 +4650,8 @@ unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<
         data.command_buffers.push(command_buffer);
     }
 
-    data.secondary_command_buffers = vec![vec![]; data.swapchain_images.len()];
+    // Comme les primaires, les secondaires déjà allouées restent dans leurs pools
+    data.secondary_command_buffers.resize_with(num_images, Vec::new);
 
     Ok(())
 }
@@ -1323,11 +4664,13 @@ struct Vertex {
     pos: glm::Vec3,
     color: glm::Vec3,
     tex_coord: glm::Vec2,
+    /// Zero when the model has no normals.
+    normal: glm::Vec3,
 }
 
 impl Vertex {
-    fn new(pos: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2) -> Self {
-        Self { pos, color, tex_coord }
+    fn new(pos: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2, normal: glm::Vec3) -> Self {
+        Self { pos, color, tex_coord, normal }
     }
 
     fn binding_description() -> vk::VertexInputBindingDescription {
@@ -1338,7 +4681,7 @@ impl Vertex {
             .build()
     }
 
-    fn attribute_description() -> [vk::VertexInputAttributeDescription; 3] {
+    fn attribute_description() -> [vk::VertexInputAttributeDescription; 4] {
         let pos = vk::VertexInputAttributeDescription::builder()
             .binding(0)
             .location(0)
@@ -1360,7 +4703,15 @@ impl Vertex {
             .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>()) as u32)
             .build();
 
-        [pos, color, tex_coord]
+        // Les locations 3 à 6 sont prises par la matrice par instance (InstanceData)
+        let normal = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(7)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>() + size_of::<glm::Vec2>()) as u32)
+            .build();
+
+        [pos, color, tex_coord, normal]
     }
 }
 
@@ -1369,6 +4720,7 @@ impl PartialEq for Vertex {
         self.pos == other.pos
             && self.color == other.color
             && self.tex_coord == other.tex_coord
+            && self.normal == other.normal
     }
 }
 
@@ -1388,34 +4740,115 @@ impl Hash for Vertex {
 
         self.tex_coord[0].to_bits().hash(state);
         self.tex_coord[1].to_bits().hash(state);
+
+        self.normal[0].to_bits().hash(state);
+        self.normal[1].to_bits().hash(state);
+        self.normal[2].to_bits().hash(state);
     }
 }
 
-unsafe fn create_vertex_buffer(
+/// Per-instance vertex data of the instanced path.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct InstanceData {
+    model: glm::Mat4,
+    /// Index of the model, written to the picking attachment.
+    model_index: u32,
+}
+
+impl InstanceData {
+    fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(size_of::<InstanceData>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    // Une mat4 occupe 4 locations, une par colonne
+    fn attribute_description() -> [vk::VertexInputAttributeDescription; 5] {
+        let column = |i: u32| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(3 + i)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(i * size_of::<glm::Vec4>() as u32)
+                .build()
+        };
+
+        // Après la normale du vertex (location 7)
+        let model_index = vk::VertexInputAttributeDescription::builder()
+            .binding(1)
+            .location(8)
+            .format(vk::Format::R32_UINT)
+            .offset(size_of::<glm::Mat4>() as u32)
+            .build();
+
+        [column(0), column(1), column(2), column(3), model_index]
+    }
+}
+
+/// The geometry of a model file and its buffers.
+#[derive(Clone, Debug, Default)]
+struct Mesh {
+    vertices: Vec<Vertex>,
+    /// Always 32 bits on the CPU, the index buffer may be 16 bits (see `index_type`).
+    indices: Vec<u32>,
+    vertex_buffer: vk::Buffer,
+    vertex_buffer_memory: Allocation,
+    index_buffer: vk::Buffer,
+    index_buffer_memory: Allocation,
+    /// `UINT16` if every index fits in 16 bits, `UINT32` otherwise.
+    index_type: vk::IndexType,
+}
+
+/// The smallest index type for `indices`. 0xFFFF is left out, being the primitive restart
+/// index of 16 bit indices.
+fn get_index_type(indices: &[u32]) -> vk::IndexType {
+    if indices.iter().all(|i| *i < u16::MAX as u32) {
+        vk::IndexType::UINT16
+    } else {
+        vk::IndexType::UINT32
+    }
+}
+
+/// Creates the vertex and index buffers of every mesh, uploaded together.
+unsafe fn create_mesh_buffers(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
-    let size = (size_of::<Vertex>() * data.vertices.len()) as u64;
+    let mut uploads = vec![];
+    for mesh_index in 0..data.meshes.len() {
+        uploads.push(create_mesh_vertex_buffer(instance, device, data, mesh_index)?);
+        uploads.push(create_mesh_index_buffer(instance, device, data, mesh_index)?);
+    }
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
+    // Une seule soumission (et une seule attente) pour tous les meshes
+    let uploads = uploads
+        .iter()
+        .map(|(bytes, buffer)| (bytes.as_slice(), *buffer))
+        .collect::<Vec<_>>();
+
+    upload_buffers(
         instance,
         device,
         data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-    )?;
-
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0,
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
+        &uploads,
+        vk::PipelineStageFlags::VERTEX_INPUT,
+        vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ,
+    )
+}
 
-    memcpy(data.vertices.as_ptr(), memory.cast(), data.vertices.len());
-    device.unmap_memory(staging_buffer_memory);
+/// Creates the vertex buffer of a mesh, and returns the bytes to upload to it.
+unsafe fn create_mesh_vertex_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    mesh_index: usize,
+) -> Result<(Vec<u8>, vk::Buffer)> {
+    let vertices = &data.meshes[mesh_index].vertices;
+    let size = (size_of::<Vertex>() * vertices.len()) as u64;
 
     let (vertex_buffer, vertex_buffer_memory) = create_buffer(
         instance,
@@ -1426,60 +4859,125 @@ unsafe fn create_vertex_buffer(
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.vertex_buffer = vertex_buffer;
-    data.vertex_buffer_memory = vertex_buffer_memory;
-
-    copy_buffer(device, data, staging_buffer, vertex_buffer, size)?;
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-
+    let mesh = &mut data.meshes[mesh_index];
+    mesh.vertex_buffer = vertex_buffer;
+    mesh.vertex_buffer_memory = vertex_buffer_memory;
 
-
-    Ok(())
+    Ok((as_bytes(&mesh.vertices).to_vec(), vertex_buffer))
 }
 
-unsafe fn create_index_buffer(
+/// Creates the index buffer of a mesh, and returns the bytes to upload to it.
+unsafe fn create_mesh_index_buffer(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
-) -> Result<()> {
-    let size = (size_of::<u32>() * data.indices.len()) as u64;
+    mesh_index: usize,
+) -> Result<(Vec<u8>, vk::Buffer)> {
+    let indices = &data.meshes[mesh_index].indices;
+    let index_type = get_index_type(indices);
+
+    // Moitié moins de mémoire et de bande passante sur 16 bits
+    let bytes = if index_type == vk::IndexType::UINT16 {
+        let indices = indices.iter().map(|i| *i as u16).collect::<Vec<_>>();
+        as_bytes(&indices).to_vec()
+    } else {
+        as_bytes(indices).to_vec()
+    };
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
+    let (index_buffer, index_buffer_memory) = create_buffer(
         instance,
         device,
         data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        bytes.len() as u64,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0,
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
+    let mesh = &mut data.meshes[mesh_index];
+    mesh.index_buffer = index_buffer;
+    mesh.index_buffer_memory = index_buffer_memory;
+    mesh.index_type = index_type;
 
-    memcpy(data.indices.as_ptr(), memory.cast(), data.indices.len());
+    Ok((bytes, index_buffer))
+}
+
+/// Per-object material constants, stored in an array in a storage buffer (std430).
+///
+/// By default each object pushes the index of its material, so a single descriptor set
+/// serves every object: the sets don't depend on the number of materials, and the draws
+/// only change push constants. With `AppConfig::material_sets`, each material has its own
+/// descriptor sets instead, the storage buffer binding only covering that material (pushed
+/// as index 0). This costs a `cmd_bind_descriptor_sets` per object and a pool growing with
+/// the materials (each material also takes `minStorageBufferOffsetAlignment` bytes), but a
+/// set can be replaced for a single material, and the shader never reads an index that
+/// could be out of the array.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Material {
+    /// Multiplied with the texture color.
+    color: glm::Vec4,
+}
 
-    device.unmap_memory(staging_buffer_memory);
+impl Material {
+    fn new(r: f32, g: f32, b: f32) -> Self {
+        Self { color: glm::vec4(r, g, b, 1.0) }
+    }
+}
 
-    let (index_buffer, index_buffer_memory) = create_buffer(
+unsafe fn create_material_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if data.materials.is_empty() {
+        data.materials = vec![
+            Material::new(1.0, 1.0, 1.0),
+            Material::new(1.0, 0.6, 0.6),
+            Material::new(0.6, 1.0, 0.6),
+            Material::new(0.6, 0.6, 1.0),
+        ];
+    }
+
+    // Chaque descriptor set d'un matériau commence à un offset aligné
+    data.material_stride = size_of::<Material>() as u64;
+    if data.material_sets {
+        let alignment = instance
+            .get_physical_device_properties(data.physical_device)
+            .limits
+            .min_storage_buffer_offset_alignment;
+        data.material_stride = data.material_stride.next_multiple_of(alignment.max(1));
+    }
+
+    let stride = data.material_stride as usize;
+    let mut bytes = vec![0; stride * data.materials.len()];
+    for (i, material) in data.materials.iter().enumerate() {
+        let material = as_bytes(slice::from_ref(material));
+        bytes[i * stride..i * stride + material.len()].copy_from_slice(material);
+    }
+
+    let size = bytes.len() as u64;
+
+    let (material_buffer, material_buffer_memory) = create_buffer(
         instance,
         device,
         data,
         size,
-        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::STORAGE_BUFFER,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.index_buffer = index_buffer;
-    data.index_buffer_memory = index_buffer_memory;
+    data.material_buffer = material_buffer;
+    data.material_buffer_memory = material_buffer_memory;
 
-    copy_buffer(device, data, staging_buffer, index_buffer, size)?;
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    upload_via_staging(
+        instance,
+        device,
+        data,
+        &bytes,
+        material_buffer,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::AccessFlags::SHADER_READ,
+    )?;
 
     Ok(())
 }
@@ -1501,11 +4999,19 @@ unsafe fn get_memory_type_index(
         .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
 }
 
+/// Color (and intensity) of the directional light, `w` unused.
+const LIGHT_COLOR: glm::Vec4 = glm::Vec4::new(1.0, 1.0, 1.0, 0.0);
+
+/// The uniform buffer of the models, laid out like its std140 block: vectors of 4 components
+/// to avoid the padding after a `vec3`.
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 struct UniformBufferObject {
     view: glm::Mat4,
     proj: glm::Mat4,
+    /// Direction towards the directional light (world space, normalized), `w` unused.
+    light_direction: glm::Vec4,
+    light_color: glm::Vec4,
 }
 
 unsafe fn create_uniform_buffers(
@@ -1516,7 +5022,8 @@ unsafe fn create_uniform_buffers(
     data.uniform_buffers.clear();
     data.uniform_buffers_memory.clear();
 
-    for _ in 0..data.swapchain_images.len() {
+    // Un par frame en cours: le CPU n'écrit que celui dont la fence a été attendue
+    for _ in 0..data.frames_in_flight {
         let (uniform_buffer, uniform_buffer_memory) = create_buffer(
             instance,
             logical_device,
@@ -1533,14 +5040,39 @@ unsafe fn create_uniform_buffers(
     Ok(())
 }
 
+unsafe fn create_instance_buffers(
+    instance: &Instance,
+    logical_device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.instance_buffers.clear();
+    data.instance_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (instance_buffer, instance_buffer_memory) = create_buffer(
+            instance,
+            logical_device,
+            data,
+            (size_of::<InstanceData>() * MAX_INSTANCES) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.instance_buffers.push(instance_buffer);
+        data.instance_buffers_memory.push(instance_buffer_memory);
+    }
+
+    Ok(())
+}
+
 unsafe fn create_buffer(
     instance: &Instance,
     logical_device: &Device,
-    data: &AppData,
+    data: &mut AppData,
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+) -> Result<(vk::Buffer, Allocation)> {
     let buffer_info = vk::BufferCreateInfo::builder()
         .size(size)
         .usage(usage)
@@ -1550,17 +5082,10 @@ unsafe fn create_buffer(
 
     let requirements = logical_device.get_buffer_memory_requirements(buffer);
 
-    let memory_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance,
-            data,
-            properties,
-            requirements,
-        )?);
+    let memory_type_index = get_memory_type_index(instance, data, properties, requirements)?;
 
-    let buffer_memory = logical_device.allocate_memory(&memory_info, None)?;
-    logical_device.bind_buffer_memory(buffer, buffer_memory, 0)?;
+    let buffer_memory = data.allocator.allocate(logical_device, requirements, memory_type_index)?;
+    logical_device.bind_buffer_memory(buffer, buffer_memory.memory, buffer_memory.offset)?;
 
     Ok((buffer, buffer_memory))
 }
@@ -1571,17 +5096,83 @@ unsafe fn copy_buffer(
     source: vk::Buffer,
     destination: vk::Buffer,
     size: vk::DeviceSize,
+    dst_stage_mask: vk::PipelineStageFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> Result<()> {
+    let region = vk::BufferCopy::builder()
+        .src_offset(0)
+        .dst_offset(0)
+        .size(size);
+
+    upload_buffer(logical_device, data, source, destination, &[region.build()], dst_stage_mask, dst_access_mask)
+}
+
+/// Copies `size` bytes from `source_offset` in `source` to `destination_offset` in
+/// `destination`, leaving the rest of `destination` untouched.
+///
+/// `destination` may already be in use: the copy is done on the graphics queue, which owns
+/// the buffers once uploaded (see `transfer`), after every previous access to the region
+/// and before any following one.
+unsafe fn copy_buffer_region(
+    logical_device: &Device,
+    data: &AppData,
+    source: vk::Buffer,
+    source_offset: vk::DeviceSize,
+    destination: vk::Buffer,
+    destination_offset: vk::DeviceSize,
+    size: vk::DeviceSize,
 ) -> Result<()> {
     let command_buffer = begin_single_time_commands(logical_device, data)?;
 
-    let regions = vk::BufferCopy::builder().size(size);
-    logical_device.cmd_copy_buffer(command_buffer, source, destination, &[regions]);
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(destination)
+        .offset(destination_offset)
+        .size(size);
 
-    end_single_time_commands(logical_device, data, command_buffer)?;
+    // Après les lectures et écritures des frames précédentes
+    let before = barrier
+        .src_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
 
-    Ok(())
+    logical_device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[before],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    let region = vk::BufferCopy::builder()
+        .src_offset(source_offset)
+        .dst_offset(destination_offset)
+        .size(size);
+
+    logical_device.cmd_copy_buffer(command_buffer, source, destination, &[region]);
+
+    // Visible pour tout ce qui lit le buffer ensuite
+    let after = barrier
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE);
+
+    logical_device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[after],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    end_single_time_commands(logical_device, data, command_buffer)
 }
 
+/// Copies `buffer` to the first mip level of `image`, in the `UNDEFINED` layout, leaving its
+/// `mip_levels` in the `TRANSFER_DST_OPTIMAL` layout.
 unsafe fn copy_buffer_to_image(
     logical_device: &Device,
     data: &AppData,
@@ -1589,39 +5180,81 @@ unsafe fn copy_buffer_to_image(
     image: vk::Image,
     width: u32,
     height: u32,
+    mip_levels: u32,
 ) -> Result<()> {
-    let command_buffer = begin_single_time_commands(logical_device, data)?;
+    let region = get_first_level_copy(width, height);
+    upload_image(logical_device, data, buffer, image, mip_levels, &[region])
+}
+
+/// Records the copy of `buffer` to the first mip level of `image`, in the
+/// `TRANSFER_DST_OPTIMAL` layout.
+unsafe fn cmd_copy_buffer_to_image(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    image: vk::Image,
+    width: u32,
+    height: u32,
+) {
+    let region = get_first_level_copy(width, height);
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+    );
+}
 
+/// The copy of a tightly packed buffer to the whole first mip level of a color image.
+fn get_first_level_copy(width: u32, height: u32) -> vk::BufferImageCopy {
     let subresource = vk::ImageSubresourceLayers::builder()
         .aspect_mask(vk::ImageAspectFlags::COLOR)
         .mip_level(0)
         .base_array_layer(0)
         .layer_count(1);
 
-    let region = vk::BufferImageCopy::builder()
+    vk::BufferImageCopy::builder()
         .buffer_offset(0)
         .buffer_row_length(0)
         .buffer_image_height(0)
         .image_subresource(subresource)
         .image_offset(vk::Offset3D { x: 0, y: 0, z: 0})
-        .image_extent(vk::Extent3D {width, height, depth: 1});
+        .image_extent(vk::Extent3D {width, height, depth: 1})
+        .build()
+}
+
+unsafe fn transition_image_layout(
+    device: &Device,
+    data: &AppData,
+    image: vk::Image,
+    format: vk::Format,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    mip_levels: u32,
+) -> Result<()> {
+    let command_buffer = begin_single_time_commands(device, data)?;
 
-    logical_device.cmd_copy_buffer_to_image(
+    let result = cmd_transition_image_layout(
+        device,
         command_buffer,
-        buffer,
         image,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        &[region],
+        format,
+        old_layout,
+        new_layout,
+        mip_levels,
     );
 
-    end_single_time_commands(logical_device, data, command_buffer)?;
+    // Soumis même en cas d'erreur, pour libérer le command buffer
+    end_single_time_commands(device, data, command_buffer)?;
 
-    Ok(())
+    result
 }
 
-unsafe fn transition_image_layout(
+/// Records the transition of the `mip_levels` of `image` from `old_layout` to `new_layout`.
+unsafe fn cmd_transition_image_layout(
     device: &Device,
-    data: &AppData,
+    command_buffer: vk::CommandBuffer,
     image: vk::Image,
     format: vk::Format,
     old_layout: vk::ImageLayout,
@@ -1648,13 +5281,30 @@ unsafe fn transition_image_layout(
             vk::PipelineStageFlags::TRANSFER,
             vk::PipelineStageFlags::FRAGMENT_SHADER,
         ),
+        (
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            | vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+            | vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL,
+        ) => (
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        ),
         _ => return Err(anyhow!("Unsupported image layout transition :'(")),
     };
 
-    let command_buffer = begin_single_time_commands(device, data)?;
+    // Les layouts DEPTH_ et STENCIL_ (layouts séparés) ne concernent qu'un aspect de l'image
+    let aspect_mask = match new_layout {
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => get_depth_aspect_mask(format),
+        vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL => vk::ImageAspectFlags::DEPTH,
+        vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
+    };
 
     let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .aspect_mask(aspect_mask)
         .base_mip_level(0)
         .level_count(mip_levels)
         .base_array_layer(0)
@@ -1681,8 +5331,6 @@ unsafe fn transition_image_layout(
         &[barrier],
     );
 
-    end_single_time_commands(device, data, command_buffer)?;
-
     Ok(())
 }
 
@@ -1690,19 +5338,39 @@ unsafe fn create_descriptor_set_layout(
     logical_device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
+    // La lumière est lue par le fragment shader
     let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
         .binding(0)
         .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
         .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::VERTEX);
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let material_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let second_sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(3)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
-    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(1)
+    // Le cube map du skybox, écrit seulement s'il a été créé
+    let skybox_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(4)
         .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
-    let bindings = &[ubo_binding, sampler_binding];
+    let bindings = &[ubo_binding, sampler_binding, material_binding, second_sampler_binding, skybox_binding];
     let info = vk::DescriptorSetLayoutCreateInfo::builder()
         .bindings(bindings);
 
@@ -1715,18 +5383,25 @@ unsafe fn create_descriptor_pool(
     logical_device: &Device,
     data: &mut AppData
 ) -> Result<()> {
+    let sets = (data.frames_in_flight * (1 + get_material_set_count(data))) as u32;
+
     let ubo_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(sets);
 
+    // Les deux textures et le skybox
     let sampler_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(3 * sets);
+
+    let material_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(sets);
 
-    let pool_sizes = &[ubo_size, sampler_size];
+    let pool_sizes = &[ubo_size, sampler_size, material_size];
     let info = vk::DescriptorPoolCreateInfo::builder()
         .pool_sizes(pool_sizes)
-        .max_sets(data.swapchain_images.len() as u32);
+        .max_sets(sets);
 
     data.descriptor_pool = logical_device.create_descriptor_pool(&info, None)?;
 
@@ -1737,49 +5412,482 @@ unsafe fn create_descriptor_sets(
     logical_device: &Device,
     data: &mut AppData
 ) -> Result<()> {
-    let layouts = vec![data.descriptor_set_layout; data.swapchain_images.len()];
+    // Un par frame en cours, liés avec `App::frame` et non l'index de l'image
+    let layouts = vec![data.descriptor_set_layout; data.frames_in_flight];
     let info = vk::DescriptorSetAllocateInfo::builder()
         .descriptor_pool(data.descriptor_pool)
         .set_layouts(&layouts);
 
     data.descriptor_sets = logical_device.allocate_descriptor_sets(&info)?;
+    assert_eq!(data.descriptor_sets.len(), data.frames_in_flight);
 
-    for i in 0..data.swapchain_images.len() {
-        let info = vk::DescriptorBufferInfo::builder()
-            .buffer(data.uniform_buffers[i])
-            .offset(0)
-            .range(size_of::<UniformBufferObject>() as u64);
+    for i in 0..data.frames_in_flight {
+        write_buffer_descriptors(logical_device, data, data.descriptor_sets[i], i, 0, vk::WHOLE_SIZE as u64);
+    }
 
-        let buffer_info = &[info];
-        let ubo_write = vk::WriteDescriptorSet::builder()
-            .dst_set(data.descriptor_sets[i])
-            .dst_binding(0)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .buffer_info(buffer_info);
-
-        let info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(data.texture_image_view)
-            .sampler(data.texture_sampler);
-
-        let image_info = &[info];
-        let sampler_write = vk::WriteDescriptorSet::builder()
-            .dst_set(data.descriptor_sets[i])
-            .dst_binding(1)
+    // Les mêmes, avec seulement le matériau dans le storage buffer
+    let material_count = get_material_set_count(data);
+    let layouts = vec![data.descriptor_set_layout; data.frames_in_flight * material_count];
+    data.material_descriptor_sets = if layouts.is_empty() {
+        vec![]
+    } else {
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(data.descriptor_pool)
+            .set_layouts(&layouts);
+
+        logical_device.allocate_descriptor_sets(&info)?
+    };
+
+    for i in 0..data.frames_in_flight {
+        for material in 0..material_count {
+            let descriptor_set = get_material_descriptor_set(data, i, material);
+            let offset = material as u64 * data.material_stride;
+            write_buffer_descriptors(logical_device, data, descriptor_set, i, offset, size_of::<Material>() as u64);
+        }
+    }
+
+    write_texture_descriptors(logical_device, data);
+
+    Ok(())
+}
+
+/// Writes the uniform buffer of `frame` and the `range` of the material buffer at `offset`
+/// to `descriptor_set`.
+unsafe fn write_buffer_descriptors(
+    logical_device: &Device,
+    data: &AppData,
+    descriptor_set: vk::DescriptorSet,
+    frame: usize,
+    offset: vk::DeviceSize,
+    range: vk::DeviceSize,
+) {
+    let info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.uniform_buffers[frame])
+        .offset(0)
+        .range(size_of::<UniformBufferObject>() as u64);
+
+    let buffer_info = &[info];
+    let ubo_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .buffer_info(buffer_info);
+
+    let info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.material_buffer)
+        .offset(offset)
+        .range(range);
+
+    let material_info = &[info];
+    let material_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(2)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(material_info);
+
+    logical_device.update_descriptor_sets(
+        &[ubo_write, material_write],
+        &[] as &[vk::CopyDescriptorSet]
+    );
+}
+
+/// The number of materials with their own descriptor sets, 0 without `material_sets`.
+fn get_material_set_count(data: &AppData) -> usize {
+    if data.material_sets {
+        data.materials.len()
+    } else {
+        0
+    }
+}
+
+/// The descriptor set of `frame` for the material at `material_index` (see `material_sets`).
+fn get_material_descriptor_set(data: &AppData, frame: usize, material_index: usize) -> vk::DescriptorSet {
+    data.material_descriptor_sets[frame * data.materials.len() + material_index]
+}
+
+/// Writes the two textures and the sampler to the descriptor sets, which must not be in
+/// use (after `create_descriptor_sets`, or to replace the sampler).
+unsafe fn write_texture_descriptors(logical_device: &Device, data: &AppData) {
+    for frame in 0..data.descriptor_sets.len() {
+        write_texture_descriptor_set(logical_device, data, frame);
+    }
+}
+
+/// Writes the texture bindings of the descriptor sets of `frame` (with the ones of its
+/// materials), which must not be in use.
+/// The first texture is the placeholder while a texture is streamed (see `streaming`).
+unsafe fn write_texture_descriptor_set(logical_device: &Device, data: &AppData, frame: usize) {
+    let material_sets = (0..get_material_set_count(data)).map(|m| get_material_descriptor_set(data, frame, m));
+    for descriptor_set in iter::once(data.descriptor_sets[frame]).chain(material_sets) {
+        write_texture_descriptor_set_to(logical_device, data, descriptor_set);
+    }
+}
+
+unsafe fn write_texture_descriptor_set_to(logical_device: &Device, data: &AppData, descriptor_set: vk::DescriptorSet) {
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(get_texture_view(data))
+        .sampler(data.texture_sampler);
+
+    let image_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.second_texture.view)
+        .sampler(data.texture_sampler);
+
+    let second_image_info = &[info];
+    let second_sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(3)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(second_image_info);
+
+    let mut writes = vec![sampler_write, second_sampler_write];
+
+    let skybox_info = get_skybox_descriptor(data).map(|i| [i]);
+    if let Some(skybox_info) = &skybox_info {
+        let skybox_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(4)
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(image_info);
+            .image_info(skybox_info);
+        writes.push(skybox_write);
+    }
+
+    logical_device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+}
+
+
+/// What the fragment shader of the models outputs instead of the shaded color, to check
+/// the inputs of the shading.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum DebugView {
+    #[default]
+    Shaded,
+    VertexColor,
+    TexCoords,
+    /// The normals in world space, from [-1, 1] to [0, 1].
+    Normals,
+    /// The distance to the camera, from 0.0 at the near plane to 1.0 at the far one.
+    Depth,
+}
+
+impl DebugView {
+    /// The view selected by Alt and the digit `index`, `None` if there is none.
+    fn from_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(Self::Shaded),
+            1 => Some(Self::VertexColor),
+            2 => Some(Self::TexCoords),
+            3 => Some(Self::Normals),
+            4 => Some(Self::Depth),
+            _ => None,
+        }
+    }
+}
+
+/// The digit of the number row `key`.
+fn get_digit(key: VirtualKeyCode) -> Option<u32> {
+    match key {
+        VirtualKeyCode::Key0 => Some(0),
+        VirtualKeyCode::Key1 => Some(1),
+        VirtualKeyCode::Key2 => Some(2),
+        VirtualKeyCode::Key3 => Some(3),
+        VirtualKeyCode::Key4 => Some(4),
+        VirtualKeyCode::Key5 => Some(5),
+        VirtualKeyCode::Key6 => Some(6),
+        VirtualKeyCode::Key7 => Some(7),
+        VirtualKeyCode::Key8 => Some(8),
+        VirtualKeyCode::Key9 => Some(9),
+        _ => None,
+    }
+}
+
+/// The index of the selected model pushed to the fragment shader, `NO_MODEL` without selection.
+fn get_selected_model_index(selected_model: Option<usize>) -> u32 {
+    selected_model.map_or(NO_MODEL, |i| i as u32)
+}
+
+/// Records the secondary command buffer drawing the model at `model_index` with the
+/// `model` matrix, tinted if it is the `selected_model` (may be called from the recording
+/// threads).
+unsafe fn record_model_command_buffer(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    frame: usize,
+    model_index: usize,
+    model: &glm::Mat4,
+    selected_model: Option<usize>,
+) -> Result<()> {
+    let (_, model_bytes, _) = model.as_slice().align_to::<u8>();
+
+    // 0.25 à 1.0, puis de nouveau 0.25 au delà de 4 modèles
+    let opacity = ((model_index % 4) + 1) as f32 * 0.25;
+    let opacity_bytes = &opacity.to_ne_bytes()[..];
+
+    // Avec un descriptor set par matériau, son storage buffer ne contient que ce matériau
+    let material_index = model_index % data.materials.len();
+    let (descriptor_set, material_index) = if data.material_sets {
+        (get_material_descriptor_set(data, frame, material_index), 0)
+    } else {
+        (data.descriptor_sets[frame], material_index as u32)
+    };
+    let material_index_bytes = &material_index.to_ne_bytes()[..];
+
+    let texture_blend_bytes = &data.texture_blend.to_ne_bytes()[..];
+
+    let model_index_bytes = &(model_index as u32).to_ne_bytes()[..];
+    let selected_model_bytes = &get_selected_model_index(selected_model).to_ne_bytes()[..];
+    let debug_view_bytes = &(data.debug_view as u32).to_ne_bytes()[..];
+
+    let mesh = &data.meshes[model_index % data.meshes.len()];
+
+    begin_secondary_command_buffer(device, data, command_buffer, image_index)?;
+
+    device.cmd_bind_pipeline(
+        command_buffer, vk::PipelineBindPoint::GRAPHICS, data.pipeline
+    );
+    cmd_set_depth_state(device, data, command_buffer);
+
+    device.cmd_bind_vertex_buffers(
+        command_buffer,
+        0,
+        &[mesh.vertex_buffer],
+        &[0]
+    );
+    device.cmd_bind_index_buffer(
+        command_buffer,
+        mesh.index_buffer,
+        0,
+        mesh.index_type
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline_layout,
+        0,
+        &[descriptor_set],
+        &[],
+    );
+
+    //Pour matrice model
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::VERTEX,
+        0,
+        model_bytes,
+    );
+
+    //Pour l'index du modèle, écrit dans l'attachment de picking
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::VERTEX,
+        64,
+        model_index_bytes,
+    );
+
+    //Pour opacity
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        68,
+        opacity_bytes, //opacité de 0.2
+    );
+
+    //Pour le matériau
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        72,
+        material_index_bytes,
+    );
+
+    //Pour le poids de la deuxième texture
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        76,
+        texture_blend_bytes,
+    );
+
+    //Pour le modèle sélectionné, teinté
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        80,
+        selected_model_bytes,
+    );
+
+    //Pour la vue de debug
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        84,
+        debug_view_bytes,
+    );
+
+    cmd_draw_if_visible(device, data, command_buffer, image_index, model_index, || {
+        device.cmd_draw_indexed(
+            command_buffer,
+            mesh.indices.len() as u32,
+            1,
+            0,
+            0,
+            0
+        );
+    });
+
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(())
+}
+
+/// Records the secondary command buffer of the depth pre-pass for `image_index`, drawing
+/// the depth of the models (`models` being their model matrices) before they are shaded.
+unsafe fn update_depth_prepass_command_buffer(
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    frame: usize,
+    models: &[glm::Mat4],
+) -> Result<vk::CommandBuffer> {
+    while image_index >= data.depth_prepass_command_buffers.len() {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(data.command_pools[data.depth_prepass_command_buffers.len()])
+            .level(vk::CommandBufferLevel::SECONDARY)
+            .command_buffer_count(1);
+
+        let command_buffer = device.allocate_command_buffers(&allocate_info)?[0];
+        data.depth_prepass_command_buffers.push(command_buffer);
+    }
+
+    let command_buffer = data.depth_prepass_command_buffers[image_index];
+
+    begin_secondary_command_buffer(device, data, command_buffer, image_index)?;
+
+    device.cmd_bind_pipeline(
+        command_buffer, vk::PipelineBindPoint::GRAPHICS, data.depth_prepass_pipeline
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline_layout,
+        0,
+        &[data.descriptor_sets[frame]],
+        &[],
+    );
+
+    for (model_index, model) in models.iter().enumerate() {
+        let (_, model_bytes, _) = model.as_slice().align_to::<u8>();
+        let model_index_bytes = &(model_index as u32).to_ne_bytes()[..];
+        let mesh = &data.meshes[model_index % data.meshes.len()];
+
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+        device.cmd_bind_index_buffer(command_buffer, mesh.index_buffer, 0, mesh.index_type);
+
+        device.cmd_push_constants(
+            command_buffer,
+            data.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            model_bytes,
+        );
 
-        logical_device.update_descriptor_sets(
-            &[ubo_write, sampler_write],
-            &[] as &[vk::CopyDescriptorSet]
+        device.cmd_push_constants(
+            command_buffer,
+            data.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            64,
+            model_index_bytes,
         );
+
+        cmd_draw_if_visible(device, data, command_buffer, image_index, model_index, || {
+            device.cmd_draw_indexed(command_buffer, mesh.indices.len() as u32, 1, 0, 0, 0);
+        });
     }
 
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(command_buffer)
+}
+
+/// Begins a secondary command buffer which draws into the swapchain image `image_index`,
+/// inheriting either the render pass or the dynamic rendering attachments.
+unsafe fn begin_secondary_command_buffer(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) -> Result<()> {
+    let color_attachment_formats = &get_color_attachment_formats(data);
+    let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_attachment_format(data))
+        .rasterization_samples(data.msaa_samples);
+
+    let mut inheritance_info = vk::CommandBufferInheritanceInfo::builder();
+
+    inheritance_info = if data.dynamic_rendering {
+        inheritance_info.push_next(&mut rendering_info)
+    } else {
+        inheritance_info
+            .render_pass(data.render_pass)
+            .subpass(0)
+            .framebuffer(data.framebuffers[image_index])
+    };
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+        .inheritance_info(&inheritance_info);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    // Les états dynamiques ne sont pas hérités du command buffer primaire
+    cmd_set_viewport_and_scissor(device, data, command_buffer);
+
     Ok(())
 }
 
+/// Sets the viewport and the scissor of the model pipelines to the whole swapchain extent.
+///
+/// The viewport keeps its positive height (Y pointing down in framebuffer space): the
+/// projection matrix flips Y instead (`proj[(1, 1)] *= -1.0`).
+unsafe fn cmd_set_viewport_and_scissor(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+    device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+}
 
 /////// RENDERING AND PRESENTATION
 unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<()> {
@@ -1787,7 +5895,7 @@ unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<()>
     let fence_info = vk::FenceCreateInfo::builder()
         .flags(vk::FenceCreateFlags::SIGNALED);
 
-    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+    for _ in 0..data.frames_in_flight {
         data.image_available_semaphores.push(
             device.create_semaphore(&semaphore_info, None)?
         );
@@ -1808,43 +5916,58 @@ unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<()>
 }
 
 //////// IMAGE CREATION ////////
+/// How the texels of a texture are encoded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TextureEncoding {
+    /// Colors, converted from sRGB to linear when sampled.
+    Srgb,
+    /// Data (normals, roughness...), sampled as is.
+    Linear,
+}
+
+impl TextureEncoding {
+    fn format(self) -> vk::Format {
+        match self {
+            Self::Srgb => vk::Format::R8G8B8A8_SRGB,
+            Self::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
 unsafe fn create_texture_image(
     instance: &Instance,
     logical_device: &Device,
     data: &mut AppData,
+    encoding: TextureEncoding,
 ) -> Result<()> {
-    let image = File::open("resources/viking_room.png")?;
-
-    let decoder = png::Decoder::new(image);
-    let mut reader = decoder.read_info()?;
-
-    let mut pixels = vec![0; reader.1.info().raw_bytes()];
-    reader.1.next_frame(&mut pixels)?;
-
-    let size = reader.1.info().raw_bytes() as u64;
-    let (width, height) = reader.1.info().size();
-
-    data.mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+    let path = &data.texture_path;
+    if !path.is_file() {
+        return Err(anyhow!("Texture `{}` not found.", path.display()));
+    }
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
-        logical_device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE
-    )?;
+    if is_ktx2(path) {
+        return create_ktx2_texture_image(instance, logical_device, data);
+    }
 
-    let memory = logical_device.map_memory(
-        staging_buffer_memory,
-        0,
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
+    let (pixels, width, height) = read_png_texture(path)?;
+    create_texture_image_from_pixels(instance, logical_device, data, encoding, &pixels, width, height)
+}
 
-    memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
+/// Creates the texture image from the RGBA `pixels` of `data.texture_path`, already decoded
+/// (by `read_png_texture`).
+unsafe fn create_texture_image_from_pixels(
+    instance: &Instance,
+    logical_device: &Device,
+    data: &mut AppData,
+    encoding: TextureEncoding,
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    let path = &data.texture_path;
+    data.mip_levels = get_texture_mip_levels(instance, data, encoding.format(), width, height, path);
 
-    logical_device.unmap_memory(staging_buffer_memory);
+    let staging_buffer = stage(instance, logical_device, data, pixels)?;
 
     let (texture_image, texture_image_memory) = create_image(
         instance,
@@ -1854,7 +5977,7 @@ unsafe fn create_texture_image(
         height,
         data.mip_levels,
         vk::SampleCountFlags::_1,
-        vk::Format::R8G8B8A8_SRGB,
+        encoding.format(),
         vk::ImageTiling::OPTIMAL,
         vk::ImageUsageFlags::SAMPLED |
             vk::ImageUsageFlags::TRANSFER_DST |
@@ -1864,16 +5987,7 @@ unsafe fn create_texture_image(
 
     data.texture_image = texture_image;
     data.texture_image_memory = texture_image_memory;
-
-    transition_image_layout(
-        logical_device,
-        data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageLayout::UNDEFINED,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        data.mip_levels,
-    )?;
+    data.texture_format = encoding.format();
 
     copy_buffer_to_image(
         logical_device,
@@ -1882,17 +5996,15 @@ unsafe fn create_texture_image(
         data.texture_image,
         width,
         height,
+        data.mip_levels,
     )?;
 
-    logical_device.destroy_buffer(staging_buffer, None);
-    logical_device.free_memory(staging_buffer_memory, None);
-
     generate_mipmaps(
         instance,
         logical_device,
         data,
         data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        encoding.format(),
         width,
         height,
         data.mip_levels,
@@ -1908,11 +6020,12 @@ unsafe fn create_texture_sampler(
     let info = vk::SamplerCreateInfo::builder()
         .mag_filter(vk::Filter::LINEAR)
         .min_filter(vk::Filter::LINEAR)
-        .address_mode_u(vk::SamplerAddressMode::REPEAT)
-        .address_mode_v(vk::SamplerAddressMode::REPEAT)
-        .address_mode_w(vk::SamplerAddressMode::REPEAT)
-        .anisotropy_enable(true)
-        .max_anisotropy(16.0)
+        .address_mode_u(data.address_mode)
+        .address_mode_v(data.address_mode)
+        .address_mode_w(data.address_mode)
+        .anisotropy_enable(data.max_anisotropy > 1.0)
+        .max_anisotropy(data.max_anisotropy)
+        // Seulement utilisée avec CLAMP_TO_BORDER
         .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
         .unnormalized_coordinates(false)
         .compare_enable(false)
@@ -1920,8 +6033,8 @@ unsafe fn create_texture_sampler(
         .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
         .mip_lod_bias(0.0)
         .min_lod(0.0)
-        .max_lod(data.mip_levels as f32)
-        .mip_lod_bias(0.0);
+        // Le dernier niveau des textures, 0 sans mipmaps
+        .max_lod((data.mip_levels.max(data.second_texture.mip_levels) - 1) as f32);
 
     data.texture_sampler = logical_device.create_sampler(&info, None)?;
 
@@ -1931,10 +6044,44 @@ unsafe fn create_texture_sampler(
 unsafe fn create_image(
     instance: &Instance,
     logical_device: &Device,
-    data: &AppData,
+    data: &mut AppData,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    samples: vk::SampleCountFlags,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    create_layered_image(
+        instance,
+        logical_device,
+        data,
+        width,
+        height,
+        mip_levels,
+        1,
+        vk::ImageCreateFlags::empty(),
+        samples,
+        format,
+        tiling,
+        usage,
+        properties,
+    )
+}
+
+/// `create_image` with `array_layers` layers and creation `flags` (`CUBE_COMPATIBLE` for a
+/// cube map).
+unsafe fn create_layered_image(
+    instance: &Instance,
+    logical_device: &Device,
+    data: &mut AppData,
     width: u32,
     height: u32,
     mip_levels: u32,
+    array_layers: u32,
+    flags: vk::ImageCreateFlags,
     samples: vk::SampleCountFlags,
     format: vk::Format,
     tiling: vk::ImageTiling,
@@ -1942,10 +6089,11 @@ unsafe fn create_image(
     properties: vk::MemoryPropertyFlags,
 ) -> Result<(vk::Image, vk::DeviceMemory)> {
     let info = vk::ImageCreateInfo::builder()
+        .flags(flags)
         .image_type(vk::ImageType::_2D)
         .extent(vk::Extent3D { width, height, depth:1})
         .mip_levels(mip_levels)
-        .array_layers(1)
+        .array_layers(array_layers)
         .format(format)
         .tiling(tiling)
         .initial_layout(vk::ImageLayout::UNDEFINED)
@@ -1953,31 +6101,90 @@ unsafe fn create_image(
         .samples(samples)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-    let image = logical_device.create_image(&info, None)?;
+    let image = logical_device.create_image(&info, None)?;
+
+    let requirements = logical_device.get_image_memory_requirements(image);
+
+    let memory_type_index = get_memory_type_index(instance, data, properties, requirements)?;
+
+    let image_memory = data.allocator.allocate_image(logical_device, requirements, memory_type_index)?;
+    logical_device.bind_image_memory(image, image_memory, 0)?;
+
+    Ok((image, image_memory))
+
+}
 
-    let requirements = logical_device.get_image_memory_requirements(image);
+/// Decodes the PNG texture at `path` to RGBA pixels, with its width and height.
+fn read_png_texture(path: &Path) -> Result<(Vec<u8>, u32, u32)> {
+    let image = File::open(path)?;
 
-    let info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance,
-            data,
-            properties,
-            requirements,
-        )?);
+    // EXPAND: palette et profondeurs < 8 bits vers 8 bits par canal
+    let mut decoder = png::Decoder::new(image);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
 
-    let image_memory = logical_device.allocate_memory(&info, None)?;
-    logical_device.bind_image_memory(image, image_memory, 0)?;
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels)?;
 
-    Ok((image, image_memory))
+    if info.bit_depth != png::BitDepth::Eight {
+        return Err(anyhow!("Unsupported texture bit depth ({:?}).", info.bit_depth));
+    }
+
+    // L'image Vulkan a toujours 4 octets par pixel
+    Ok((expand_to_rgba(&pixels, info.color_type), info.width, info.height))
+}
+
+/// Number of mip levels of a `width` by `height` texture in `format` (the one at `path`),
+/// 1 if mipmaps are disabled or can't be generated.
+unsafe fn get_texture_mip_levels(
+    instance: &Instance,
+    data: &AppData,
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> u32 {
+    // Sans blit linéaire possible, un seul niveau plutôt qu'une erreur
+    let mipmaps = data.mipmaps && can_generate_mipmaps(instance, data, format);
+    if data.mipmaps && !mipmaps {
+        warn!("Format {:?} can't be blitted linearly, no mipmaps for `{}`.", format, path.display());
+    }
+
+    if mipmaps {
+        (width.max(height) as f32).log2().floor() as u32 + 1
+    } else {
+        1
+    }
+}
 
+/// Converts 8 bit pixels of `color_type` (after palette expansion) to RGBA.
+fn expand_to_rgba(pixels: &[u8], color_type: png::ColorType) -> Vec<u8> {
+    match color_type {
+        png::ColorType::RGB => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::Grayscale => pixels
+            .iter()
+            .flat_map(|g| [*g, *g, *g, 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        _ => pixels.to_vec(),
+    }
 }
 
-unsafe fn create_texture_image_view(logical_device: &Device, data: &mut AppData) -> Result<()> {
+/// `encoding` must be the one the texture image was created with.
+unsafe fn create_texture_image_view(
+    logical_device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
     data.texture_image_view = create_image_view(
         logical_device,
         data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        data.texture_format,
         vk::ImageAspectFlags::COLOR,
         data.mip_levels,
     )?;
@@ -1991,17 +6198,31 @@ unsafe fn create_image_view(
     format: vk::Format,
     aspects: vk::ImageAspectFlags,
     mip_levels: u32,
+) -> Result<vk::ImageView> {
+    create_layered_image_view(logical_device, image, vk::ImageViewType::_2D, format, aspects, mip_levels, 1)
+}
+
+/// `create_image_view` of the `layer_count` first layers, as a `view_type` view (`CUBE` for
+/// the 6 layers of a cube map).
+unsafe fn create_layered_image_view(
+    logical_device: &Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    aspects: vk::ImageAspectFlags,
+    mip_levels: u32,
+    layer_count: u32,
 ) -> Result<vk::ImageView> {
     let subresource_range = vk::ImageSubresourceRange::builder()
         .aspect_mask(aspects)
         .base_mip_level(0)
         .level_count(mip_levels)
         .base_array_layer(0)
-        .layer_count(1);
+        .layer_count(layer_count);
 
     let info = vk::ImageViewCreateInfo::builder()
         .image(image)
-        .view_type(vk::ImageViewType::_2D)
+        .view_type(view_type)
         .format(format)
         .subresource_range(subresource_range);
 
@@ -2049,12 +6270,20 @@ unsafe fn end_single_time_commands(
 }
 
 ////// DEPTH STUFF //////
+/// Creates the depth image and its view, unless `data.use_depth` is false (they stay null).
 unsafe fn create_depth_objects(
     instance: &Instance,
     logical_device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
-    let format = get_depth_format(instance, data)?;
+    if !data.use_depth {
+        data.depth_image = vk::Image::null();
+        data.depth_image_memory = vk::DeviceMemory::null();
+        data.depth_image_view = vk::ImageView::null();
+        return Ok(());
+    }
+
+    let format = data.depth_format;
 
     let (depth_image, depth_image_memory) = create_image(
         instance,
@@ -2066,7 +6295,7 @@ unsafe fn create_depth_objects(
         data.msaa_samples,
         format,
         vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
@@ -2077,13 +6306,81 @@ unsafe fn create_depth_objects(
         logical_device,
         data.depth_image,
         format,
-        vk::ImageAspectFlags::DEPTH,
+        get_depth_aspect_mask(format),
         1,
     )?;
 
     Ok(())
 }
 
+/// Aspects of a depth image, including stencil for the combined formats: used for its view
+/// and its layout transitions, which must cover both aspects.
+fn get_depth_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT =>
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+/// Whether the picked device supports Vulkan 1.2 with separate depth/stencil layouts.
+unsafe fn check_separate_depth_stencil_layouts(instance: &Instance, data: &AppData) -> bool {
+    if Version::from(data.physical_device_properties.api_version) < Version::new(1, 2, 0) {
+        return false;
+    }
+
+    let mut vulkan_1_2_features = vk::PhysicalDeviceVulkan12Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut vulkan_1_2_features);
+    instance.get_physical_device_features2(data.physical_device, &mut features);
+
+    vulkan_1_2_features.separate_depth_stencil_layouts == vk::TRUE
+}
+
+/// Layout of the depth image while it is used as an attachment: only the depth
+/// aspect with separate layouts, both aspects otherwise.
+fn get_depth_layout(data: &AppData) -> vk::ImageLayout {
+    if data.separate_depth_stencil_layouts {
+        vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+    } else {
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    }
+}
+
+/// Format of the depth attachment given to the pipelines and the secondary command
+/// buffers, `UNDEFINED` without depth buffer.
+fn get_depth_attachment_format(data: &AppData) -> vk::Format {
+    if data.use_depth {
+        data.depth_format
+    } else {
+        vk::Format::UNDEFINED
+    }
+}
+
+/// Whether the depth test and write can be changed while recording (extended dynamic
+/// state, core in Vulkan 1.3 which is only used with dynamic rendering).
+fn has_dynamic_depth_state(data: &AppData) -> bool {
+    data.dynamic_rendering
+}
+
+/// Sets the depth test and write of the bound pipeline, if they are dynamic (they are baked
+/// into the pipelines otherwise).
+unsafe fn cmd_set_depth_state(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    if has_dynamic_depth_state(data) {
+        device.cmd_set_depth_test_enable(command_buffer, data.depth_test);
+        device.cmd_set_depth_write_enable(command_buffer, data.depth_write);
+    }
+}
+
+/// Aspects of the depth image to transition along with the depth, the stencil
+/// following it unless the layouts are separate.
+fn get_depth_barrier_aspect_mask(data: &AppData) -> vk::ImageAspectFlags {
+    if data.separate_depth_stencil_layouts {
+        vk::ImageAspectFlags::DEPTH
+    } else {
+        get_depth_aspect_mask(data.depth_format)
+    }
+}
+
 unsafe fn get_depth_format(
     instance: &Instance,
     data: &AppData
@@ -2129,62 +6426,332 @@ unsafe fn get_supported_format(
 }
 
 /////// MODELS //////
+
+/// Axes and handedness of the positions of a model file. The scene is Z-up and
+/// right-handed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CoordinateSystem {
+    /// Z-up, right-handed (Blender, the OBJ files of the tutorial).
+    ZUpRight,
+    /// Y-up, right-handed (glTF, Maya).
+    YUpRight,
+    /// Z-up, left-handed (Unreal).
+    ZUpLeft,
+    /// Y-up, left-handed (Unity, DirectX).
+    YUpLeft,
+}
+
+impl CoordinateSystem {
+    /// Converts a position into the coordinate system of the scene.
+    fn to_scene(self, p: glm::Vec3) -> glm::Vec3 {
+        match self {
+            Self::ZUpRight => p,
+            Self::YUpRight => glm::vec3(p.x, -p.z, p.y),
+            Self::ZUpLeft => glm::vec3(p.x, -p.y, p.z),
+            Self::YUpLeft => glm::vec3(p.x, p.z, p.y),
+        }
+    }
+
+    /// Whether the conversion is a mirror, which reverses the winding of the triangles.
+    fn flips_winding(self) -> bool {
+        matches!(self, Self::ZUpLeft | Self::YUpLeft)
+    }
+}
+
+/// The texture to use: the `configured` one, or else the `model_texture` referenced by the
+/// models, or else the default one.
+fn get_texture_path(configured: Option<&PathBuf>, model_texture: Option<&PathBuf>) -> PathBuf {
+    configured
+        .or(model_texture)
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from(TEXTURE_PATH))
+}
+
+/// Loads a mesh per model file, in the order of the files.
 unsafe fn load_models(
     data: &mut AppData,
+    paths: &[PathBuf],
+    coordinate_system: Option<CoordinateSystem>,
 ) -> Result<()> {
-    let mut reader = BufReader::new(File::open("resources/viking_room.obj")?);
+    data.meshes = paths
+        .iter()
+        .map(|p| load_model(&mut data.model_texture, p, coordinate_system))
+        .collect::<Result<_>>()?;
+
+    Ok(())
+}
 
-    let (models, _) = tobj::load_obj_buf(
+/// Loads the mesh of a model file, setting `model_texture` to the texture it references
+/// if it isn't set yet. Only reads files, so it can run on the loading thread.
+fn load_model(
+    model_texture: &mut Option<PathBuf>,
+    path: &Path,
+    coordinate_system: Option<CoordinateSystem>,
+) -> Result<Mesh> {
+    if !path.is_file() {
+        return Err(anyhow!("Model `{}` not found.", path.display()));
+    }
+
+    let mut mesh = Mesh::default();
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let default = match extension.as_deref() {
+        Some("gltf") | Some("glb") => {
+            load_gltf_model(model_texture, &mut mesh, path)?;
+            CoordinateSystem::YUpRight
+        }
+        _ => {
+            load_obj_model(&mut mesh, path)?;
+            CoordinateSystem::ZUpRight
+        }
+    };
+
+    convert_to_scene(&mut mesh, coordinate_system.unwrap_or(default));
+
+    Ok(mesh)
+}
+
+/// Converts the positions and the normals of `mesh` from `coordinate_system` into the
+/// coordinate system of the scene, keeping its front faces in front.
+fn convert_to_scene(mesh: &mut Mesh, coordinate_system: CoordinateSystem) {
+    mesh.vertices
+        .iter_mut()
+        .for_each(|v| {
+            // Un changement de repère orthogonal s'applique tel quel aux normales
+            v.pos = coordinate_system.to_scene(v.pos);
+            v.normal = coordinate_system.to_scene(v.normal);
+        });
+
+    // Sinon les faces avant deviendraient des faces arrière, supprimées par le culling
+    if coordinate_system.flips_winding() {
+        mesh.indices
+            .chunks_exact_mut(3)
+            .for_each(|t| t.swap(1, 2));
+    }
+}
+
+fn load_obj_model(mesh: &mut Mesh, path: &Path) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    // Le MTL est relatif au fichier OBJ
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    let (models, materials) = tobj::load_obj_buf(
         &mut reader,
         &tobj::LoadOptions {
             triangulate: true,
             ..Default::default()
         },
-        |_| Ok(Default::default()),
+        |p| tobj::load_mtl(directory.join(p)),
     )?;
 
+    let materials = materials.unwrap_or_else(|e| {
+        info!("No materials for `{}` ({}), the model is white.", path.display(), e);
+        vec![]
+    });
+
+    // tobj sépare les faces par matériau: une couleur par modèle
     let mut unique_vertices = HashMap::new();
 
     for model in &models {
-        for index in &model.mesh.indices {
+        let color = match model.mesh.material_id.and_then(|i| materials.get(i)) {
+            Some(material) => glm::make_vec3(&material.diffuse),
+            None => glm::vec3(1.0, 1.0, 1.0),
+        };
+
+        for (i, index) in model.mesh.indices.iter().enumerate() {
             let pos_offset = (3 * index) as usize;
-            let tex_coord_offset = (2 * index) as usize;
+            // Les UVs et les normales ont leurs propres indices, sauf avec `single_index`
+            let tex_coord_offset = 2 * model.mesh.texcoord_indices.get(i).copied().unwrap_or(*index) as usize;
+            let normal_offset = 3 * model.mesh.normal_indices.get(i).copied().unwrap_or(*index) as usize;
+
+            let pos = model.mesh.positions.get(pos_offset..pos_offset + 3).ok_or_else(|| {
+                anyhow!("Invalid position index {} in `{}`.", index, path.display())
+            })?;
 
             let vertex = Vertex {
-                pos: glm::vec3(
-                    model.mesh.positions[pos_offset],
-                    model.mesh.positions[pos_offset + 1],
-                    model.mesh.positions[pos_offset + 2],
-                ),
-                color: glm::vec3(1.0, 1.0, 1.0),
-                tex_coord: glm::vec2(
-                    model.mesh.texcoords[tex_coord_offset],
-                    1.0 - model.mesh.texcoords[tex_coord_offset + 1],
-                ),
+                pos: glm::vec3(pos[0], pos[1], pos[2]),
+                color,
+                // Sans UVs, le coin de la texture
+                tex_coord: match model.mesh.texcoords.get(tex_coord_offset..tex_coord_offset + 2) {
+                    Some(t) => glm::vec2(t[0], 1.0 - t[1]),
+                    None => glm::vec2(0.0, 0.0),
+                },
+                normal: match model.mesh.normals.get(normal_offset..normal_offset + 3) {
+                    Some(n) => glm::vec3(n[0], n[1], n[2]),
+                    None => glm::vec3(0.0, 0.0, 0.0),
+                },
             };
 
             if let Some(index) = unique_vertices.get(&vertex) {
-                data.indices.push(*index as u32);
+                mesh.indices.push(*index as u32);
             } else {
-                let index = data.vertices.len();
+                let index = mesh.vertices.len();
                 unique_vertices.insert(vertex, index);
-                data.vertices.push(vertex);
-                data.indices.push(index as u32);
+                mesh.vertices.push(vertex);
+                mesh.indices.push(index as u32);
             }
         }
     }
 
     Ok(())
 }
-////// MSAA //////
 
-unsafe fn get_max_msaa_samples(
+/// Loads the first mesh of a (static) glTF file.
+///
+/// glTF is Y-up while the scene is Z-up: positions are rotated by 90° around X.
+/// A rotation keeps the handedness, so the counter-clockwise winding of glTF still
+/// matches the pipeline's front face. glTF UVs already have their origin at the top left.
+fn load_gltf_model(model_texture: &mut Option<PathBuf>, mesh: &mut Mesh, path: &Path) -> Result<()> {
+    let gltf = gltf::Gltf::open(path)?;
+    let buffers = gltf::import_buffers(&gltf.document, path.parent(), gltf.blob.clone())?;
+
+    let gltf_mesh = gltf
+        .document
+        .meshes()
+        .next()
+        .ok_or_else(|| anyhow!("No mesh in `{}`.", path.display()))?;
+
+    for primitive in gltf_mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            warn!("Skipping non triangle primitive of `{}`.", path.display());
+            continue;
+        }
+
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+
+        let positions = reader
+            .read_positions()
+            .ok_or_else(|| anyhow!("Primitive without positions in `{}`.", path.display()))?;
+        let tex_coords = reader
+            .read_tex_coords(0)
+            .map(|t| t.into_f32().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let normals = reader
+            .read_normals()
+            .map(|n| n.collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let pbr = primitive.material().pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+
+        let offset = mesh.vertices.len() as u32;
+        for (i, [x, y, z]) in positions.enumerate() {
+            let [u, v] = tex_coords.get(i).copied().unwrap_or([0.0, 0.0]);
+            let [nx, ny, nz] = normals.get(i).copied().unwrap_or([0.0, 0.0, 0.0]);
+            mesh.vertices.push(Vertex::new(
+                glm::vec3(x, y, z),
+                glm::vec3(r, g, b),
+                glm::vec2(u, v),
+                glm::vec3(nx, ny, nz),
+            ));
+        }
+
+        match reader.read_indices() {
+            Some(indices) => mesh.indices.extend(indices.into_u32().map(|i| i + offset)),
+            None => mesh.indices.extend(offset..mesh.vertices.len() as u32),
+        }
+
+        if model_texture.is_none() {
+            *model_texture = get_gltf_texture(path, pbr.base_color_texture());
+        }
+    }
+
+    Ok(())
+}
+
+/// Path of the base color texture, when it is an external PNG (the only format we decode).
+fn get_gltf_texture(path: &Path, info: Option<gltf::texture::Info>) -> Option<PathBuf> {
+    match info?.texture().source().source() {
+        gltf::image::Source::Uri { uri, .. } if uri.to_lowercase().ends_with(".png") => {
+            Some(path.parent().unwrap_or_else(|| Path::new("")).join(uri))
+        }
+        _ => {
+            warn!("Unsupported base color texture in `{}`, using the default one.", path.display());
+            None
+        }
+    }
+}
+
+////// QUALITY //////
+
+/// Sets MSAA, anisotropy, mipmapping and presentation mode together.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum QualityPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+impl QualityPreset {
+    /// The preset after this one, wrapping around.
+    fn next(self) -> Self {
+        match self {
+            Self::Low => Self::Medium,
+            Self::Medium => Self::High,
+            Self::High => Self::Ultra,
+            Self::Ultra => Self::Low,
+        }
+    }
+
+    /// Requested MSAA samples, anisotropy, mipmapping and presentation mode,
+    /// before clamping to what the device supports.
+    fn settings(self) -> (vk::SampleCountFlags, f32, bool, vk::PresentModeKHR) {
+        match self {
+            Self::Low => (vk::SampleCountFlags::_1, 1.0, false, vk::PresentModeKHR::FIFO),
+            Self::Medium => (vk::SampleCountFlags::_2, 4.0, true, vk::PresentModeKHR::FIFO),
+            Self::High => (vk::SampleCountFlags::_4, 8.0, true, vk::PresentModeKHR::MAILBOX),
+            Self::Ultra => (vk::SampleCountFlags::_64, 16.0, true, vk::PresentModeKHR::MAILBOX),
+        }
+    }
+}
+
+/// Expands `preset` into concrete settings clamped to the device support.
+unsafe fn apply_quality_preset(
     instance: &Instance,
-    data: &AppData,
-) -> vk::SampleCountFlags {
-    let properties = instance.get_physical_device_properties(data.physical_device);
-    let counts = properties.limits.framebuffer_color_sample_counts &
-        properties.limits.framebuffer_depth_sample_counts;
+    data: &mut AppData,
+    preset: QualityPreset,
+) -> Result<()> {
+    let (samples, anisotropy, mipmaps, presentation_mode) = preset.settings();
+    let support = SwapchainSupport::get(instance, data, data.physical_device)?;
+
+    data.requested_msaa_samples = samples;
+    data.msaa_samples = get_max_msaa_samples(data, samples);
+    data.max_anisotropy = get_max_anisotropy(data, anisotropy);
+    data.mipmaps = mipmaps;
+    data.presentation_mode = get_swapchain_presentation_mode(&support.presentation_modes, presentation_mode);
+
+    info!(
+        "Quality preset {:?}: {:?} MSAA samples, {}x anisotropy, mipmaps {}, {:?} presentation.",
+        preset,
+        data.msaa_samples,
+        data.max_anisotropy,
+        if data.mipmaps { "on" } else { "off" },
+        data.presentation_mode,
+    );
+
+    Ok(())
+}
+
+////// MSAA //////
+
+/// Highest sample count supported for both color and depth, up to `max`.
+/// `anisotropy` clamped to what the device supports (1.0 disables anisotropic filtering).
+fn get_max_anisotropy(data: &AppData, anisotropy: f32) -> f32 {
+    if !data.sampler_anisotropy {
+        return 1.0;
+    }
+
+    anisotropy.min(data.physical_device_properties.limits.max_sampler_anisotropy).max(1.0)
+}
+
+fn get_max_msaa_samples(data: &AppData, max: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    let limits = &data.physical_device_properties.limits;
+    let counts = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
 
     [
         vk::SampleCountFlags::_64,
@@ -2196,7 +6763,7 @@ unsafe fn get_max_msaa_samples(
     ]
     .iter()
     .cloned()
-    .find(|c| counts.contains(*c))
+    .find(|c| c.bits() <= max.bits() && counts.contains(*c))
     .unwrap_or(vk::SampleCountFlags::_1)
 }
 
@@ -2237,6 +6804,22 @@ unsafe fn create_color_objects(
 
 ////// MIPMAPS //////
 
+/// Whether the mipmaps of an image in `format` can be generated by `generate_mipmaps`, which
+/// blits each level to the next one with a linear filter.
+unsafe fn can_generate_mipmaps(instance: &Instance, data: &AppData, format: vk::Format) -> bool {
+    instance
+        .get_physical_device_format_properties(data.physical_device, format)
+        .optimal_tiling_features
+        .contains(
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+}
+
+/// Generates the `mip_levels` of `image` from its first one, all in the
+/// `TRANSFER_DST_OPTIMAL` layout, and leaves them in `SHADER_READ_ONLY_OPTIMAL`. With a
+/// single level, it only transitions it.
 unsafe fn generate_mipmaps(
     instance: &Instance,
     logical_device: &Device,
@@ -2247,16 +6830,27 @@ unsafe fn generate_mipmaps(
     height: u32,
     mip_levels: u32,
 ) -> Result<()> {
-    if !instance
-        .get_physical_device_format_properties(data.physical_device, format)
-        .optimal_tiling_features
-        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
-    {
+    if mip_levels > 1 && !can_generate_mipmaps(instance, data, format) {
         return Err(anyhow!("Texture image format does not support linear blitting!"));
     }
 
     let command_buffer = begin_single_time_commands(logical_device, data)?;
+    cmd_generate_mipmaps(logical_device, command_buffer, image, width, height, mip_levels);
+    end_single_time_commands(logical_device, data, command_buffer)?;
+
+    Ok(())
+}
 
+/// Records the generation of the mipmaps of `generate_mipmaps`, whose format must support
+/// linear blitting if there are several levels.
+unsafe fn cmd_generate_mipmaps(
+    logical_device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) {
     let subresource = vk::ImageSubresourceRange::builder()
         .aspect_mask(vk::ImageAspectFlags::COLOR)
         .base_array_layer(0)
@@ -2371,29 +6965,76 @@ unsafe fn generate_mipmaps(
         &[] as &[vk::BufferMemoryBarrier],
         &[barrier],
     );
+}
 
 
-    end_single_time_commands(logical_device, data, command_buffer)?;
+////// ERRORS //////
 
-    Ok(())
-}
+/// Environment variable setting the minimum severity of the validation messages which are
+/// logged (`verbose`, `info`, `warning` or `error`), `warning` by default.
+const LOG_LEVEL_VARIABLE: &str = "VK_TUTORIAL_LOG_LEVEL";
 
+/// The minimum severity of the validation messages, read once from `LOG_LEVEL_VARIABLE`.
+static MIN_VALIDATION_SEVERITY: OnceLock<vk::DebugUtilsMessageSeverityFlagsEXT> = OnceLock::new();
 
-////// ERRORS //////
+/// The minimum severity set by `LOG_LEVEL_VARIABLE`, which can't be above ERROR so that errors
+/// are always logged.
+fn get_min_validation_severity() -> vk::DebugUtilsMessageSeverityFlagsEXT {
+    let value = match std::env::var(LOG_LEVEL_VARIABLE) {
+        Ok(value) => value,
+        Err(_) => return vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+    };
+
+    match value.to_lowercase().as_str() {
+        "verbose" | "trace" => vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        "info" | "debug" => vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+        "warning" | "warn" => vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+        "error" => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        _ => {
+            warn!("Invalid {} (`{}`), using `warning`.", LOG_LEVEL_VARIABLE, value);
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+        }
+    }
+}
 
-#[derive(Debug, Error)]
-#[error("Missing {0}.")]
-pub struct SuitabilityError(pub &'static str);
+/// A message received from the validation layers.
+#[derive(Clone, Debug)]
+struct ValidationMessage {
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    type_: vk::DebugUtilsMessageTypeFlagsEXT,
+    message: String,
+}
 
 extern "system" fn debug_callback(
     severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     type_: vk::DebugUtilsMessageTypeFlagsEXT,
     data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _: *mut c_void,
+    user_data: *mut c_void,
 ) -> vk::Bool32 {
+    let min_severity = MIN_VALIDATION_SEVERITY
+        .get()
+        .copied()
+        .unwrap_or(vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE);
+    if severity < min_severity {
+        return vk::FALSE;
+    }
+
     let data = unsafe { *data };
     let message = unsafe { CStr::from_ptr(data.message) }.to_string_lossy();
 
+    // Les messages VERBOSE sont trop nombreux pour être gardés
+    if !user_data.is_null() && severity >= vk::DebugUtilsMessageSeverityFlagsEXT::INFO {
+        let messages = unsafe { &*(user_data as *const Mutex<VecDeque<ValidationMessage>>) };
+
+        if let Ok(mut messages) = messages.lock() {
+            if messages.len() == MAX_VALIDATION_MESSAGES {
+                messages.pop_front();
+            }
+
+            messages.push_back(ValidationMessage { severity, type_, message: message.to_string() });
+        }
+    }
+
     if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
         error!("({:?}) {}", type_, message);
     } else if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING {
@@ -2407,9 +7048,304 @@ extern "system" fn debug_callback(
     vk::FALSE
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use winit::event_loop::EventLoopBuilder;
+
+    /// A headless app (see `AppConfig::headless`) and its hidden window, dropped in this
+    /// order. The window needs a display, and the app a Vulkan device.
+    struct HeadlessApp {
+        app: App,
+        window: Window,
+        event_loop: EventLoop<()>,
+    }
+
+    impl HeadlessApp {
+        unsafe fn new() -> Self {
+            let event_loop = create_event_loop();
+            let config = AppConfig {
+                headless: Some(HeadlessOutput { path: PathBuf::from("test.png"), frame_count: 1 }),
+                ..Default::default()
+            };
+            let window = config.get_window_builder().unwrap().with_visible(false).build(&event_loop).unwrap();
+            let app = App::create_with_config(&window, config, &|_, _| {}).unwrap();
+
+            Self { app, window, event_loop }
+        }
+    }
+
+    /// The tests don't run on the main thread, which winit requires unless told otherwise.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn create_event_loop() -> EventLoop<()> {
+        use winit::platform::unix::EventLoopBuilderExtUnix;
+        EventLoopBuilder::new().with_any_thread(true).build()
+    }
+
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    fn create_event_loop() -> EventLoop<()> {
+        EventLoopBuilder::new().build()
+    }
+
+    /// One second, for the waits which should return immediately.
+    const TIMEOUT: u64 = 1_000_000_000;
+
+    /// The bytes of the uniform buffer of `frame`.
+    unsafe fn read_uniform_buffer(app: &App, frame: usize) -> Vec<u8> {
+        let size = size_of::<UniformBufferObject>();
+        let allocation = app.data.uniform_buffers_memory[frame];
+        let memory = app
+            .logical_device
+            .map_memory(allocation.memory, allocation.offset, size as u64, vk::MemoryMapFlags::empty())
+            .unwrap();
+
+        let bytes = std::slice::from_raw_parts(memory.cast::<u8>(), size).to_vec();
+        app.logical_device.unmap_memory(allocation.memory);
+
+        bytes
+    }
+
+    /// A triangle facing +Z in the coordinate system of its file.
+    fn get_triangle() -> Mesh {
+        let normal = glm::vec3(0.0, 0.0, 1.0);
+        let vertex = |x, y| Vertex::new(glm::vec3(x, y, 0.0), glm::vec3(1.0, 1.0, 1.0), glm::vec2(x, y), normal);
+
+        Mesh {
+            vertices: vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn convert_to_scene_keeps_front_faces() {
+        let cases = [
+            (CoordinateSystem::ZUpRight, [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0, 1, 2]),
+            (CoordinateSystem::YUpRight, [0.0, 0.0, 1.0], [0.0, -1.0, 0.0], [0, 1, 2]),
+            (CoordinateSystem::ZUpLeft, [0.0, -1.0, 0.0], [0.0, 0.0, 1.0], [0, 2, 1]),
+            (CoordinateSystem::YUpLeft, [0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [0, 2, 1]),
+        ];
+
+        for (coordinate_system, third, normal, indices) in cases {
+            let mut mesh = get_triangle();
+            convert_to_scene(&mut mesh, coordinate_system);
+
+            let positions = mesh.vertices.iter().map(|v| v.pos).collect::<Vec<_>>();
+            let third = glm::Vec3::from(third);
+            let normal = glm::Vec3::from(normal);
+            assert_eq!(positions, [glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0), third], "{:?}", coordinate_system);
+            assert!(mesh.vertices.iter().all(|v| v.normal == normal), "{:?}", coordinate_system);
+            assert_eq!(mesh.indices, indices, "{:?}", coordinate_system);
+
+            // Dans l'ordre des indices, la face tourne toujours autour de sa normale
+            let [a, b, c] = indices.map(|i| positions[i as usize]);
+            assert_eq!(glm::cross(&(b - a), &(c - a)), normal, "{:?}", coordinate_system);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a display and a Vulkan device"]
+    fn swapchain_recreation_leaves_fences_signaled() {
+        unsafe {
+            let HeadlessApp { app, window, .. } = &mut HeadlessApp::new();
+
+            for _ in 0..8 {
+                app.recreate_swapchain(window).unwrap();
+
+                // Chaque frame commence par attendre sa fence, qui bloquerait si elle n'était
+                // pas signalée après la recréation
+                let fences = app.data.in_flight_fences.clone();
+                let result = app.logical_device.wait_for_fences(&fences, true, TIMEOUT);
+                assert_eq!(result, Ok(vk::SuccessCode::SUCCESS));
+
+                app.render_offscreen_frame().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a display and a Vulkan device"]
+    fn render_to_image_renders_into_the_target() {
+        unsafe {
+            let HeadlessApp { app, window, .. } = &mut HeadlessApp::new();
+
+            // Comme l'image d'une app qui intègre le renderer
+            let device = app.device().clone();
+            let extent = vk::Extent2D { width: 64, height: 32 };
+            let (image, image_memory) = create_image(
+                &app.instance,
+                &device,
+                &mut app.data,
+                extent.width,
+                extent.height,
+                1,
+                vk::SampleCountFlags::_1,
+                vk::Format::R8G8B8A8_UNORM,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+
+            for _ in 0..2 {
+                app.render_to_image(window, image, vk::Format::R8G8B8A8_UNORM, extent).unwrap();
+                assert_eq!(app.data.swapchain_images, [image]);
+                assert_eq!(app.data.swapchain_extent, extent);
+            }
+
+            let errors = app
+                .recent_validation_messages()
+                .into_iter()
+                .filter(|m| m.severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+                .map(|m| m.message)
+                .collect::<Vec<_>>();
+            assert!(errors.is_empty(), "{:?}", errors);
+
+            // L'image n'est plus utilisée une fois l'image offscreen recréée
+            app.data.render_target = None;
+            app.recreate_swapchain(window).unwrap();
+            device.destroy_image(image, None);
+            app.data.allocator.free_image(&device, image_memory);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a display and a Vulkan device"]
+    fn frames_in_flight_have_their_own_uniform_buffer() {
+        unsafe {
+            let HeadlessApp { app, .. } = &mut HeadlessApp::new();
+
+            let frames = app.data.frames_in_flight;
+            assert!(frames >= 2);
+            assert_eq!(app.data.descriptor_sets.len(), frames);
+            assert_eq!(app.data.uniform_buffers.iter().collect::<HashSet<_>>().len(), frames);
+
+            // La frame 0 est soumise sans être attendue, comme par `render`
+            app.update_uniform_buffer(0).unwrap();
+            app.update_command_buffer(0).unwrap();
+
+            let command_buffers = &[app.data.command_buffers[0]];
+            let submit_info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+            let fence = app.data.in_flight_fences[0];
+            app.logical_device.reset_fences(&[fence]).unwrap();
+            app.logical_device.queue_submit(app.data.graphics_queue, &[submit_info], fence).unwrap();
+            let submitted = read_uniform_buffer(app, 0);
+
+            // La frame 1 écrit le sien pendant que la GPU peut encore lire celui de la frame 0
+            app.frame = 1;
+            app.rotate_light(90.0);
+            app.update_uniform_buffer(1).unwrap();
+            assert_eq!(read_uniform_buffer(app, 0), submitted);
+            assert_ne!(read_uniform_buffer(app, 1), submitted);
+
+            let result = app.logical_device.wait_for_fences(&[fence], true, TIMEOUT);
+            assert_eq!(result, Ok(vk::SuccessCode::SUCCESS));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a display and a Vulkan device"]
+    fn copy_buffer_region_copies_only_the_region() {
+        unsafe {
+            let HeadlessApp { app, .. } = &mut HeadlessApp::new();
+            let (instance, device, data) = (&app.instance, &app.logical_device, &mut app.data);
+
+            let properties = vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE;
+            let (source, source_memory) =
+                create_buffer(instance, device, data, 16, vk::BufferUsageFlags::TRANSFER_SRC, properties).unwrap();
+            let (destination, destination_memory) =
+                create_buffer(instance, device, data, 16, vk::BufferUsageFlags::TRANSFER_DST, properties).unwrap();
+
+            let write = |allocation: Allocation, bytes: &[u8; 16]| {
+                let memory = device.map_memory(allocation.memory, allocation.offset, 16, vk::MemoryMapFlags::empty()).unwrap();
+                memcpy(bytes.as_ptr(), memory.cast(), 16);
+                device.unmap_memory(allocation.memory);
+            };
+            write(source_memory, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+            write(destination_memory, &[0; 16]);
+
+            let read = || {
+                let memory = device.map_memory(destination_memory.memory, destination_memory.offset, 16, vk::MemoryMapFlags::empty()).unwrap();
+                let copied = std::slice::from_raw_parts(memory.cast::<u8>(), 16).to_vec();
+                device.unmap_memory(destination_memory.memory);
+                copied
+            };
+
+            copy_buffer_region(device, data, source, 4, destination, 8, 4).unwrap();
+            device.device_wait_idle().unwrap();
+            assert_eq!(read(), [0, 0, 0, 0, 0, 0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0]);
+
+            // La destination a déjà servi à la première copie: la région de celle-ci reste
+            copy_buffer_region(device, data, source, 12, destination, 0, 4).unwrap();
+            device.device_wait_idle().unwrap();
+            assert_eq!(read(), [13, 14, 15, 16, 0, 0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0]);
+
+            device.destroy_buffer(source, None);
+            device.destroy_buffer(destination, None);
+            data.allocator.free(device, source_memory);
+            data.allocator.free(device, destination_memory);
+        }
+    }
+
+    #[test]
+    fn expand_to_rgba_adds_opaque_alpha() {
+        let rgb = expand_to_rgba(&[1, 2, 3, 4, 5, 6], png::ColorType::RGB);
+        assert_eq!(rgb, [1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let grayscale = expand_to_rgba(&[7, 8], png::ColorType::Grayscale);
+        assert_eq!(grayscale, [7, 7, 7, 255, 8, 8, 8, 255]);
+    }
+
+    #[test]
+    fn expand_to_rgba_keeps_grayscale_alpha() {
+        let grayscale_alpha = expand_to_rgba(&[7, 100, 8, 0], png::ColorType::GrayscaleAlpha);
+        assert_eq!(grayscale_alpha, [7, 7, 7, 100, 8, 8, 8, 0]);
+    }
+
+    #[test]
+    fn read_png_texture_expands_rgb() {
+        // Une face du skybox, en RGB 8 bits
+        let (pixels, width, height) = read_png_texture(Path::new("resources/skybox/px.png")).unwrap();
 
+        assert!(width > 0 && height > 0);
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+        assert!(pixels.chunks_exact(4).all(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn read_png_texture_reads_the_font_atlas() {
+        // Copié tel quel dans une image R8G8B8A8 (voir create_text_atlas)
+        let (pixels, width, height) = read_png_texture(Path::new("resources/font_sdf.png")).unwrap();
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+    }
+
+    /// Loads the OBJ `source` from a temporary file.
+    fn load_obj_source(name: &str, source: &str) -> Result<Mesh> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, source)?;
 
+        let mut mesh = Mesh::default();
+        let result = load_obj_model(&mut mesh, &path);
+        let _ = std::fs::remove_file(&path);
+        result.map(|_| mesh)
+    }
 
+    #[test]
+    fn load_obj_model_without_tex_coords() {
+        let mesh = load_obj_source("no_uv.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
 
+        assert_eq!(mesh.vertices.len(), 3);
+        assert!(mesh.vertices.iter().all(|v| v.tex_coord == glm::vec2(0.0, 0.0)));
+    }
 
+    #[test]
+    fn load_obj_model_uses_tex_coord_indices() {
+        // Les UVs dans l'ordre inverse des positions
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.25 0\nvt 0.5 0\nvt 0.75 0\nf 1/3 2/2 3/1\n";
+        let mesh = load_obj_source("uv_indices.obj", source).unwrap();
 
+        let tex_coords = mesh.indices.iter().map(|i| mesh.vertices[*i as usize].tex_coord.x).collect::<Vec<_>>();
+        assert_eq!(tex_coords, vec![0.75, 0.5, 0.25]);
+    }
+}
@@ -0,0 +1,288 @@
+//! Selection of the model under the cursor (GPU picking).
+//!
+//! The models write their index to a second color attachment, in `PICKING_FORMAT`, during
+//! the main pass. On a click the texel under the cursor is copied to a host visible buffer
+//! and read back. With MSAA the attachment is resolved to a single sample image (sample 0,
+//! the indices can't be averaged), which is the one read.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    begin_single_time_commands, create_buffer, create_image, create_image_view,
+    end_single_time_commands, get_attachment_barrier, AppData,
+};
+
+/// Format of the model indices.
+pub const PICKING_FORMAT: vk::Format = vk::Format::R32_UINT;
+/// Index of the pixels where no model is drawn (the clear value).
+pub const NO_MODEL: u32 = u32::MAX;
+
+/// The attachment the model indices are written to.
+#[derive(Clone, Debug, Default)]
+pub struct PickingData {
+    image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    image_view: vk::ImageView,
+    /// Single sample image the attachment is resolved to with MSAA, null otherwise.
+    resolve_image: vk::Image,
+    resolve_image_memory: vk::DeviceMemory,
+    resolve_image_view: vk::ImageView,
+}
+
+/// Creates the attachment (and its resolve image with MSAA), with the swapchain objects.
+pub unsafe fn create_picking_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+    let extent = data.swapchain_extent;
+
+    // Avec MSAA seule l'image résolue est copiée
+    let usage = if multisampled {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+    } else {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC
+    };
+
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        extent.width,
+        extent.height,
+        1,
+        data.msaa_samples,
+        PICKING_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.picking.image = image;
+    data.picking.image_memory = image_memory;
+    data.picking.image_view = create_image_view(device, image, PICKING_FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+
+    if multisampled {
+        let (resolve_image, resolve_image_memory) = create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            vk::SampleCountFlags::_1,
+            PICKING_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        data.picking.resolve_image = resolve_image;
+        data.picking.resolve_image_memory = resolve_image_memory;
+        data.picking.resolve_image_view =
+            create_image_view(device, resolve_image, PICKING_FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+    }
+
+    Ok(())
+}
+
+pub unsafe fn destroy_picking_objects(device: &Device, data: &mut AppData) {
+    let picking = &data.picking;
+    device.destroy_image_view(picking.resolve_image_view, None);
+    device.destroy_image(picking.resolve_image, None);
+    data.allocator.free_image(device, picking.resolve_image_memory);
+    device.destroy_image_view(picking.image_view, None);
+    device.destroy_image(picking.image, None);
+    data.allocator.free_image(device, picking.image_memory);
+
+    data.picking = PickingData::default();
+}
+
+/// The formats of the color attachments of the main pass: the swapchain image, then the
+/// model indices.
+pub fn get_color_attachment_formats(data: &AppData) -> [vk::Format; 2] {
+    [data.swapchain_format, PICKING_FORMAT]
+}
+
+/// The render pass attachments of the model indices: the attachment, then its resolve
+/// image with MSAA. Both are left as color attachments, `read_picked_model` transitions them.
+pub fn get_picking_attachment_descriptions(data: &AppData) -> Vec<vk::AttachmentDescription> {
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+
+    let attachment = vk::AttachmentDescription::builder()
+        .format(PICKING_FORMAT)
+        .samples(data.msaa_samples)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(if multisampled { vk::AttachmentStoreOp::DONT_CARE } else { vk::AttachmentStoreOp::STORE })
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build();
+
+    // Les formats entiers sont résolus avec le premier échantillon
+    let resolve_attachment = vk::AttachmentDescription::builder()
+        .format(PICKING_FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build();
+
+    if multisampled {
+        vec![attachment, resolve_attachment]
+    } else {
+        vec![attachment]
+    }
+}
+
+/// The views of the attachments of `get_picking_attachment_descriptions`, in the same order.
+pub fn get_picking_attachment_views(data: &AppData) -> Vec<vk::ImageView> {
+    if data.msaa_samples != vk::SampleCountFlags::_1 {
+        vec![data.picking.image_view, data.picking.resolve_image_view]
+    } else {
+        vec![data.picking.image_view]
+    }
+}
+
+pub fn get_picking_clear_value() -> vk::ClearValue {
+    vk::ClearValue {
+        color: vk::ClearColorValue {
+            uint32: [NO_MODEL, 0, 0, 0],
+        },
+    }
+}
+
+/// The blend state of the model indices, written as is (integers can't be blended) by the
+/// pipelines of the models if `write` is set, left untouched otherwise.
+pub fn get_picking_blend_attachment(write: bool) -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(if write { vk::ColorComponentFlags::R } else { vk::ColorComponentFlags::empty() })
+        .blend_enable(false)
+        .build()
+}
+
+/// The transitions of the attachments before dynamic rendering, from `UNDEFINED` like the
+/// render pass.
+pub fn get_picking_barriers(data: &AppData) -> Vec<vk::ImageMemoryBarrier> {
+    [data.picking.image, data.picking.resolve_image]
+        .into_iter()
+        .filter(|i| !i.is_null())
+        .map(|image| {
+            get_attachment_barrier(
+                image,
+                vk::ImageAspectFlags::COLOR,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )
+        })
+        .collect()
+}
+
+/// The attachment of the model indices with dynamic rendering.
+pub fn get_picking_rendering_attachment(data: &AppData) -> vk::RenderingAttachmentInfo {
+    let attachment = vk::RenderingAttachmentInfo::builder()
+        .image_view(data.picking.image_view)
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(get_picking_clear_value());
+
+    if data.msaa_samples != vk::SampleCountFlags::_1 {
+        attachment
+            .resolve_mode(vk::ResolveModeFlags::SAMPLE_ZERO)
+            .resolve_image_view(data.picking.resolve_image_view)
+            .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()
+    } else {
+        attachment.build()
+    }
+}
+
+/// Reads back the index of the model drawn at the pixel (`x`, `y`) of the last rendered
+/// frame, `None` if there is none. The device must be idle.
+pub unsafe fn read_picked_model(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    x: u32,
+    y: u32,
+) -> Result<Option<usize>> {
+    let image = if data.picking.resolve_image.is_null() {
+        data.picking.image
+    } else {
+        data.picking.resolve_image
+    };
+
+    let (buffer, buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size_of::<u32>() as u64,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    // Pas besoin de revenir au layout d'origine: la frame suivante part de UNDEFINED
+    let barrier = get_attachment_barrier(
+        image,
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        vk::AccessFlags::TRANSFER_READ,
+    );
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: x as i32, y: y as i32, z: 0 })
+        .image_extent(vk::Extent3D { width: 1, height: 1, depth: 1 });
+
+    device.cmd_copy_image_to_buffer(command_buffer, image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer, &[region]);
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+    let memory = device.map_memory(
+        buffer_memory.memory,
+        buffer_memory.offset,
+        size_of::<u32>() as u64,
+        vk::MemoryMapFlags::empty(),
+    )?;
+    let index = *memory.cast::<u32>();
+    device.unmap_memory(buffer_memory.memory);
+
+    device.destroy_buffer(buffer, None);
+    data.allocator.free(device, buffer_memory);
+
+    Ok(if index == NO_MODEL { None } else { Some(index as usize) })
+}
@@ -0,0 +1,289 @@
+//! Multi-threaded recording of the per-model secondary command buffers.
+//!
+//! The threads are started with the command pools and wait for a job on their channel
+//! each frame, rather than being spawned for every frame.
+//!
+//! Each thread starts with a contiguous share of the models in its own queue and, once it
+//! is empty, steals models from the back of the other queues, so a thread slowed down
+//! (by the OS or by heavier models) doesn't hold back the whole frame.
+//!
+//! Command pools are externally synchronized: a pool, and every command buffer allocated
+//! from it, must only be used by one thread at a time. Each swapchain image therefore has
+//! one pool per recording thread (`ThreadCommandPool`), handed by `&mut` to its thread for
+//! the duration of the recording and reset by that thread only. The per-image pool of
+//! `AppData::command_pools` stays on the main thread, for the primary command buffer and
+//! the other secondary ones. A pool of an image is only reset once the last frame using
+//! that image is finished (its fence is waited for by `App::render`). The rest of `AppData`
+//! is only read by the threads.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::visibility::is_model_recorded;
+use crate::{create_command_pool, record_model_command_buffer, AppData};
+
+/// A command pool owned by a single recording thread, with the secondary command
+/// buffers allocated from it.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadCommandPool {
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+/// The Vulkan handles used by the recording threads, and the threads.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingData {
+    /// Command pools per swapchain image, then per thread.
+    command_pools: Vec<Vec<ThreadCommandPool>>,
+    threads: Arc<Vec<RecordingThread>>,
+}
+
+/// The models recorded by a thread, with their command buffers.
+type Recorded = Vec<(usize, vk::CommandBuffer)>;
+
+/// A recording thread, waiting for its next job.
+#[derive(Debug)]
+struct RecordingThread {
+    jobs: Sender<Job>,
+    /// Only received from by the main thread, the mutex keeping `AppData` shareable.
+    results: Mutex<Receiver<Result<Recorded>>>,
+    handle: JoinHandle<()>,
+}
+
+/// The arguments of `record_thread`, borrowed by `record_model_command_buffers` which waits
+/// for the result of the job before returning.
+struct Job {
+    device: *const Device,
+    data: *const AppData,
+    pool: *mut ThreadCommandPool,
+    image_index: usize,
+    frame: usize,
+    models: *const [glm::Mat4],
+    selected_model: Option<usize>,
+    queues: *const [Mutex<VecDeque<usize>>],
+    thread: usize,
+}
+
+// Les pointeurs restent valides tant que record_model_command_buffers attend le résultat
+unsafe impl Send for Job {}
+
+/// The maximum number of recording threads (one per hardware thread).
+pub fn get_max_recording_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Starts `threads` recording threads (at least one) and creates a command pool per
+/// swapchain image for each of them.
+pub unsafe fn create_recording_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    threads: usize,
+) -> Result<()> {
+    let threads = threads.max(1);
+    data.recording.command_pools.clear();
+    data.recording.threads = Arc::new((0..threads).map(spawn_recording_thread).collect::<Result<_>>()?);
+
+    for _ in 0..data.swapchain_images.len() {
+        let command_pools = (0..threads)
+            .map(|_| {
+                Ok(ThreadCommandPool {
+                    command_pool: create_command_pool(instance, device, data)?,
+                    command_buffers: vec![],
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        data.recording.command_pools.push(command_pools);
+    }
+
+    Ok(())
+}
+
+fn spawn_recording_thread(index: usize) -> Result<RecordingThread> {
+    let (jobs, job_receiver) = mpsc::channel::<Job>();
+    let (result_sender, results) = mpsc::channel();
+
+    // S'arrête quand destroy_recording_objects ferme son channel
+    let handle = thread::Builder::new()
+        .name(format!("recording {}", index))
+        .spawn(move || {
+            for job in job_receiver {
+                let result = unsafe {
+                    record_thread(
+                        &*job.device,
+                        &*job.data,
+                        &mut *job.pool,
+                        job.image_index,
+                        job.frame,
+                        &*job.models,
+                        job.selected_model,
+                        &*job.queues,
+                        job.thread,
+                    )
+                };
+
+                if result_sender.send(result).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+    Ok(RecordingThread { jobs, results: Mutex::new(results), handle })
+}
+
+/// Records one secondary command buffer per model (`models` being their model matrices)
+/// on `threads` threads, tinting the `selected_model`, and returns them in the order of the
+/// models (`None` for the culled ones, see `is_model_recorded`).
+pub unsafe fn record_model_command_buffers(
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    frame: usize,
+    models: &[glm::Mat4],
+    selected_model: Option<usize>,
+    threads: usize,
+) -> Result<Vec<Option<vk::CommandBuffer>>> {
+    let recording_threads = data.recording.threads.clone();
+    let available = data.recording.command_pools[image_index].len().min(recording_threads.len());
+    if available == 0 {
+        return Err(anyhow!("No recording thread."));
+    }
+
+    // Chaque thread a besoin de son pool en &mut, le reste de data est partagé
+    let mut command_pools = mem::take(&mut data.recording.command_pools[image_index]);
+    let threads = threads.clamp(1, available);
+
+    // Les modèles culled ne sont pas répartis
+    let recorded = (0..models.len())
+        .filter(|i| is_model_recorded(data, *i))
+        .collect::<Vec<_>>();
+    let queues = (0..threads)
+        .map(|t| {
+            let share = (t * recorded.len() / threads)..((t + 1) * recorded.len() / threads);
+            Mutex::new(recorded[share].iter().copied().collect::<VecDeque<_>>())
+        })
+        .collect::<Vec<_>>();
+
+    let mut sent = 0;
+    for (thread, pool) in command_pools[..threads].iter_mut().enumerate() {
+        let job = Job {
+            device,
+            data: &*data,
+            pool,
+            image_index,
+            frame,
+            models,
+            selected_model,
+            queues: &queues[..],
+            thread,
+        };
+
+        if recording_threads[thread].jobs.send(job).is_err() {
+            break;
+        }
+        sent += 1;
+    }
+
+    // Chaque job envoyé est attendu, même après une erreur: les threads utilisent encore
+    // les pools, data et les queues
+    let results = recording_threads[..sent]
+        .iter()
+        .map(|t| t.results.lock().unwrap().recv().map_err(|_| anyhow!("Recording thread panicked."))?)
+        .collect::<Vec<_>>();
+    let results = if sent < threads {
+        Err(anyhow!("Recording thread panicked."))
+    } else {
+        results.into_iter().collect::<Result<Vec<_>>>()
+    };
+
+    // Les pools sont rendus avant de propager une éventuelle erreur
+    data.recording.command_pools[image_index] = command_pools;
+
+    let mut command_buffers = vec![None; models.len()];
+    for (model_index, command_buffer) in results?.concat() {
+        command_buffers[model_index] = Some(command_buffer);
+    }
+
+    Ok(command_buffers)
+}
+
+/// Records models until every queue is empty, returning the recorded command buffers
+/// with the index of their model.
+unsafe fn record_thread(
+    device: &Device,
+    data: &AppData,
+    pool: &mut ThreadCommandPool,
+    image_index: usize,
+    frame: usize,
+    models: &[glm::Mat4],
+    selected_model: Option<usize>,
+    queues: &[Mutex<VecDeque<usize>>],
+    thread: usize,
+) -> Result<Recorded> {
+    device.reset_command_pool(pool.command_pool, vk::CommandPoolResetFlags::empty())?;
+
+    let mut recorded = vec![];
+
+    while let Some(model_index) = get_next_model(queues, thread) {
+        if recorded.len() == pool.command_buffers.len() {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(pool.command_pool)
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .command_buffer_count(1);
+
+            pool.command_buffers.push(device.allocate_command_buffers(&allocate_info)?[0]);
+        }
+
+        let command_buffer = pool.command_buffers[recorded.len()];
+        record_model_command_buffer(
+            device,
+            data,
+            command_buffer,
+            image_index,
+            frame,
+            model_index,
+            &models[model_index],
+            selected_model,
+        )?;
+
+        recorded.push((model_index, command_buffer));
+    }
+
+    Ok(recorded)
+}
+
+/// Takes the next model from the front of the queue of `thread`, or else steals one
+/// from the back of another queue.
+fn get_next_model(queues: &[Mutex<VecDeque<usize>>], thread: usize) -> Option<usize> {
+    if let Some(model_index) = queues[thread].lock().unwrap().pop_front() {
+        return Some(model_index);
+    }
+
+    (1..queues.len())
+        .map(|offset| (thread + offset) % queues.len())
+        .find_map(|victim| queues[victim].lock().unwrap().pop_back())
+}
+
+pub unsafe fn destroy_recording_objects(device: &Device, data: &mut AppData) {
+    data.recording.command_pools
+        .iter()
+        .flatten()
+        .for_each(|p| device.destroy_command_pool(p.command_pool, None));
+    data.recording.command_pools.clear();
+
+    // Sans job en cours, les threads s'arrêtent dès que leur channel est fermé
+    if let Ok(threads) = Arc::try_unwrap(mem::take(&mut data.recording.threads)) {
+        for RecordingThread { jobs, handle, .. } in threads {
+            drop(jobs);
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! A skybox: a cube map drawn behind the models, an optional mode toggled at runtime
+//! (`App::set_skybox`).
+//!
+//! The six faces are the layers of a `CUBE_COMPATIBLE` image, sampled through a `CUBE` view
+//! bound to the descriptor sets of the models (binding 4). `skybox.vert` builds the cube from
+//! the vertex index and draws it at the far plane (`xyww`), centered on the camera: drawn
+//! first, with `LESS_OR_EQUAL` and without depth writes, it is covered by everything else.
+
+use std::mem::size_of;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::buffers::stage;
+use crate::picking::{get_color_attachment_formats, get_picking_blend_attachment};
+use crate::{
+    begin_secondary_command_buffer, begin_single_time_commands, create_layered_image, create_layered_image_view,
+    create_shader_module, end_single_time_commands, get_depth_attachment_format, get_output_transfer,
+    read_png_texture, read_shader, AppData, TextureEncoding,
+};
+
+/// Directory of the faces, in the order of the layers of a cube map.
+const SKYBOX_PATH: &str = "resources/skybox";
+const FACES: [&str; 6] = ["px.png", "nx.png", "py.png", "ny.png", "pz.png", "nz.png"];
+/// Number of vertices of the cube drawn by `skybox.vert`.
+const VERTEX_COUNT: u32 = 36;
+
+/// The Vulkan handles of the skybox, null until it is first enabled.
+#[derive(Clone, Debug, Default)]
+pub struct SkyboxData {
+    image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    image_view: vk::ImageView,
+    sampler: vk::Sampler,
+    pipeline: vk::Pipeline,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+/// Whether the skybox has been created.
+pub fn has_skybox(data: &AppData) -> bool {
+    !data.skybox.image.is_null()
+}
+
+/// The cube map and its sampler to bind, if the skybox has been created.
+pub fn get_skybox_descriptor(data: &AppData) -> Option<vk::DescriptorImageInfo> {
+    has_skybox(data).then(|| {
+        vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.skybox.image_view)
+            .sampler(data.skybox.sampler)
+            .build()
+    })
+}
+
+/////// CUBE MAP ///////
+
+/// Loads the faces in `SKYBOX_PATH` into the cube map and creates its sampler, then the
+/// pipeline (`create_skybox_swapchain_objects`). The descriptor sets must be written again.
+pub unsafe fn create_skybox_objects(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let mut pixels = vec![];
+    let mut size = None;
+    for face in FACES {
+        let path = Path::new(SKYBOX_PATH).join(face);
+        let (face_pixels, width, height) = read_png_texture(&path)
+            .map_err(|e| anyhow!("Could not read skybox face `{}` ({}).", path.display(), e))?;
+
+        // Les faces d'un cube sont carrées et de même taille
+        if width != height || size.is_some_and(|s| s != width) {
+            return Err(anyhow!("Skybox face `{}` is not a square like the others.", path.display()));
+        }
+
+        size = Some(width);
+        pixels.extend(face_pixels);
+    }
+
+    let size = size.unwrap();
+    let format = TextureEncoding::Srgb.format();
+    let staging_buffer = stage(instance, device, data, &pixels)?;
+
+    let (image, image_memory) = create_layered_image(
+        instance,
+        device,
+        data,
+        size,
+        size,
+        1,
+        FACES.len() as u32,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.skybox.image = image;
+    data.skybox.image_memory = image_memory;
+
+    upload_faces(device, data, staging_buffer, size)?;
+
+    data.skybox.image_view = create_layered_image_view(
+        device,
+        image,
+        vk::ImageViewType::CUBE,
+        format,
+        vk::ImageAspectFlags::COLOR,
+        1,
+        FACES.len() as u32,
+    )?;
+
+    // CLAMP_TO_EDGE: les bords des faces ne sont pas mélangés avec le côté opposé
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    data.skybox.sampler = device.create_sampler(&info, None)?;
+
+    create_skybox_swapchain_objects(device, data)
+}
+
+/// Copies the faces, one after the other in `staging_buffer`, to the layers of the cube map
+/// and transitions them for sampling.
+unsafe fn upload_faces(device: &Device, data: &AppData, staging_buffer: vk::Buffer, size: u32) -> Result<()> {
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(FACES.len() as u32);
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(data.skybox.image)
+        .subresource_range(subresource_range)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    // Les couches sont contiguës dans le buffer
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(FACES.len() as u32);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D { width: size, height: size, depth: 1 });
+
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        staging_buffer,
+        data.skybox.image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+    );
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(data.skybox.image)
+        .subresource_range(subresource_range)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    end_single_time_commands(device, data, command_buffer)
+}
+
+/////// PIPELINE ///////
+
+/// Creates the pipeline of the skybox, for the current swapchain (if the skybox has been
+/// created). It uses the layout of the models, for their uniform buffer and descriptor sets.
+pub unsafe fn create_skybox_swapchain_objects(device: &Device, data: &mut AppData) -> Result<()> {
+    if !has_skybox(data) {
+        return Ok(());
+    }
+
+    let vert = read_shader(data, "skybox_vert.spv")?;
+    let frag = read_shader(data, "skybox_frag.spv")?;
+
+    let vert_shader_module = create_shader_module(device, &vert[..])?;
+    let frag_shader_module = create_shader_module(device, &frag[..])?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let output_transfer = get_output_transfer(data).to_ne_bytes();
+    let map_entries = &[vk::SpecializationMapEntry::builder()
+        .constant_id(0)
+        .offset(0)
+        .size(size_of::<u32>())];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&output_transfer);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    // Pas de vertex buffer: les sommets sont calculés depuis gl_VertexIndex
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Le viewport et le scissor sont dynamiques, fixés par `begin_secondary_command_buffer`
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    // Le cube est vu de l'intérieur
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Au plan lointain (1.0, la valeur d'effacement): LESS_OR_EQUAL pour qu'il passe
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment.build(), get_picking_blend_attachment(false)];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+        .dynamic_states(dynamic_states);
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(0);
+
+    let color_attachment_formats = &get_color_attachment_formats(data);
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_attachment_format(data));
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.skybox.pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/////// DRAWING ///////
+
+/// Records a secondary command buffer drawing the skybox with the descriptor set of `frame`.
+pub unsafe fn update_skybox_command_buffer(
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    frame: usize,
+) -> Result<vk::CommandBuffer> {
+    while image_index >= data.skybox.command_buffers.len() {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(data.command_pools[data.skybox.command_buffers.len()])
+            .level(vk::CommandBufferLevel::SECONDARY)
+            .command_buffer_count(1);
+
+        let command_buffer = device.allocate_command_buffers(&allocate_info)?[0];
+        data.skybox.command_buffers.push(command_buffer);
+    }
+
+    let command_buffer = data.skybox.command_buffers[image_index];
+
+    begin_secondary_command_buffer(device, data, command_buffer, image_index)?;
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.skybox.pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline_layout,
+        0,
+        &[data.descriptor_sets[frame]],
+        &[],
+    );
+    device.cmd_draw(command_buffer, VERTEX_COUNT, 1, 0, 0);
+
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(command_buffer)
+}
+
+/////// DESTRUCTION ///////
+
+pub unsafe fn destroy_skybox_swapchain_objects(device: &Device, data: &mut AppData) {
+    device.destroy_pipeline(data.skybox.pipeline, None);
+    data.skybox.pipeline = vk::Pipeline::null();
+}
+
+/// Forgets the command buffers of the skybox, freed with the command pools of the swapchain
+/// images they were allocated from.
+pub fn clear_skybox_command_buffers(data: &mut AppData) {
+    data.skybox.command_buffers.clear();
+}
+
+pub unsafe fn destroy_skybox_objects(device: &Device, data: &mut AppData) {
+    destroy_skybox_swapchain_objects(device, data);
+
+    device.destroy_sampler(data.skybox.sampler, None);
+    device.destroy_image_view(data.skybox.image_view, None);
+    device.destroy_image(data.skybox.image, None);
+    data.allocator.free_image(device, data.skybox.image_memory);
+
+    // Les command buffers restent dans les pools des images
+    let command_buffers = std::mem::take(&mut data.skybox.command_buffers);
+    data.skybox = SkyboxData { command_buffers, ..Default::default() };
+}
@@ -0,0 +1,304 @@
+//! Textures replaced at runtime (dropped on the window) without stalling the render loop.
+//!
+//! Unlike `create_texture_image`, whose uploads are waited for, the upload of a streamed
+//! texture is submitted with a fence that `App::render` polls each frame. Meanwhile the
+//! models are drawn with a placeholder texture, and once the fence is signaled the descriptor
+//! sets are switched to the new texture.
+//!
+//! A descriptor set can't be updated while a frame using it is in flight: each one is
+//! rewritten at the start of its frame, once its fence was waited for, and the previous
+//! texture is destroyed when none of them references it anymore.
+
+use std::path::{Path, PathBuf};
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator::{Allocation, Allocator};
+use crate::buffers::stage;
+use crate::ktx::is_ktx2;
+use crate::{
+    cmd_copy_buffer_to_image, cmd_generate_mipmaps, cmd_transition_image_layout, copy_buffer_to_image,
+    create_buffer, create_image, create_image_view, get_texture_mip_levels, read_png_texture,
+    transition_image_layout, write_texture_descriptor_set, AppData, TextureEncoding,
+};
+
+/// Color of the placeholder texture (sRGB encoded), a neutral grey.
+const PLACEHOLDER_COLOR: [u8; 4] = [128, 128, 128, 255];
+
+/// A texture image and its view.
+#[derive(Clone, Debug, Default)]
+struct StreamedTexture {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+}
+
+impl StreamedTexture {
+    unsafe fn destroy(&self, device: &Device, allocator: &mut Allocator) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        allocator.free_image(device, self.memory);
+    }
+}
+
+/// An upload in progress.
+#[derive(Clone, Debug)]
+struct PendingTexture {
+    path: PathBuf,
+    texture: StreamedTexture,
+    mip_levels: u32,
+    /// Its own staging buffer, the shared one may be reused before the upload finishes.
+    staging_buffer: vk::Buffer,
+    staging_buffer_memory: Allocation,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StreamingData {
+    /// Drawn instead of the first texture while a texture is streamed, created on the first one.
+    placeholder: StreamedTexture,
+    pending: Option<PendingTexture>,
+    /// The descriptor sets (per frame in flight) to rewrite at the start of their next frame,
+    /// empty until a texture is streamed.
+    outdated_sets: Vec<bool>,
+    /// Previous textures, destroyed once no descriptor set is outdated.
+    retired: Vec<StreamedTexture>,
+}
+
+/// Whether a texture is being streamed.
+pub fn is_streaming_texture(data: &AppData) -> bool {
+    data.streaming.pending.is_some()
+}
+
+/// The view of the first texture to bind: the placeholder while a texture is streamed.
+pub fn get_texture_view(data: &AppData) -> vk::ImageView {
+    if is_streaming_texture(data) {
+        data.streaming.placeholder.view
+    } else {
+        data.texture_image_view
+    }
+}
+
+/// Starts streaming the PNG texture at `path` to replace the first texture, submitting its
+/// upload (and the generation of its mipmaps) without waiting for it.
+pub unsafe fn start_texture_stream(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<()> {
+    if is_streaming_texture(data) {
+        return Err(anyhow!("A texture is already being streamed."));
+    }
+    if is_ktx2(path) {
+        return Err(anyhow!("KTX2 textures can't be streamed (`{}`).", path.display()));
+    }
+
+    // Le décodage reste synchrone, seul l'upload n'est pas attendu
+    let (pixels, width, height) = read_png_texture(path)?;
+    let format = TextureEncoding::Srgb.format();
+    let mip_levels = get_texture_mip_levels(instance, data, format, width, height, path);
+
+    if data.streaming.placeholder.image.is_null() {
+        create_placeholder_texture(instance, device, data)?;
+    }
+
+    let size = pixels.len() as u64;
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let memory = device.map_memory(
+        staging_buffer_memory.memory,
+        staging_buffer_memory.offset,
+        size,
+        vk::MemoryMapFlags::empty(),
+    )?;
+    memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
+    device.unmap_memory(staging_buffer_memory.memory);
+
+    let (image, memory) = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let view = create_image_view(device, image, format, vk::ImageAspectFlags::COLOR, mip_levels)?;
+
+    // Les mêmes commandes que create_texture_image, dans un seul command buffer
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(data.command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    cmd_transition_image_layout(
+        device,
+        command_buffer,
+        image,
+        format,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        mip_levels,
+    )?;
+    cmd_copy_buffer_to_image(device, command_buffer, staging_buffer, image, width, height);
+    cmd_generate_mipmaps(device, command_buffer, image, width, height, mip_levels);
+
+    device.end_command_buffer(command_buffer)?;
+
+    let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+    let command_buffers = &[command_buffer];
+    let info = vk::SubmitInfo::builder()
+        .command_buffers(command_buffers);
+
+    device.queue_submit(data.graphics_queue, &[info], fence)?;
+
+    data.streaming.pending = Some(PendingTexture {
+        path: path.to_path_buf(),
+        texture: StreamedTexture { image, memory, view },
+        mip_levels,
+        staging_buffer,
+        staging_buffer_memory,
+        command_buffer,
+        fence,
+    });
+
+    // Le placeholder est affiché en attendant
+    data.streaming.outdated_sets = vec![true; data.frames_in_flight];
+
+    info!("Streaming texture `{}` ({}x{}).", path.display(), width, height);
+
+    Ok(())
+}
+
+/// Polls the streamed texture, replacing the first texture with it once uploaded, and
+/// rewrites the descriptor set of `frame` if it is outdated. Called at the start of `frame`,
+/// once its fence was waited for.
+pub unsafe fn update_texture_stream(device: &Device, data: &mut AppData, frame: usize) -> Result<()> {
+    let uploaded = match &data.streaming.pending {
+        Some(pending) => device.get_fence_status(pending.fence)? == vk::SuccessCode::SUCCESS,
+        None => false,
+    };
+
+    if uploaded {
+        let pending = data.streaming.pending.take().unwrap();
+
+        device.destroy_fence(pending.fence, None);
+        device.free_command_buffers(data.command_pool, &[pending.command_buffer]);
+        device.destroy_buffer(pending.staging_buffer, None);
+        data.allocator.free(device, pending.staging_buffer_memory);
+
+        // L'ancienne texture peut encore être utilisée par les frames en cours
+        data.streaming.retired.push(StreamedTexture {
+            image: data.texture_image,
+            memory: data.texture_image_memory,
+            view: data.texture_image_view,
+        });
+
+        data.texture_image = pending.texture.image;
+        data.texture_image_memory = pending.texture.memory;
+        data.texture_image_view = pending.texture.view;
+        data.texture_format = TextureEncoding::Srgb.format();
+        // Le sampler garde son max_lod: une texture plus grande n'utilise pas ses derniers niveaux
+        data.mip_levels = pending.mip_levels;
+        data.texture_path = pending.path;
+
+        data.streaming.outdated_sets = vec![true; data.frames_in_flight];
+
+        info!("Texture `{}` streamed.", data.texture_path.display());
+    }
+
+    if data.streaming.outdated_sets.get(frame) == Some(&true) {
+        write_texture_descriptor_set(device, data, frame);
+        data.streaming.outdated_sets[frame] = false;
+    }
+
+    // Plus aucun descriptor set ne référence les anciennes textures, et les frames qui les
+    // utilisaient sont finies (leurs fences ont été attendues avant de réécrire leurs sets)
+    if !data.streaming.outdated_sets.contains(&true) {
+        for texture in data.streaming.retired.drain(..) {
+            texture.destroy(device, &mut data.allocator);
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the 1x1 placeholder texture, uploaded synchronously (it is tiny).
+unsafe fn create_placeholder_texture(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let format = TextureEncoding::Srgb.format();
+    let staging_buffer = stage(instance, device, data, &PLACEHOLDER_COLOR)?;
+
+    let (image, memory) = create_image(
+        instance,
+        device,
+        data,
+        1,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.streaming.placeholder = StreamedTexture {
+        image,
+        memory,
+        view: create_image_view(device, image, format, vk::ImageAspectFlags::COLOR, 1)?,
+    };
+
+    copy_buffer_to_image(device, data, staging_buffer, image, 1, 1, 1)?;
+    transition_image_layout(
+        device,
+        data,
+        image,
+        format,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        1,
+    )
+}
+
+/// Destroys the placeholder, the previous textures and the texture being streamed, once the
+/// device is idle.
+pub unsafe fn destroy_streaming_objects(device: &Device, data: &mut AppData) {
+    if let Some(pending) = data.streaming.pending.take() {
+        device.destroy_fence(pending.fence, None);
+        device.free_command_buffers(data.command_pool, &[pending.command_buffer]);
+        device.destroy_buffer(pending.staging_buffer, None);
+        data.allocator.free(device, pending.staging_buffer_memory);
+        pending.texture.destroy(device, &mut data.allocator);
+    }
+
+    for texture in data.streaming.retired.drain(..) {
+        texture.destroy(device, &mut data.allocator);
+    }
+
+    data.streaming.placeholder.destroy(device, &mut data.allocator);
+    data.streaming = StreamingData::default();
+}
@@ -0,0 +1,149 @@
+//! Sweep of the MSAA and anisotropy settings: each combination is rendered for a number of
+//! frames to measure its frame time, then captured, to help choosing the defaults for a GPU.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+/// Frames rendered after changing the settings before measuring, so the recreation of the
+/// swapchain doesn't skew the results.
+const WARMUP_FRAMES: u32 = 30;
+
+/// The settings swept, every MSAA sample count being combined with every anisotropy.
+#[derive(Clone, Debug)]
+pub struct SweepRanges {
+    pub msaa_samples: Vec<vk::SampleCountFlags>,
+    pub anisotropy: Vec<f32>,
+}
+
+impl Default for SweepRanges {
+    fn default() -> Self {
+        Self {
+            msaa_samples: vec![
+                vk::SampleCountFlags::_1,
+                vk::SampleCountFlags::_2,
+                vk::SampleCountFlags::_4,
+                vk::SampleCountFlags::_8,
+            ],
+            anisotropy: vec![1.0, 4.0, 16.0],
+        }
+    }
+}
+
+/// What the app has to do after a frame of the sweep.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SweepAction {
+    None,
+    /// Switch to these MSAA samples and anisotropy.
+    Apply(vk::SampleCountFlags, f32),
+    /// Capture the next frame to this file.
+    Screenshot(PathBuf),
+    /// Every combination has been measured.
+    Finished,
+}
+
+#[derive(Clone, Debug)]
+struct Measurement {
+    msaa_samples: vk::SampleCountFlags,
+    anisotropy: f32,
+    frame_time: Duration,
+    screenshot: String,
+}
+
+/// Renders every combination of [`SweepRanges`] in turn.
+#[derive(Clone, Debug)]
+pub struct QualitySweep {
+    directory: PathBuf,
+    combinations: Vec<(vk::SampleCountFlags, f32)>,
+    /// Frames measured per combination (after warmup).
+    frames: u32,
+    combination: usize,
+    frame: u32,
+    start: Instant,
+    results: Vec<Measurement>,
+}
+
+impl QualitySweep {
+    /// Creates `directory` if needed, for the screenshots and the summary.
+    pub fn new(directory: &Path, ranges: &SweepRanges, frames: u32) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+
+        let combinations = ranges.msaa_samples
+            .iter()
+            .flat_map(|s| ranges.anisotropy.iter().map(move |a| (*s, *a)))
+            .collect();
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            combinations,
+            frames: frames.max(1),
+            combination: 0,
+            frame: 0,
+            start: Instant::now(),
+            results: vec![],
+        })
+    }
+
+    /// The settings of the first combination, to apply before starting, `None` if there
+    /// is nothing to sweep.
+    pub fn first(&self) -> Option<(vk::SampleCountFlags, f32)> {
+        self.combinations.first().copied()
+    }
+
+    /// Accounts for a rendered frame, which used `msaa_samples` and `anisotropy` (the
+    /// requested settings clamped to what the device supports).
+    pub fn frame(&mut self, msaa_samples: vk::SampleCountFlags, anisotropy: f32) -> SweepAction {
+        self.frame += 1;
+
+        if self.frame == WARMUP_FRAMES {
+            self.start = Instant::now();
+        } else if self.frame == WARMUP_FRAMES + self.frames {
+            let screenshot = format!("msaa{}_aniso{}.png", msaa_samples.bits(), anisotropy);
+            self.results.push(Measurement {
+                msaa_samples,
+                anisotropy,
+                frame_time: self.start.elapsed() / self.frames,
+                screenshot: screenshot.clone(),
+            });
+
+            return SweepAction::Screenshot(self.directory.join(screenshot));
+        } else if self.frame > WARMUP_FRAMES + self.frames {
+            // La capture a été enregistrée dans cette frame
+            self.combination += 1;
+            self.frame = 0;
+
+            return match self.combinations.get(self.combination) {
+                Some((samples, anisotropy)) => SweepAction::Apply(*samples, *anisotropy),
+                None => SweepAction::Finished,
+            };
+        }
+
+        SweepAction::None
+    }
+
+    /// Writes the results as `summary.csv` in the directory of the sweep, and returns its path.
+    pub fn write_summary(&self) -> Result<PathBuf> {
+        let mut summary = String::from("msaa_samples,anisotropy,frame_ms,fps,screenshot\n");
+
+        for m in &self.results {
+            let _ = writeln!(
+                summary,
+                "{},{},{:.3},{:.1},{}",
+                m.msaa_samples.bits(),
+                m.anisotropy,
+                m.frame_time.as_secs_f64() * 1000.0,
+                1.0 / m.frame_time.as_secs_f64(),
+                m.screenshot,
+            );
+        }
+
+        let path = self.directory.join("summary.csv");
+        fs::write(&path, summary)?;
+
+        Ok(path)
+    }
+}
@@ -0,0 +1,581 @@
+//! Text overlay drawn on top of the scene from a signed distance field (SDF) font atlas.
+//!
+//! Each texel of the atlas stores the distance to the closest glyph edge (0.5 on the edge),
+//! so thresholding it in the fragment shader gives sharp glyphs at any size.
+
+use std::mem::size_of;
+use std::path::Path;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator::Allocation;
+use crate::buffers::stage;
+use crate::picking::{get_color_attachment_formats, get_picking_blend_attachment};
+use crate::{
+    begin_secondary_command_buffer, copy_buffer_to_image, create_buffer, create_image,
+    create_image_view, create_shader_module, get_depth_attachment_format, get_output_transfer,
+    read_png_texture, read_shader, transition_image_layout, AppData,
+};
+
+/// The SDF font atlas: ASCII 32 to 127, 16 glyphs per row.
+const FONT_ATLAS: &str = "resources/font_sdf.png";
+const FIRST_CHAR: u8 = b' ';
+const LAST_CHAR: u8 = 127;
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_ROWS: u32 = 6;
+/// Size of a glyph cell of the atlas, relative to the font size it was rasterized at.
+const CELL_SIZE: f32 = 1.5;
+/// Horizontal advance of the (monospace) font, relative to the font size.
+const ADVANCE: f32 = 0.52;
+
+/// Maximum number of characters drawn per frame.
+pub const MAX_CHARS: usize = 256;
+
+/// The Vulkan handles used to draw the text overlay.
+#[derive(Clone, Debug, Default)]
+pub struct TextData {
+    atlas_image: vk::Image,
+    atlas_image_memory: vk::DeviceMemory,
+    atlas_image_view: vk::ImageView,
+    atlas_sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    vertex_buffers: Vec<vk::Buffer>,
+    vertex_buffers_memory: Vec<Allocation>,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+/// How the text is drawn.
+#[derive(Copy, Clone, Debug)]
+pub struct TextStyle {
+    /// Font size in pixels.
+    pub size: f32,
+    /// Width of the anti-aliased edge, in screen pixels.
+    pub smoothing: f32,
+    pub color: [f32; 4],
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self { size: 24.0, smoothing: 1.0, color: [1.0, 1.0, 1.0, 1.0] }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct TextVertex {
+    pos: glm::Vec2,
+    tex_coord: glm::Vec2,
+}
+
+impl TextVertex {
+    fn new(pos: glm::Vec2, tex_coord: glm::Vec2) -> Self {
+        Self { pos, tex_coord }
+    }
+
+    fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<TextVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    fn attribute_description() -> [vk::VertexInputAttributeDescription; 2] {
+        let pos = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(0)
+            .build();
+
+        let tex_coord = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(size_of::<glm::Vec2>() as u32)
+            .build();
+
+        [pos, tex_coord]
+    }
+}
+
+/// Push constants shared by the vertex and fragment stages.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct TextPushConstants {
+    screen_size: glm::Vec2,
+    smoothing: f32,
+    _padding: f32,
+    color: glm::Vec4,
+}
+
+/////// ATLAS ///////
+
+/// Creates the objects which do not depend on the swapchain (atlas, sampler, descriptors).
+pub unsafe fn create_text_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    create_text_atlas(instance, device, data)?;
+    create_text_sampler(device, data)?;
+    create_text_descriptors(device, data)?;
+
+    Ok(())
+}
+
+unsafe fn create_text_atlas(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Les atlas SDF sont souvent en niveaux de gris: étendus en RGBA, la distance dans R
+    let (pixels, width, height) = read_png_texture(Path::new(FONT_ATLAS))?;
+
+    let staging_buffer = stage(instance, device, data, &pixels)?;
+
+    // Les distances sont des données, pas des couleurs: pas de conversion sRGB
+    let (atlas_image, atlas_image_memory) = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::_1,
+        vk::Format::R8G8B8A8_UNORM,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.text.atlas_image = atlas_image;
+    data.text.atlas_image_memory = atlas_image_memory;
+
+    copy_buffer_to_image(device, data, staging_buffer, atlas_image, width, height, 1)?;
+
+    transition_image_layout(
+        device,
+        data,
+        atlas_image,
+        vk::Format::R8G8B8A8_UNORM,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        1,
+    )?;
+
+    data.text.atlas_image_view = create_image_view(
+        device,
+        atlas_image,
+        vk::Format::R8G8B8A8_UNORM,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    Ok(())
+}
+
+unsafe fn create_text_sampler(device: &Device, data: &mut AppData) -> Result<()> {
+    // Le filtrage linéaire interpole les distances, ce qui garde des bords nets une fois seuillés
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .mip_lod_bias(0.0)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    data.text.atlas_sampler = device.create_sampler(&info, None)?;
+
+    Ok(())
+}
+
+unsafe fn create_text_descriptors(device: &Device, data: &mut AppData) -> Result<()> {
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[sampler_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.text.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.text.descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.text.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.text.descriptor_pool)
+        .set_layouts(layouts);
+
+    data.text.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.text.atlas_image_view)
+        .sampler(data.text.atlas_sampler);
+
+    let image_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.text.descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[sampler_write], &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(())
+}
+
+/////// PIPELINE ///////
+
+/// Creates the pipeline and the per swapchain image vertex buffers.
+pub unsafe fn create_text_swapchain_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    create_text_pipeline(device, data)?;
+    create_text_vertex_buffers(instance, device, data)?;
+
+    Ok(())
+}
+
+unsafe fn create_text_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let vert = read_shader(data, "text_vert.spv")?;
+    let frag = read_shader(data, "text_sdf_frag.spv")?;
+
+    let vert_shader_module = create_shader_module(device, &vert[..])?;
+    let frag_shader_module = create_shader_module(device, &frag[..])?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let output_transfer = get_output_transfer(data).to_ne_bytes();
+    let map_entries = &[vk::SpecializationMapEntry::builder()
+        .constant_id(0)
+        .offset(0)
+        .size(size_of::<u32>())];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&output_transfer);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let binding_descriptions = &[TextVertex::binding_description()];
+    let attribute_descriptions = TextVertex::attribute_description();
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Le texte est toujours dessiné par dessus la scène
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::ALWAYS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment.build(), get_picking_blend_attachment(false)];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<TextPushConstants>() as u32);
+
+    let set_layouts = &[data.text.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.text.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.text.pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(0);
+
+    // Le texte ne teste pas la profondeur, mais les formats doivent correspondre aux attachments
+    let color_attachment_formats = &get_color_attachment_formats(data);
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_attachment_format(data));
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.text.pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Un vertex buffer par image de la swapchain, réécrit à chaque frame.
+unsafe fn create_text_vertex_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.text.vertex_buffers.clear();
+    data.text.vertex_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (vertex_buffer, vertex_buffer_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            (size_of::<TextVertex>() * 6 * MAX_CHARS) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.text.vertex_buffers.push(vertex_buffer);
+        data.text.vertex_buffers_memory.push(vertex_buffer_memory);
+    }
+
+    Ok(())
+}
+
+/////// DRAWING ///////
+
+/// Builds two triangles per character, `position` being the top left corner of the text in pixels.
+fn build_text_vertices(text: &str, position: glm::Vec2, size: f32) -> Vec<TextVertex> {
+    let cell = size * CELL_SIZE;
+    let (cell_u, cell_v) = (1.0 / ATLAS_COLUMNS as f32, 1.0 / ATLAS_ROWS as f32);
+
+    let mut vertices = Vec::with_capacity(text.len() * 6);
+    let mut pen = position;
+
+    for c in text.bytes().take(MAX_CHARS) {
+        if c == b'\n' {
+            pen = glm::vec2(position.x, pen.y + cell);
+            continue;
+        }
+
+        let index = if (FIRST_CHAR..=LAST_CHAR).contains(&c) { c - FIRST_CHAR } else { b'?' - FIRST_CHAR } as u32;
+        let u = (index % ATLAS_COLUMNS) as f32 * cell_u;
+        let v = (index / ATLAS_COLUMNS) as f32 * cell_v;
+
+        // La cellule est centrée sur le caractère, plus large que son avance
+        let x = pen.x - (cell - size * ADVANCE) / 2.0;
+        let (x0, y0, x1, y1) = (x, pen.y, x + cell, pen.y + cell);
+        let (u0, v0, u1, v1) = (u, v, u + cell_u, v + cell_v);
+
+        vertices.push(TextVertex::new(glm::vec2(x0, y0), glm::vec2(u0, v0)));
+        vertices.push(TextVertex::new(glm::vec2(x1, y0), glm::vec2(u1, v0)));
+        vertices.push(TextVertex::new(glm::vec2(x1, y1), glm::vec2(u1, v1)));
+        vertices.push(TextVertex::new(glm::vec2(x1, y1), glm::vec2(u1, v1)));
+        vertices.push(TextVertex::new(glm::vec2(x0, y1), glm::vec2(u0, v1)));
+        vertices.push(TextVertex::new(glm::vec2(x0, y0), glm::vec2(u0, v0)));
+
+        pen.x += size * ADVANCE;
+    }
+
+    vertices
+}
+
+/// Records a secondary command buffer drawing `text` at `position` (in pixels).
+pub unsafe fn update_text_command_buffer(
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    text: &str,
+    position: glm::Vec2,
+    style: &TextStyle,
+) -> Result<vk::CommandBuffer> {
+    while image_index >= data.text.command_buffers.len() {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(data.command_pools[data.text.command_buffers.len()])
+            .level(vk::CommandBufferLevel::SECONDARY)
+            .command_buffer_count(1);
+
+        let command_buffer = device.allocate_command_buffers(&allocate_info)?[0];
+        data.text.command_buffers.push(command_buffer);
+    }
+
+    let command_buffer = data.text.command_buffers[image_index];
+
+    let vertices = build_text_vertices(text, position, style.size);
+
+    if !vertices.is_empty() {
+        let vertex_buffer_memory = data.text.vertex_buffers_memory[image_index];
+        let memory = device.map_memory(
+            vertex_buffer_memory.memory,
+            vertex_buffer_memory.offset,
+            (size_of::<TextVertex>() * vertices.len()) as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+
+        memcpy(vertices.as_ptr(), memory.cast(), vertices.len());
+
+        device.unmap_memory(vertex_buffer_memory.memory);
+    }
+
+    let push_constants = TextPushConstants {
+        screen_size: glm::vec2(
+            data.swapchain_extent.width as f32,
+            data.swapchain_extent.height as f32,
+        ),
+        smoothing: style.smoothing,
+        _padding: 0.0,
+        color: glm::Vec4::from(style.color),
+    };
+
+    let (_, push_constants_bytes, _) = std::slice::from_ref(&push_constants).align_to::<u8>();
+
+    begin_secondary_command_buffer(device, data, command_buffer, image_index)?;
+
+    if !vertices.is_empty() {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.text.pipeline);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.text.vertex_buffers[image_index]], &[0]);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            data.text.pipeline_layout,
+            0,
+            &[data.text.descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            data.text.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            push_constants_bytes,
+        );
+        device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+    }
+
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(command_buffer)
+}
+
+/////// DESTRUCTION ///////
+
+pub unsafe fn destroy_text_swapchain_objects(device: &Device, data: &mut AppData) {
+    data.text.vertex_buffers
+        .iter()
+        .for_each(|b| device.destroy_buffer(*b, None));
+    data.text.vertex_buffers_memory
+        .drain(..)
+        .for_each(|m| data.allocator.free(device, m));
+
+    device.destroy_pipeline(data.text.pipeline, None);
+    device.destroy_pipeline_layout(data.text.pipeline_layout, None);
+}
+
+/// Forgets the command buffers of the overlay, freed with the command pools of the swapchain
+/// images they were allocated from.
+pub fn clear_text_command_buffers(data: &mut AppData) {
+    data.text.command_buffers.clear();
+}
+
+pub unsafe fn destroy_text_objects(device: &Device, data: &mut AppData) {
+    device.destroy_descriptor_pool(data.text.descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.text.descriptor_set_layout, None);
+    device.destroy_sampler(data.text.atlas_sampler, None);
+    device.destroy_image_view(data.text.atlas_image_view, None);
+    device.destroy_image(data.text.atlas_image, None);
+    data.allocator.free_image(device, data.text.atlas_image_memory);
+}
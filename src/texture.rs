@@ -0,0 +1,62 @@
+//! The second texture of the models, blended with the first one in the fragment shader by
+//! the factor `AppData::texture_blend` (0.0 for the first texture only, 1.0 for the second).
+//!
+//! It is loaded with the functions of the first texture, which work on the fields of
+//! `AppData`: the fields of the two textures are swapped while it is created.
+
+use std::mem::swap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{create_texture_image, create_texture_image_view, AppData, TextureEncoding};
+
+/// The second texture, sampled with the sampler of the first one.
+#[derive(Clone, Debug, Default)]
+pub struct SecondTexture {
+    /// The configured file, or else the one of the first texture.
+    pub path: PathBuf,
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub format: vk::Format,
+    pub view: vk::ImageView,
+    pub mip_levels: u32,
+}
+
+/// Exchanges the fields of the first texture with the ones of `data.second_texture`.
+fn swap_textures(data: &mut AppData) {
+    let texture = &mut data.second_texture;
+    swap(&mut data.texture_path, &mut texture.path);
+    swap(&mut data.texture_image, &mut texture.image);
+    swap(&mut data.texture_image_memory, &mut texture.memory);
+    swap(&mut data.texture_format, &mut texture.format);
+    swap(&mut data.texture_image_view, &mut texture.view);
+    swap(&mut data.mip_levels, &mut texture.mip_levels);
+}
+
+/// Creates the image and the view of the second texture from `data.second_texture.path`.
+pub unsafe fn create_second_texture(
+    instance: &Instance,
+    logical_device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    swap_textures(data);
+
+    // Les champs sont remis en place même en cas d'erreur
+    let result = create_texture_image(instance, logical_device, data, TextureEncoding::Srgb)
+        .and_then(|_| create_texture_image_view(logical_device, data));
+
+    swap_textures(data);
+
+    result
+}
+
+pub unsafe fn destroy_second_texture(logical_device: &Device, data: &mut AppData) {
+    let texture = &data.second_texture;
+    logical_device.destroy_image_view(texture.view, None);
+    logical_device.destroy_image(texture.image, None);
+    data.allocator.free_image(logical_device, texture.memory);
+
+    data.second_texture = SecondTexture { path: texture.path.clone(), ..Default::default() };
+}
@@ -0,0 +1,279 @@
+//! Uploads on a dedicated transfer queue, when the device has a queue family with `TRANSFER`
+//! but not `GRAPHICS` (usually backed by a DMA engine), so they don't stall the graphics queue.
+//!
+//! The resources are `EXCLUSIVE`: once written, their ownership is released by the transfer
+//! queue and acquired by the graphics queue with a pair of identical barriers. Without a
+//! dedicated family, the transfer queue is the graphics queue and there is no ownership
+//! transfer.
+//!
+//! Only new buffers and images are uploaded here: a region copied into a buffer already in
+//! use (`copy_buffer_region`) is copied on the graphics queue, which owns it.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{begin_single_time_commands, end_single_time_commands, AppData, QueueFamilyIndices};
+
+/// The queue the uploads are submitted to, and its command pool.
+#[derive(Clone, Debug, Default)]
+pub struct TransferData {
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    /// Family of `queue`, the graphics one without a dedicated transfer family.
+    queue_family: u32,
+    graphics_queue_family: u32,
+}
+
+impl TransferData {
+    fn is_dedicated(&self) -> bool {
+        self.queue_family != self.graphics_queue_family
+    }
+}
+
+/// Gets the transfer queue (created with the device) and creates its command pool.
+pub unsafe fn create_transfer_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+        .queue_family_index(indices.transfer);
+
+    data.transfer = TransferData {
+        queue: device.get_device_queue(indices.transfer, 0),
+        command_pool: device.create_command_pool(&info, None)?,
+        queue_family: indices.transfer,
+        graphics_queue_family: indices.graphics,
+    };
+
+    if data.transfer.is_dedicated() {
+        info!("Uploading on the dedicated transfer queue family {}.", indices.transfer);
+    }
+
+    Ok(())
+}
+
+pub unsafe fn destroy_transfer_objects(device: &Device, data: &mut AppData) {
+    device.destroy_command_pool(data.transfer.command_pool, None);
+    data.transfer = TransferData::default();
+}
+
+/// Copies `regions` of `source` to `destination`, a buffer which hasn't been used yet (its
+/// content would be lost with a dedicated family, owned by the graphics queue), then first
+/// accessed with `dst_access_mask` in `dst_stage_mask`.
+pub unsafe fn upload_buffer(
+    device: &Device,
+    data: &AppData,
+    source: vk::Buffer,
+    destination: vk::Buffer,
+    regions: &[vk::BufferCopy],
+    dst_stage_mask: vk::PipelineStageFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> Result<()> {
+    let copies = regions.iter().map(|r| (source, destination, *r)).collect::<Vec<_>>();
+    upload_buffer_regions(device, data, &copies, dst_stage_mask, dst_access_mask)
+}
+
+/// Copies the regions of `copies` (source, destination, region), to buffers which haven't
+/// been used yet, in a single submission. The buffers are first accessed with
+/// `dst_access_mask` in `dst_stage_mask`.
+pub unsafe fn upload_buffer_regions(
+    device: &Device,
+    data: &AppData,
+    copies: &[(vk::Buffer, vk::Buffer, vk::BufferCopy)],
+    dst_stage_mask: vk::PipelineStageFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> Result<()> {
+    let command_buffer = begin_transfer_commands(device, data)?;
+
+    let mut destinations = vec![];
+    for (source, destination, region) in copies {
+        device.cmd_copy_buffer(command_buffer, *source, *destination, &[*region]);
+        if !destinations.contains(destination) {
+            destinations.push(*destination);
+        }
+    }
+
+    let barriers = destinations
+        .iter()
+        .map(|destination| {
+            vk::BufferMemoryBarrier::builder()
+                .src_queue_family_index(data.transfer.queue_family)
+                .dst_queue_family_index(data.transfer.graphics_queue_family)
+                .buffer(*destination)
+                .offset(0)
+                .size(vk::WHOLE_SIZE as u64)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    end_transfer_commands(device, data, command_buffer, dst_stage_mask, dst_access_mask, &barriers, &[])
+}
+
+/// Copies `regions` of `source` to `image`, a color image in the `UNDEFINED` layout, leaving
+/// its `mip_levels` in the `TRANSFER_DST_OPTIMAL` layout for the graphics queue (to generate
+/// the mipmaps or to transition them to their final layout).
+///
+/// The regions are whole mip levels, which suit any `minImageTransferGranularity` of the
+/// transfer queue.
+pub unsafe fn upload_image(
+    device: &Device,
+    data: &AppData,
+    source: vk::Buffer,
+    image: vk::Image,
+    mip_levels: u32,
+    regions: &[vk::BufferImageCopy],
+) -> Result<()> {
+    let command_buffer = begin_transfer_commands(device, data)?;
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        source,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        regions,
+    );
+
+    // Le layout ne change pas, seul le propriétaire change
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(data.transfer.queue_family)
+        .dst_queue_family_index(data.transfer.graphics_queue_family)
+        .image(image)
+        .subresource_range(subresource);
+
+    end_transfer_commands(
+        device,
+        data,
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+        &[],
+        &[barrier.build()],
+    )
+}
+
+unsafe fn begin_transfer_commands(device: &Device, data: &AppData) -> Result<vk::CommandBuffer> {
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(data.transfer.command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    Ok(command_buffer)
+}
+
+/// Submits `command_buffer` to the transfer queue and waits for it, then transfers the
+/// ownership of the resources of the barriers (whose access masks are set here) to the
+/// graphics queue, for `dst_access_mask` in `dst_stage_mask`.
+unsafe fn end_transfer_commands(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    dst_stage_mask: vk::PipelineStageFlags,
+    dst_access_mask: vk::AccessFlags,
+    buffer_barriers: &[vk::BufferMemoryBarrier],
+    image_barriers: &[vk::ImageMemoryBarrier],
+) -> Result<()> {
+    let dedicated = data.transfer.is_dedicated();
+
+    // Release: les accès de destination sont ignorés côté transfert
+    if dedicated {
+        let buffer_barriers = buffer_barriers
+            .iter()
+            .map(|b| vk::BufferMemoryBarrier { src_access_mask: vk::AccessFlags::TRANSFER_WRITE, ..*b })
+            .collect::<Vec<_>>();
+        let image_barriers = image_barriers
+            .iter()
+            .map(|b| vk::ImageMemoryBarrier { src_access_mask: vk::AccessFlags::TRANSFER_WRITE, ..*b })
+            .collect::<Vec<_>>();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &buffer_barriers,
+            &image_barriers,
+        );
+    }
+
+    device.end_command_buffer(command_buffer)?;
+
+    let command_buffers = &[command_buffer];
+    let info = vk::SubmitInfo::builder()
+        .command_buffers(command_buffers);
+
+    device.queue_submit(data.transfer.queue, &[info], vk::Fence::null())?;
+    device.queue_wait_idle(data.transfer.queue)?;
+
+    device.free_command_buffers(data.transfer.command_pool, &[command_buffer]);
+
+    if !dedicated {
+        return Ok(());
+    }
+
+    // Acquire: les accès de source sont ignorés côté graphique, l'attente de la file de
+    // transfert suffit à ordonner les deux soumissions
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    let buffer_barriers = buffer_barriers
+        .iter()
+        .map(|b| vk::BufferMemoryBarrier { dst_access_mask, ..*b })
+        .collect::<Vec<_>>();
+    let image_barriers = image_barriers
+        .iter()
+        .map(|b| vk::ImageMemoryBarrier { dst_access_mask, ..*b })
+        .collect::<Vec<_>>();
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &buffer_barriers,
+        &image_barriers,
+    );
+
+    end_single_time_commands(device, data, command_buffer)
+}
@@ -0,0 +1,215 @@
+//! Frustum culling of the models, optionally with conditional rendering
+//! (`VK_EXT_conditional_rendering`).
+//!
+//! With the extension, the visibility of each model is written to a predicate buffer and
+//! its draw is wrapped in a conditional rendering block reading it, so a compute pass can
+//! take over writing the buffer without the CPU being involved. Without it, the secondary
+//! command buffers of the culled models are simply not recorded (`is_model_recorded`).
+
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::{ExtConditionalRenderingExtension, InstanceV1_1};
+
+use crate::allocator::Allocation;
+use crate::{create_buffer, AppData, MAX_INSTANCES};
+
+/// The predicate buffers and the visibility of the models.
+#[derive(Clone, Debug, Default)]
+pub struct VisibilityData {
+    /// Predicate buffers per swapchain image, a `u32` per model (0 skips its draw).
+    predicate_buffers: Vec<vk::Buffer>,
+    predicate_buffers_memory: Vec<Allocation>,
+    /// Whether each model is in the view frustum, for the frame being recorded.
+    visible: Vec<bool>,
+    /// Center and radius of a sphere containing each mesh.
+    bounding_spheres: Vec<(glm::Vec3, f32)>,
+}
+
+/// Whether the device supports conditional rendering.
+pub unsafe fn check_conditional_rendering(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Result<bool> {
+    let extensions = instance
+        .enumerate_device_extension_properties(physical_device, None)?
+        .iter()
+        .map(|e| e.extension_name)
+        .collect::<HashSet<_>>();
+
+    if !extensions.contains(&vk::EXT_CONDITIONAL_RENDERING_EXTENSION.name) {
+        return Ok(false);
+    }
+
+    let mut conditional_rendering_features = vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut conditional_rendering_features);
+    instance.get_physical_device_features2(physical_device, &mut features);
+
+    Ok(conditional_rendering_features.conditional_rendering == vk::TRUE)
+}
+
+/// Computes the bounding sphere of each loaded mesh.
+pub fn compute_bounding_spheres(data: &mut AppData) {
+    data.visibility.bounding_spheres = data.meshes
+        .iter()
+        .map(|mesh| {
+            let (min, max) = mesh.vertices.iter().fold(
+                (glm::vec3(f32::MAX, f32::MAX, f32::MAX), glm::vec3(f32::MIN, f32::MIN, f32::MIN)),
+                |(min, max), v| (glm::min2(&min, &v.pos), glm::max2(&max, &v.pos)),
+            );
+
+            let center = (min + max) * 0.5;
+            let radius = mesh.vertices
+                .iter()
+                .map(|v| glm::distance(&center, &v.pos))
+                .fold(0.0, f32::max);
+
+            (center, radius)
+        })
+        .collect();
+}
+
+/// Creates a predicate buffer per swapchain image, if conditional rendering is used.
+pub unsafe fn create_predicate_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.visibility.predicate_buffers.clear();
+    data.visibility.predicate_buffers_memory.clear();
+
+    if !data.conditional_rendering {
+        return Ok(());
+    }
+
+    for _ in 0..data.swapchain_images.len() {
+        // STORAGE_BUFFER pour qu'une passe de compute puisse l'écrire
+        let (buffer, buffer_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            (size_of::<u32>() * MAX_INSTANCES) as u64,
+            vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.visibility.predicate_buffers.push(buffer);
+        data.visibility.predicate_buffers_memory.push(buffer_memory);
+    }
+
+    Ok(())
+}
+
+/// Culls the models (`models` being their model matrices) against the view frustum of
+/// `view_projection`, and writes their predicates for `image_index`.
+pub unsafe fn update_visibility(
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    view_projection: &glm::Mat4,
+    models: &[glm::Mat4],
+) -> Result<()> {
+    // Le modèle i dessine le mesh i % meshes.len()
+    let spheres = &data.visibility.bounding_spheres;
+    data.visibility.visible = models
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let (center, radius) = spheres[i % spheres.len()];
+            is_sphere_in_frustum(&(view_projection * m), &center, radius)
+        })
+        .collect();
+
+    if !data.conditional_rendering {
+        return Ok(());
+    }
+
+    let predicates = data.visibility.visible
+        .iter()
+        .take(MAX_INSTANCES)
+        .map(|v| *v as u32)
+        .collect::<Vec<_>>();
+
+    let memory = data.visibility.predicate_buffers_memory[image_index];
+    let mapped = device.map_memory(
+        memory.memory,
+        memory.offset,
+        (size_of::<u32>() * predicates.len()) as u64,
+        vk::MemoryMapFlags::empty(),
+    )?;
+
+    memcpy(predicates.as_ptr(), mapped.cast(), predicates.len());
+
+    device.unmap_memory(memory.memory);
+
+    Ok(())
+}
+
+/// Whether the secondary command buffer of the model `model_index` has to be recorded:
+/// always with conditional rendering (the predicate skips its draw), only if it is visible
+/// otherwise. Every model is recorded before the first culling.
+pub fn is_model_recorded(data: &AppData, model_index: usize) -> bool {
+    (data.conditional_rendering && model_index < MAX_INSTANCES)
+        || data.visibility.visible.get(model_index).copied().unwrap_or(true)
+}
+
+/// Number of models outside the view frustum in the frame being recorded.
+pub fn get_culled_count(data: &AppData) -> usize {
+    data.visibility.visible.iter().filter(|v| !**v).count()
+}
+
+/// Records the commands of `draw` for the model `model_index`, in a conditional rendering
+/// block if supported, or only if the model is visible otherwise.
+pub unsafe fn cmd_draw_if_visible(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    model_index: usize,
+    draw: impl FnOnce(),
+) {
+    if data.conditional_rendering && model_index < MAX_INSTANCES {
+        let info = vk::ConditionalRenderingBeginInfoEXT::builder()
+            .buffer(data.visibility.predicate_buffers[image_index])
+            .offset((size_of::<u32>() * model_index) as u64);
+
+        device.cmd_begin_conditional_rendering_ext(command_buffer, &info);
+        draw();
+        device.cmd_end_conditional_rendering_ext(command_buffer);
+    } else if data.visibility.visible.get(model_index).copied().unwrap_or(true) {
+        draw();
+    }
+}
+
+/// Whether a sphere (in model space) is at least partly inside the frustum of `mvp`.
+fn is_sphere_in_frustum(mvp: &glm::Mat4, center: &glm::Vec3, radius: f32) -> bool {
+    // Plans du frustum extraits de la matrice (Gribb-Hartmann), avec z entre 0 et w
+    let row = |i| mvp.row(i).transpose();
+    let planes = [
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+        row(2),
+        row(3) - row(2),
+    ];
+
+    planes.iter().all(|p| {
+        let normal = p.xyz();
+        (normal.dot(center) + p.w) / normal.norm() >= -radius
+    })
+}
+
+pub unsafe fn destroy_predicate_buffers(device: &Device, data: &mut AppData) {
+    data.visibility.predicate_buffers
+        .iter()
+        .for_each(|b| device.destroy_buffer(*b, None));
+    data.visibility.predicate_buffers_memory
+        .drain(..)
+        .for_each(|m| data.allocator.free(device, m));
+    data.visibility.predicate_buffers.clear();
+}
@@ -0,0 +1,142 @@
+//! Export of the visible wireframe of the models as an SVG, computed on the CPU.
+//!
+//! The triangles are projected with the same matrices as the shaders, the back faces are
+//! culled like the pipeline does (counter-clockwise front faces) and the edges are clipped
+//! to the viewport.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+
+/// How the edges are drawn in the SVG.
+#[derive(Clone, Debug)]
+pub struct WireframeStyle {
+    /// Stroke width, in pixels.
+    pub line_width: f32,
+    /// Stroke color, as any SVG color (`#rrggbb`, `black`...).
+    pub color: String,
+}
+
+impl Default for WireframeStyle {
+    fn default() -> Self {
+        Self {
+            line_width: 0.5,
+            color: String::from("black"),
+        }
+    }
+}
+
+/// Writes to `path` the edges of the front faces of the meshes in `meshes` (positions,
+/// indices and model-view-projection matrix), as seen in a viewport of `width` x `height`
+/// pixels.
+pub fn export_wireframe_svg(
+    path: &Path,
+    meshes: &[(&[glm::Vec3], &[u32], glm::Mat4)],
+    width: f32,
+    height: f32,
+    style: &WireframeStyle,
+) -> Result<()> {
+    let mut d = String::new();
+
+    for (positions, indices, mvp) in meshes {
+        // Position dans le viewport (y vers le bas, comme en SVG), None derrière la caméra
+        let projected = positions
+            .iter()
+            .map(|p| {
+                let clip = mvp * glm::vec4(p.x, p.y, p.z, 1.0);
+                if clip.w <= 0.0 || clip.z < 0.0 || clip.z > clip.w {
+                    return None;
+                }
+
+                Some(glm::vec2(
+                    (clip.x / clip.w + 1.0) * 0.5 * width,
+                    (clip.y / clip.w + 1.0) * 0.5 * height,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        // Une arête partagée par deux faces n'est écrite qu'une fois
+        let mut edges = HashSet::new();
+
+        for triangle in indices.chunks_exact(3) {
+            let (Some(a), Some(b), Some(c)) = (
+                projected[triangle[0] as usize],
+                projected[triangle[1] as usize],
+                projected[triangle[2] as usize],
+            ) else {
+                continue;
+            };
+
+            // Aire signée telle que définie par Vulkan: positive pour une face avant
+            let area = -0.5
+                * ((a.x * b.y - b.x * a.y) + (b.x * c.y - c.x * b.y) + (c.x * a.y - a.x * c.y));
+            if area <= 0.0 {
+                continue;
+            }
+
+            for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+                edges.insert((triangle[i].min(triangle[j]), triangle[i].max(triangle[j])));
+            }
+        }
+
+        for (i, j) in edges {
+            let (a, b) = (projected[i as usize].unwrap(), projected[j as usize].unwrap());
+            if let Some((a, b)) = clip_line(a, b, width, height) {
+                let _ = write!(d, "M{:.2} {:.2}L{:.2} {:.2}", a.x, a.y, b.x, b.y);
+            }
+        }
+    }
+
+    let svg = format!(
+        concat!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+            "<path d=\"{d}\" fill=\"none\" stroke=\"{color}\" stroke-width=\"{line_width}\" stroke-linecap=\"round\"/>\n",
+            "</svg>\n",
+        ),
+        w = width,
+        h = height,
+        d = d,
+        color = style.color,
+        line_width = style.line_width,
+    );
+
+    fs::write(path, svg)?;
+
+    Ok(())
+}
+
+/// Clips the segment `a`-`b` to the viewport (Liang-Barsky), `None` if it is outside.
+fn clip_line(a: glm::Vec2, b: glm::Vec2, width: f32, height: f32) -> Option<(glm::Vec2, glm::Vec2)> {
+    let delta = b - a;
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+
+    for (p, q) in [
+        (-delta.x, a.x),
+        (delta.x, width - a.x),
+        (-delta.y, a.y),
+        (delta.y, height - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+
+    if t0 > t1 {
+        return None;
+    }
+
+    Some((a + delta * t0, a + delta * t1))
+}